@@ -1,9 +1,18 @@
-use std::fs::{self, File};
-use std::io::{ErrorKind, Read};
-use std::{fmt, io};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fmt, io, process};
 
+use crate::diff;
+use crate::file_format::{self, FileFormat};
 use crate::piece_table::PieceTable;
+use crate::piece_tree::PieceTree;
 use crate::position::Position;
+use crate::regex::Regex;
+use crate::swap_file::{self, SwapFile};
+use crate::undo_history::{self, History};
+use crate::unicode;
 
 #[derive(Debug)]
 struct FilePathUndefined;
@@ -14,10 +23,123 @@ impl fmt::Display for FilePathUndefined {
     }
 }
 
+impl std::error::Error for FilePathUndefined {}
+
+/// Follow symlinks until a path, which is not a symlink (or does not exist yet).
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    // same limit as the kernel, to not loop forever on cyclic links
+    for _ in 0..40 {
+        match fs::read_link(&path) {
+            Ok(target) => {
+                path = match path.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Add the failed step and path to an io error.
+fn context(step: &str, path: &Path) -> impl FnOnce(io::Error) -> io::Error {
+    let message = format!("{} {}", step, path.display());
+    move |e| io::Error::new(e.kind(), format!("{}: {}", message, e))
+}
+
+/// Write the contents into a temporary file next to the target, and rename
+/// it over the target. The target is either completely written or left
+/// untouched. Symlinks are followed and the mode bits of an existing target
+/// are kept.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let target = resolve_symlinks(path);
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| io::Error::other(format!("invalid file name {}", target.display())))?;
+    let tmp_path = dir.join(format!(
+        ".{}.{}.nimbus-tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let permissions = match fs::metadata(&target) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(context("reading permissions of", &target)(e)),
+    };
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .map_err(context("creating", &tmp_path))?;
+        file.write_all(contents)
+            .map_err(context("writing", &tmp_path))?;
+        file.sync_all().map_err(context("syncing", &tmp_path))?;
+        if let Some(permissions) = permissions {
+            fs::set_permissions(&tmp_path, permissions)
+                .map_err(context("setting permissions of", &tmp_path))?;
+        }
+        fs::rename(&tmp_path, &target).map_err(context("renaming to", &target))
+    })();
+
+    if result.is_err() {
+        fs::remove_file(&tmp_path).ok();
+        return result;
+    }
+
+    // persist the rename itself, not supported on every platform
+    if let Ok(dir) = File::open(&dir) {
+        dir.sync_all().ok();
+    }
+    Ok(())
+}
+
+/// State of the file on disk, as of the last load or save.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl DiskState {
+    fn new(metadata: Option<fs::Metadata>, contents: &[u8]) -> DiskState {
+        DiskState {
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            len: contents.len() as u64,
+            hash: undo_history::hash(contents),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Buffer {
     piece_table: PieceTable,
     file_path: Option<String>,
+    file_format: FileFormat,
+    mixed_line_endings: bool,
+    message: Option<String>,
+    disk_state: DiskState,
+    /// Pieces as of the last load or save, to tell if there are unsaved changes.
+    saved_pieces: PieceTree,
+    /// Swap file left behind by an earlier session, until it is recovered
+    /// or discarded.
+    found_swap_file: Option<SwapFile>,
+    /// A file with the name of the swap file exists, but it is not a swap
+    /// file of Nimbus (or can not be read). It is neither written nor
+    /// removed, and the edits are not journaled.
+    foreign_swap_file: bool,
+    /// Directory of the undo files. Without one, the undo history is not
+    /// kept between sessions.
+    undo_dir: Option<PathBuf>,
 }
 impl Buffer {
     pub fn new() -> Buffer {
@@ -25,23 +147,90 @@ impl Buffer {
     }
 
     pub fn from_string(contents: String) -> Buffer {
+        let (file_format, mixed_line_endings) = FileFormat::detect(&contents);
+        let mut piece_table = PieceTable::from_string(file_format::normalize(&contents));
+        if mixed_line_endings {
+            piece_table.lf_line_breaks = file_format::lf_line_breaks(&contents);
+        }
         Buffer {
-            piece_table: PieceTable::from_string(contents),
+            saved_pieces: piece_table.pieces.clone(),
+            piece_table,
             file_path: None,
+            file_format,
+            mixed_line_endings,
+            ..Default::default()
         }
     }
 
     pub fn from_file(file_path: &str) -> std::io::Result<Buffer> {
+        Self::from_file_with_undo_dir(file_path, undo_history::cache_dir())
+    }
+
+    /// Load a file, restoring and saving its undo history in `undo_dir`
+    /// instead of the cache directory.
+    pub fn from_file_with_undo_dir(
+        file_path: &str,
+        undo_dir: Option<PathBuf>,
+    ) -> std::io::Result<Buffer> {
         let mut file = File::open(file_path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
+        let (file_format, mixed_line_endings) = FileFormat::detect(&contents);
+        let mut message = None;
+        let history = match &undo_dir {
+            Some(dir) => undo_history::read(dir, file_path, &contents),
+            None => Ok(History::Missing),
+        };
+        let mut piece_table = match history {
+            Ok(History::Restored(piece_table)) => *piece_table,
+            Ok(History::Stale) => {
+                message = Some("File changed since last edit, undo history discarded.".to_string());
+                PieceTable::from_string(file_format::normalize(&contents))
+            }
+            Ok(History::Missing) => PieceTable::from_string(file_format::normalize(&contents)),
+            Err(e) => {
+                message = Some(format!("Undo history discarded: {}.", e));
+                PieceTable::from_string(file_format::normalize(&contents))
+            }
+        };
+        // each line keeps its line ending, until the file format is set
+        if mixed_line_endings {
+            piece_table.lf_line_breaks = file_format::lf_line_breaks(&contents);
+        }
+
+        let swap_path = swap_file::swap_path(file_path);
+        let (found_swap_file, foreign_swap_file) = match swap_file::read(&swap_path) {
+            Ok(swap_file) => (swap_file, false),
+            Err(e) => {
+                message = Some(format!(
+                    "{} left alone, no swap file is written: {}.",
+                    swap_path.display(),
+                    e
+                ));
+                (None, true)
+            }
+        };
+
         Ok(Buffer {
-            piece_table: PieceTable::from_string(contents),
+            saved_pieces: piece_table.pieces.clone(),
+            piece_table,
             file_path: Some(file_path.to_string()),
+            file_format,
+            mixed_line_endings,
+            message,
+            disk_state: DiskState::new(file.metadata().ok(), contents.as_bytes()),
+            found_swap_file,
+            foreign_swap_file,
+            undo_dir,
         })
     }
 
+    /// Take the message left for the user while loading the buffer.
+    pub fn take_message(&mut self) -> Option<String> {
+        self.message.take()
+    }
+
     pub fn file_path(&self) -> Option<&str> {
         if let Some(path) = self.file_path.as_ref() {
             return Some(path.as_str());
@@ -53,36 +242,228 @@ impl Buffer {
         self.file_path = Some(file_path);
     }
 
-    pub fn save_file(&self) -> std::io::Result<&str> {
+    pub fn file_format(&self) -> FileFormat {
+        self.file_format
+    }
+
+    /// Set the line endings used when saving. Mixed line endings are
+    /// converted to the format on the next save.
+    pub fn set_file_format(&mut self, file_format: FileFormat) {
+        self.file_format = file_format;
+        self.mixed_line_endings = false;
+        self.piece_table.lf_line_breaks.clear();
+    }
+
+    /// True if the file was loaded with both unix and dos line endings.
+    /// Each line is saved with its line ending, new lines with the most used
+    /// one, until the file format is set.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    /// Contents of the buffer, with line endings of the file format.
+    fn file_contents(&self) -> String {
+        let text = self.piece_table.get(0, None);
+        match self.mixed_line_endings {
+            true => self
+                .file_format
+                .convert_mixed(&text, &self.piece_table.lf_line_breaks),
+            false => self.file_format.convert(&text),
+        }
+    }
+
+    pub fn save_file(&mut self) -> std::io::Result<&str> {
         if let Some(path) = self.file_path.as_ref() {
-            fs::write(path, self.piece_table.get(0, None))?;
+            let contents = self.file_contents();
+            write_atomic(Path::new(path), contents.as_bytes())?;
+            self.disk_state = DiskState::new(fs::metadata(path).ok(), contents.as_bytes());
+            self.saved_pieces = self.piece_table.pieces.clone();
+
+            // The undo history is a cache: failing to write it must not fail the save.
+            if let Some(dir) = &self.undo_dir {
+                undo_history::write(dir, path, &self.piece_table, self.disk_state.hash).ok();
+            }
+
+            // everything in the journal is on disk now
+            self.piece_table.journal.clear();
+            self.remove_swap_file().ok();
             Ok(path.as_str())
         } else {
-            Err(io::Error::new(
-                ErrorKind::Other,
-                "Variable file_path not set.",
-            ))
+            Err(io::Error::other(FilePathUndefined))
+        }
+    }
+
+    /// True if the buffer has changes, which are not saved.
+    pub fn is_modified(&self) -> bool {
+        self.piece_table.pieces != self.saved_pieces
+    }
+
+    /// Check whether the file was changed on disk by another program since it
+    /// was loaded or saved. Returns the new state of the file, if it was. The
+    /// modification time and size are compared first, the contents are only
+    /// read if one of them changed.
+    pub fn disk_changed(&mut self) -> io::Result<Option<DiskState>> {
+        let Some(path) = self.file_path.as_ref() else {
+            return Ok(None);
+        };
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            // saving creates it again
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if metadata.modified().ok() == self.disk_state.modified
+            && metadata.len() == self.disk_state.len
+        {
+            return Ok(None);
+        }
+
+        let state = DiskState::new(Some(metadata), &fs::read(path)?);
+        if state.hash == self.disk_state.hash {
+            // only touched, don't read it again on the next check
+            self.disk_state.modified = state.modified;
+            return Ok(None);
+        }
+        Ok(Some(state))
+    }
+
+    /// Load the file again, dropping all changes in the buffer.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let Some(path) = self.file_path.clone() else {
+            return Err(io::Error::other(FilePathUndefined));
+        };
+        // the swap file belongs to the dropped changes
+        self.remove_swap_file()?;
+        let marks = self.marks();
+        *self = Buffer::from_file_with_undo_dir(&path, self.undo_dir.clone())?;
+        for (name, position) in marks {
+            self.set_mark(name, &position);
+        }
+        Ok(())
+    }
+
+    /// Save the buffer next to the file as `<name>.mine` (or `<name>.mine.N`
+    /// if that exists), leaving the file itself untouched. Returns the path of
+    /// the copy. The buffer still belongs to the file, and its changes are
+    /// still unsaved.
+    pub fn save_copy(&self) -> io::Result<String> {
+        let Some(path) = self.file_path.clone() else {
+            return Err(io::Error::other(FilePathUndefined));
+        };
+        let copy_path = (0..)
+            .map(|n| match n {
+                0 => format!("{}.mine", path),
+                n => format!("{}.mine.{}", path, n),
+            })
+            .find(|copy_path| !Path::new(copy_path).exists())
+            .unwrap();
+
+        write_atomic(Path::new(&copy_path), self.file_contents().as_bytes())?;
+        Ok(copy_path)
+    }
+
+    /// True if a swap file of an earlier session was found when loading.
+    pub fn has_swap_file(&self) -> bool {
+        self.found_swap_file.is_some()
+    }
+
+    /// True if the found swap file was written for the current file contents,
+    /// i.e. its edits can be replayed.
+    pub fn swap_file_matches(&self) -> bool {
+        self.found_swap_file
+            .as_ref()
+            .is_some_and(|swap_file| swap_file.content_hash == self.disk_state.hash)
+    }
+
+    /// Contents of the buffer after replaying the found swap file, without
+    /// modifying the buffer.
+    fn recovered_contents(&self) -> Option<String> {
+        let swap_file = self.found_swap_file.as_ref()?;
+        let mut piece_table = self.piece_table.clone();
+        for edit in swap_file.edits.iter() {
+            piece_table.apply(edit);
+        }
+        Some(piece_table.get(0, None))
+    }
+
+    /// Line diff between the file and the recovered contents.
+    pub fn swap_file_diff(&self) -> Vec<String> {
+        match self.recovered_contents() {
+            Some(recovered) => diff::line_diff(&self.piece_table.get(0, None), &recovered),
+            None => vec![],
+        }
+    }
+
+    /// Replay the edits of the found swap file. The swap file is kept until
+    /// the buffer is saved, new edits are appended to it.
+    pub fn recover_swap_file(&mut self) {
+        if let Some(swap_file) = self.found_swap_file.take() {
+            for edit in swap_file.edits.iter() {
+                self.piece_table.apply(edit);
+            }
+            self.piece_table.journal.clear();
+        }
+    }
+
+    /// Delete the found swap file, keeping the file contents.
+    pub fn discard_swap_file(&mut self) -> io::Result<()> {
+        self.found_swap_file = None;
+        self.remove_swap_file()
+    }
+
+    /// True if there are edits, which are not yet in the swap file.
+    pub fn has_unjournaled_edits(&self) -> bool {
+        !self.piece_table.journal.is_empty()
+    }
+
+    /// Append the edits made since the last call to the swap file.
+    /// The journal is kept in memory if writing fails, and retried next time.
+    /// Without a swap file (e.g. for an unnamed buffer) it is dropped.
+    pub fn write_swap_file(&mut self) -> io::Result<()> {
+        // don't touch the swap file of another session before the user decided
+        if self.found_swap_file.is_some() || self.piece_table.journal.is_empty() {
+            return Ok(());
+        }
+        // nor one of another program, ever; an unnamed buffer has none
+        let Some(path) = self.file_path.as_ref().filter(|_| !self.foreign_swap_file) else {
+            self.piece_table.journal.clear();
+            return Ok(());
+        };
+        let swap_path = swap_file::swap_path(path);
+        swap_file::append(&swap_path, self.disk_state.hash, &self.piece_table.journal)?;
+        self.piece_table.journal.clear();
+        Ok(())
+    }
+
+    /// Delete the swap file, e.g. when quitting without saving.
+    pub fn remove_swap_file(&self) -> io::Result<()> {
+        if self.foreign_swap_file {
+            return Ok(());
+        }
+        match self.file_path.as_ref() {
+            Some(path) => swap_file::remove(&swap_file::swap_path(path)),
+            None => Ok(()),
         }
     }
 
     pub fn file_name(&self) -> Option<&str> {
         if let Some(file_path) = &self.file_path {
-            return Some(file_path.split('/').last().unwrap());
+            return Some(file_path.split('/').next_back().unwrap());
         }
         None
     }
 
     pub fn file_extension(&self) -> Option<&str> {
         if let Some(file_name) = &self.file_name() {
-            return Some(file_name.split('.').last().unwrap());
+            return Some(file_name.split('.').next_back().unwrap());
         }
         None
     }
 
     pub fn get(&self, from: &Position, until: Option<&Position>) -> String {
-        if let Some(from_offset) = self.piece_table.get_offset_from_position(from) {
+        if let Some(from_offset) = self.get_offset_from_position(from) {
             if let Some(until_pos) = until {
-                if let Some(until_offset) = self.piece_table.get_offset_from_position(until_pos) {
+                if let Some(until_offset) = self.get_offset_from_position(until_pos) {
                     return self.piece_table.get(from_offset, Some(until_offset));
                 }
             }
@@ -91,12 +472,95 @@ impl Buffer {
         self.piece_table.get(0, None)
     }
 
-    pub fn find(&self, text: &str, offset: usize, all: bool) -> Vec<std::ops::Range<usize>> {
-        self.piece_table.find(text, offset, all)
+    /// Get the contents of line `y` without the line break.
+    pub fn get_line(&self, y: usize) -> String {
+        let Some(range) = self.line_range(y) else {
+            return String::new();
+        };
+        let mut line = self.piece_table.get(range.start, Some(range.end));
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        line
+    }
+
+    /// Length of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.piece_table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.piece_table.is_empty()
+    }
+
+    /// Get the text of a byte range.
+    pub fn get_range(&self, range: std::ops::Range<usize>) -> String {
+        self.piece_table.get(range.start, Some(range.end))
+    }
+
+    /// Set a mark at the position, which is clamped to the buffer.
+    pub fn set_mark(&mut self, name: char, position: &Position) {
+        let offset = self
+            .get_offset_from_position(position)
+            .unwrap_or(self.piece_table.len());
+        self.piece_table.marks.insert(name, offset);
+    }
+
+    /// Position of a mark, which moved along with the text since it was set.
+    pub fn mark(&self, name: char) -> Option<Position> {
+        let offset = self.piece_table.marks.get(&name)?;
+        Some(self.get_position_from_offset(*offset))
+    }
+
+    pub fn remove_mark(&mut self, name: char) {
+        self.piece_table.marks.remove(&name);
+    }
+
+    /// All marks with their positions, sorted by name.
+    pub fn marks(&self) -> Vec<(char, Position)> {
+        let mut marks: Vec<(char, Position)> = self
+            .piece_table
+            .marks
+            .iter()
+            .map(|(name, offset)| (*name, self.get_position_from_offset(*offset)))
+            .collect();
+        marks.sort_by_key(|(name, _)| *name);
+        marks
+    }
+
+    /// Iterate the characters, starting at a byte offset.
+    pub fn chars(&self, offset: usize) -> impl Iterator<Item = char> + '_ {
+        self.piece_table.chars(offset)
+    }
+
+    /// Iterate the characters before a byte offset, backwards.
+    pub fn chars_rev(&self, offset: usize) -> impl Iterator<Item = char> + '_ {
+        self.piece_table.chars_rev(offset)
+    }
+
+    /// Insert text at a byte offset.
+    pub fn insert_at(&mut self, text: &str, offset: usize) {
+        self.piece_table.insert(text, offset);
+    }
+
+    /// Delete a byte range.
+    pub fn delete_range(&mut self, range: std::ops::Range<usize>) {
+        if !range.is_empty() {
+            self.piece_table
+                .delete(range.start, range.end - range.start);
+        }
+    }
+
+    /// Matches of a pattern from the byte offset on (the first one, or `all`).
+    pub fn find(&self, regex: &Regex, offset: usize, all: bool) -> Vec<std::ops::Range<usize>> {
+        self.piece_table.find(regex, offset, all)
     }
 
     pub fn insert_new_line(&mut self, position: &Position) {
-        if let Some(offset) = self.piece_table.get_offset_from_position(position) {
+        if let Some(offset) = self.get_offset_from_position(position) {
             self.piece_table.insert_new_line(offset);
         } else {
             // TODO: write warning to logs
@@ -104,60 +568,107 @@ impl Buffer {
     }
 
     pub fn insert(&mut self, text: &str, position: &Position) {
-        if let Some(offset) = self.piece_table.get_offset_from_position(position) {
+        if let Some(offset) = self.get_offset_from_position(position) {
             self.piece_table.insert(text, offset);
         } else {
             // TODO: write warning to logs
         }
     }
 
+    /// Delete `count` grapheme clusters, starting at the position. A line
+    /// break counts as one.
     pub fn delete(&mut self, position: &Position, count: usize) {
-        if let Some(offset) = self.piece_table.get_offset_from_position(position) {
-            self.piece_table.delete(offset, count);
+        let mut end = *position;
+        let mut length = self.get_line_length(end.y);
+        for _ in 0..count {
+            if end.x < length {
+                end.x += 1;
+            } else {
+                end = Position::new(0, end.y + 1);
+                length = self.get_line_length(end.y);
+            }
+        }
+        if let Some(offset) = self.get_offset_from_position(position) {
+            let end = self
+                .get_offset_from_position(&end)
+                .unwrap_or(self.piece_table.len());
+            self.piece_table.delete(offset, end.saturating_sub(offset));
         } else {
             // TODO: write warning to logs
         }
     }
 
-    /// Check if the buffer contains the column for the line. Use 0-based alignment.
-    pub fn get_line_length(&self, y: usize) -> usize {
-        let y_line_start_res = self
-            .piece_table
-            .get_offset_from_position(&Position { x: 0, y });
-        let next_y_line_start_res = self
-            .piece_table
-            .get_offset_from_position(&Position { x: 0, y: y + 1 });
+    pub fn begin_undo_group(&mut self) {
+        self.piece_table.begin_undo_group();
+    }
 
-        if let Some(y_line_start) = y_line_start_res {
-            if let Some(next_y_line_start) = next_y_line_start_res {
-                return next_y_line_start
-                    .saturating_sub(y_line_start)
-                    .saturating_sub(2);
-            }
-            // TODO: avoid getting full sequence
-            // idea: count via piece in reverse
-            let content_len = self
-                .get(&Position::new(0, 0), None)
-                .chars()
-                .skip(y_line_start)
-                .filter(|x| *x != '\n' && *x != '\r')
-                .count();
+    pub fn end_undo_group(&mut self) {
+        self.piece_table.end_undo_group();
+    }
 
-            return content_len;
-        }
-        0
+    /// Undo the last change. Returns the position where the change happened.
+    pub fn undo(&mut self) -> Option<Position> {
+        let offset = self.piece_table.undo()?;
+        Some(self.get_position_from_offset(offset))
+    }
+
+    /// Redo the last undone change. Returns the position where the change happened.
+    pub fn redo(&mut self) -> Option<Position> {
+        let offset = self.piece_table.redo()?;
+        Some(self.get_position_from_offset(offset))
+    }
+
+    /// Number of grapheme clusters in line `y`, without the line break. Use 0-based alignment.
+    pub fn get_line_length(&self, y: usize) -> usize {
+        unicode::graphemes(&self.get_line(y)).count()
     }
 
     pub fn get_total_lines(&self) -> usize {
         self.piece_table.get_total_lines()
     }
 
+    /// Byte range of line `y`, including its line break.
+    pub fn line_range(&self, y: usize) -> Option<std::ops::Range<usize>> {
+        let start = self
+            .piece_table
+            .get_offset_from_position(&Position::new(0, y))?;
+        let end = self
+            .piece_table
+            .get_offset_from_position(&Position::new(0, y + 1))
+            .unwrap_or(self.piece_table.len());
+        Some(start..end)
+    }
+
+    /// Convert a position (line, grapheme column) into a byte offset.
+    /// A column past the end of the line is clamped to the end of the line
+    /// (after its line break).
     pub fn get_offset_from_position(&self, position: &Position) -> Option<usize> {
-        self.piece_table.get_offset_from_position(position)
+        let range = self.line_range(position.y)?;
+        if position.x == 0 {
+            return Some(range.start);
+        }
+
+        let line = self.piece_table.get(range.start, Some(range.end));
+        Some(range.start + unicode::grapheme_offset(&line, position.x))
     }
 
+    /// Convert a byte offset into a position (line, grapheme column). An
+    /// offset inside a grapheme cluster maps to the cluster.
     pub fn get_position_from_offset(&self, offset: usize) -> Position {
-        self.piece_table.get_position_from_offset(offset)
+        let offset = std::cmp::min(offset, self.piece_table.len());
+        let position = self.piece_table.get_position_from_offset(offset);
+        Position::new(
+            unicode::grapheme_index(&self.get_line(position.y), position.x),
+            position.y,
+        )
+    }
+
+    /// Terminal column of the position, taking wide characters into account.
+    pub fn get_display_column(&self, position: &Position) -> usize {
+        unicode::graphemes(&self.get_line(position.y))
+            .take(position.x)
+            .map(unicode::grapheme_width)
+            .sum()
     }
 
     pub fn get_debug_status(&self, position: &Position) -> String {
@@ -180,6 +691,7 @@ impl Buffer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_dir::TestDir;
 
     #[test]
     fn test_buffer_init() {
@@ -209,9 +721,10 @@ mod tests {
         let buffer = Buffer::from_string(String::from(
             "File is read.\r\nThe hero lied.\r\nThe end.\r\nThe hero rises once more.",
         ));
-        let occurences = buffer.find("hero", 0, false);
-        assert_eq!(occurences[0], 19..23);
-        assert_eq!(occurences[1], 45..49);
+        let regex = Regex::new("hero", false).unwrap();
+        let occurences = buffer.find(&regex, 0, true);
+        assert_eq!(occurences, vec![19..23, 45..49]);
+        assert_eq!(buffer.find(&regex, 20, false), vec![45..49]);
     }
 
     #[test]
@@ -224,4 +737,356 @@ mod tests {
         assert_eq!(buffer.get_line_length(3), 0);
         assert_eq!(buffer.get_line_length(4), 0);
     }
+
+    #[test]
+    fn test_buffer_undo_redo() {
+        let mut buffer = Buffer::from_string("File is read.\r\nThe hero lied.".to_string());
+        buffer.begin_undo_group();
+        buffer.insert("has ", &Position::new(9, 1));
+        buffer.insert_new_line(&Position::new(13, 1));
+        buffer.end_undo_group();
+        assert_eq!(
+            buffer.get(&Position::new(0, 0), None),
+            "File is read.\r\nThe hero has \r\nlied."
+        );
+
+        assert_eq!(buffer.undo(), Some(Position::new(9, 1)));
+        assert_eq!(
+            buffer.get(&Position::new(0, 0), None),
+            "File is read.\r\nThe hero lied."
+        );
+        assert_eq!(buffer.redo(), Some(Position::new(9, 1)));
+        assert_eq!(buffer.get_total_lines(), 3);
+    }
+
+    #[test]
+    fn test_multi_byte_insert_delete() {
+        let mut buffer = Buffer::from_string("Grüße.\r\n🦀 lied.".to_string());
+        assert_eq!(buffer.get_line_length(0), 6);
+        assert_eq!(buffer.get_line_length(1), 7);
+
+        buffer.insert("ö", &Position::new(4, 0));
+        assert_eq!(buffer.get_line(0), "Grüßöe.");
+        buffer.insert("has ", &Position::new(2, 1));
+        assert_eq!(buffer.get_line(1), "🦀 has lied.");
+
+        buffer.delete(&Position::new(2, 0), 3);
+        assert_eq!(buffer.get_line(0), "Gre.");
+        buffer.delete(&Position::new(0, 1), 2);
+        assert_eq!(buffer.get_line(1), "has lied.");
+        buffer.delete(&Position::new(4, 0), 1);
+        assert_eq!(buffer.get(&Position::new(0, 0), None), "Gre.has lied.");
+        assert_eq!(buffer.get_total_lines(), 1);
+    }
+
+    #[test]
+    fn test_combining_characters() {
+        let mut buffer = Buffer::from_string("Cafe\u{301} na\u{308}ive.\r\n👍🏽 ok".to_string());
+        assert_eq!(buffer.get_line_length(0), 11);
+        assert_eq!(buffer.get_line_length(1), 4);
+        assert_eq!(
+            buffer.get_offset_from_position(&Position::new(4, 0)),
+            Some(6)
+        );
+        assert_eq!(buffer.get_position_from_offset(6), Position::new(4, 0));
+        // an offset inside a cluster maps to the cluster
+        assert_eq!(buffer.get_position_from_offset(4), Position::new(3, 0));
+        assert_eq!(buffer.get_display_column(&Position::new(5, 0)), 5);
+        assert_eq!(buffer.get_display_column(&Position::new(1, 1)), 2);
+
+        buffer.delete(&Position::new(3, 0), 1);
+        assert_eq!(buffer.get_line(0), "Caf na\u{308}ive.");
+        buffer.delete(&Position::new(5, 0), 2);
+        assert_eq!(buffer.get_line(0), "Caf nve.");
+        buffer.delete(&Position::new(0, 1), 1);
+        assert_eq!(buffer.get_line(1), " ok");
+    }
+
+    #[test]
+    fn test_multi_byte_find() {
+        let buffer = Buffer::from_string("Grüße, 🦀.\r\nÄ 🦀 lied.".to_string());
+        let occurences = buffer.find(&Regex::new("🦀", false).unwrap(), 0, true);
+        assert_eq!(occurences[0], 9..13);
+        assert_eq!(buffer.get_position_from_offset(9), Position::new(7, 0));
+        assert_eq!(occurences[1], 19..23);
+        assert_eq!(buffer.get_position_from_offset(19), Position::new(2, 1));
+        assert_eq!(
+            buffer.get_offset_from_position(&Position::new(2, 1)),
+            Some(19)
+        );
+        assert_eq!(buffer.get_display_column(&Position::new(3, 1)), 4);
+    }
+
+    #[test]
+    fn test_file_format() {
+        let buffer = Buffer::from_string("File is read.\nThe hero lied.\n".to_string());
+        assert_eq!(buffer.file_format(), FileFormat::Unix);
+        assert_eq!(buffer.get_line_length(0), 13);
+        assert_eq!(buffer.get_total_lines(), 3);
+        assert_eq!(buffer.file_contents(), "File is read.\nThe hero lied.\n");
+
+        let mut buffer = Buffer::from_string("File is read.\r\nThe end.\nlo.\r\n".to_string());
+        assert_eq!(buffer.file_format(), FileFormat::Dos);
+        assert!(buffer.has_mixed_line_endings());
+        assert_eq!(buffer.file_contents(), "File is read.\r\nThe end.\nlo.\r\n");
+
+        buffer.set_file_format(FileFormat::Unix);
+        assert!(!buffer.has_mixed_line_endings());
+        assert_eq!(buffer.file_contents(), "File is read.\nThe end.\nlo.\n");
+    }
+
+    #[test]
+    fn test_save_file_keeps_line_endings() {
+        let dir = TestDir::new("format");
+        let path = dir.0.join("format.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "File is read.\nThe end.\n").unwrap();
+
+        let mut buffer = open(path);
+        buffer.insert("!", &Position::new(8, 1));
+        buffer.save_file().unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "File is read.\nThe end.!\n"
+        );
+    }
+
+    /// Load a file without reading or writing the undo history in the user's
+    /// cache directory.
+    fn open(path: &str) -> Buffer {
+        Buffer::from_file_with_undo_dir(path, None).unwrap()
+    }
+
+    #[test]
+    fn test_undo_dir() {
+        let dir = TestDir::new("undo-dir");
+        let path = dir.0.join("undo-dir.txt");
+        let path = path.to_str().unwrap();
+        let undo_dir = dir.0.join("undo");
+        fs::write(path, "File is read.\n").unwrap();
+
+        let mut buffer = Buffer::from_file_with_undo_dir(path, Some(undo_dir.clone())).unwrap();
+        buffer.insert("!", &Position::new(13, 0));
+        buffer.save_file().unwrap();
+        assert!(undo_history::history_path(&undo_dir, path).exists());
+
+        let mut buffer = Buffer::from_file_with_undo_dir(path, Some(undo_dir.clone())).unwrap();
+        buffer.undo();
+        assert_eq!(buffer.get_line(0), "File is read.");
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = TestDir::new("atomic");
+        let path = dir.0.join("atomic.txt");
+        fs::write(&path, "File is read.").unwrap();
+
+        write_atomic(&path, b"The hero lied.").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "The hero lied.");
+
+        let tmp_name = format!(
+            ".{}.{}.nimbus-tmp",
+            path.file_name().unwrap().to_string_lossy(),
+            process::id()
+        );
+        assert!(!path.with_file_name(tmp_name).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = TestDir::new("atomic-link");
+        let path = dir.0.join("atomic-target.txt");
+        let link = dir.0.join("atomic-link.txt");
+        fs::write(&path, "File is read.").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(&path, &link).unwrap();
+
+        write_atomic(&link, b"The hero lied.").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "The hero lied.");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+    }
+
+    #[test]
+    fn test_write_atomic_error() {
+        let dir = TestDir::new("atomic-error");
+        let path = dir.0.join("missing-dir").join("file.txt");
+        let error = write_atomic(&path, b"The hero lied.").unwrap_err();
+        assert!(error.to_string().starts_with("creating "));
+    }
+
+    #[test]
+    fn test_save_file_mixed_line_endings() {
+        let dir = TestDir::new("mixed");
+        let path = dir.0.join("mixed.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "a\r\nb\nc\r\nd\ne\r\n").unwrap();
+
+        // unchanged lines keep their line ending, new ones get the most used
+        let mut buffer = open(path);
+        buffer.insert_new_line(&Position::new(1, 2));
+        buffer.insert("x", &Position::new(0, 3));
+        buffer.delete(&Position::new(1, 0), 1);
+        buffer.save_file().unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "ab\nc\r\nx\r\nd\ne\r\n");
+
+        // undo brings back the line break with its line ending
+        buffer.undo();
+        buffer.save_file().unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "a\r\nb\nc\r\nx\r\nd\ne\r\n"
+        );
+
+        let mut buffer = open(path);
+        assert!(buffer.has_mixed_line_endings());
+        buffer.set_file_format(FileFormat::Unix);
+        buffer.save_file().unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "a\nb\nc\nx\nd\ne\n");
+    }
+
+    #[test]
+    fn test_swap_file_recovery() {
+        let dir = TestDir::new("swap");
+        let path = dir.0.join("swap.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "File is read.\nThe end.\n").unwrap();
+
+        // first session crashes after the swap file was written
+        let mut buffer = open(path);
+        assert!(!buffer.has_swap_file());
+        buffer.begin_undo_group();
+        buffer.insert("not ", &Position::new(8, 0));
+        buffer.end_undo_group();
+        buffer.write_swap_file().unwrap();
+        assert!(!buffer.has_unjournaled_edits());
+
+        let mut buffer = open(path);
+        assert!(buffer.has_swap_file());
+        assert!(buffer.swap_file_matches());
+        assert_eq!(
+            buffer.swap_file_diff(),
+            vec!["+ File is not read.", "- File is read.", "  The end."]
+        );
+        buffer.recover_swap_file();
+        assert_eq!(buffer.get_line(0), "File is not read.");
+
+        // recovered edits can be undone, saving removes the swap file
+        buffer.undo();
+        assert_eq!(buffer.get_line(0), "File is read.");
+        buffer.redo();
+        buffer.save_file().unwrap();
+        assert!(!swap_file::swap_path(path).exists());
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "File is not read.\nThe end.\n"
+        );
+    }
+
+    #[test]
+    fn test_swap_file_of_unnamed_buffer() {
+        let mut buffer = Buffer::from_string("File is read.".to_string());
+        buffer.insert("!", &Position::new(13, 0));
+        assert!(buffer.has_unjournaled_edits());
+        buffer.write_swap_file().unwrap();
+        assert!(!buffer.has_unjournaled_edits());
+    }
+
+    #[test]
+    fn test_foreign_swap_file() {
+        let dir = TestDir::new("swap-foreign");
+        let path = dir.0.join("swap-foreign.txt");
+        let path = path.to_str().unwrap();
+        let swap_path = swap_file::swap_path(path);
+        let name = Path::new(path).file_name().unwrap().to_str().unwrap();
+        let vim_swap_path = Path::new(path).with_file_name(format!(".{}.swp", name));
+        fs::write(path, "File is read.\n").unwrap();
+        fs::write(&swap_path, b"b0VIM 9.0\0").unwrap();
+        fs::write(&vim_swap_path, b"b0VIM 9.0\0").unwrap();
+
+        // the file is neither written, nor removed on save or quit
+        let mut buffer = open(path);
+        assert!(!buffer.has_swap_file());
+        assert!(buffer.take_message().unwrap().contains("left alone"));
+        buffer.insert("!", &Position::new(13, 0));
+        buffer.write_swap_file().unwrap();
+        assert!(!buffer.has_unjournaled_edits());
+        buffer.save_file().unwrap();
+        buffer.remove_swap_file().unwrap();
+        assert_eq!(fs::read(&swap_path).unwrap(), b"b0VIM 9.0\0");
+        assert_eq!(fs::read(&vim_swap_path).unwrap(), b"b0VIM 9.0\0");
+    }
+
+    #[test]
+    fn test_swap_file_of_changed_file() {
+        let dir = TestDir::new("swap-changed");
+        let path = dir.0.join("swap-changed.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "File is read.\n").unwrap();
+
+        let mut buffer = open(path);
+        buffer.insert("!", &Position::new(13, 0));
+        buffer.write_swap_file().unwrap();
+        fs::write(path, "File was changed.\n").unwrap();
+
+        let mut buffer = open(path);
+        assert!(buffer.has_swap_file());
+        assert!(!buffer.swap_file_matches());
+        buffer.discard_swap_file().unwrap();
+        assert!(!swap_file::swap_path(path).exists());
+        assert_eq!(buffer.get_line(0), "File was changed.");
+    }
+
+    #[test]
+    fn test_disk_changed() {
+        let dir = TestDir::new("disk-changed");
+        let path = dir.0.join("disk-changed.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "File is read.\n").unwrap();
+
+        let mut buffer = open(path);
+        assert_eq!(buffer.disk_changed().unwrap(), None);
+
+        // same contents written again is not a change
+        fs::write(path, "File is read.\n").unwrap();
+        assert_eq!(buffer.disk_changed().unwrap(), None);
+
+        fs::write(path, "File was changed.\n").unwrap();
+        assert!(buffer.disk_changed().unwrap().is_some());
+
+        buffer.insert("!", &Position::new(13, 0));
+        let copy_path = buffer.save_copy().unwrap();
+        assert_eq!(copy_path, format!("{}.mine", path));
+        assert_eq!(fs::read_to_string(&copy_path).unwrap(), "File is read.!\n");
+        assert_eq!(fs::read_to_string(path).unwrap(), "File was changed.\n");
+        assert_eq!(buffer.file_path(), Some(path));
+        assert!(buffer.is_modified());
+        assert!(buffer.disk_changed().unwrap().is_some());
+        assert_eq!(buffer.save_copy().unwrap(), format!("{}.mine.1", path));
+
+        let mut buffer = open(path);
+        fs::write(path, "File was changed again.\n").unwrap();
+        buffer.reload().unwrap();
+        assert_eq!(buffer.get_line(0), "File was changed again.");
+        assert_eq!(buffer.disk_changed().unwrap(), None);
+    }
+
+    #[test]
+    fn test_is_modified() {
+        let mut buffer = Buffer::from_string("File is read.".to_string());
+        assert!(!buffer.is_modified());
+        buffer.insert("!", &Position::new(13, 0));
+        assert!(buffer.is_modified());
+        buffer.undo();
+        assert!(!buffer.is_modified());
+    }
 }
@@ -0,0 +1,132 @@
+use std::{io, process::Command};
+
+use crate::register::{Register, RegisterKind};
+
+/// Environment variable with the default read command.
+const READ_COMMAND_VAR: &str = "NIMBUS_CLIPBOARD_CMD";
+
+/// System clipboard, behind the register `"+`.
+///
+/// Text is copied with the OSC 52 escape sequence, which the terminal
+/// forwards to the local clipboard, also over SSH. Terminals rarely allow
+/// reading the clipboard that way, so pasting runs a command which prints
+/// the clipboard (e.g. `xclip -o -selection clipboard`). Without a command,
+/// the text copied last is pasted.
+#[derive(Default)]
+pub struct Clipboard {
+    /// Shell command, which prints the clipboard contents.
+    pub read_command: Option<String>,
+    copied: Option<String>,
+}
+
+impl Clipboard {
+    /// Clipboard with the read command from `$NIMBUS_CLIPBOARD_CMD`.
+    pub fn from_env() -> Clipboard {
+        Clipboard {
+            read_command: std::env::var(READ_COMMAND_VAR)
+                .ok()
+                .filter(|command| !command.is_empty()),
+            copied: None,
+        }
+    }
+
+    /// Remember copied text, with line breaks as in the buffer.
+    pub fn copy(&mut self, text: &str) {
+        self.copied = Some(text.to_string());
+    }
+
+    /// Clipboard contents as a register. Text ending with a line break is
+    /// put as lines.
+    pub fn paste(&self) -> io::Result<Option<Register>> {
+        let text = match &self.read_command {
+            Some(command) => {
+                let output = Command::new("sh").arg("-c").arg(command).output()?;
+                if !output.status.success() {
+                    return Err(io::Error::other(format!(
+                        "{} failed: {}",
+                        command, output.status
+                    )));
+                }
+                String::from_utf8_lossy(&output.stdout)
+                    .replace("\r\n", "\n")
+                    .replace('\n', "\r\n")
+            }
+            None => match &self.copied {
+                Some(text) => text.clone(),
+                None => return Ok(None),
+            },
+        };
+        let kind = match text.ends_with("\r\n") {
+            true => RegisterKind::Lines,
+            false => RegisterKind::Chars,
+        };
+        Ok(Some(Register { text, kind }))
+    }
+}
+
+/// OSC 52 escape sequence, which sets the clipboard to the text. Line breaks
+/// are sent as `\n`.
+pub fn osc52(text: &str) -> String {
+    format!(
+        "\x1b]52;c;{}\x07",
+        base64(text.replace("\r\n", "\n").as_bytes())
+    )
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(osc52("fn main\r\n"), "\x1b]52;c;Zm4gbWFpbgo=\x07");
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut clipboard = Clipboard::default();
+        assert_eq!(clipboard.paste().unwrap(), None);
+        clipboard.copy("copied");
+        assert_eq!(clipboard.paste().unwrap().unwrap().text, "copied");
+
+        // a fake clipboard program
+        clipboard.read_command = Some("printf 'one\\ntwo\\n'".to_string());
+        assert_eq!(
+            clipboard.paste().unwrap(),
+            Some(Register {
+                text: "one\r\ntwo\r\n".to_string(),
+                kind: RegisterKind::Lines
+            })
+        );
+        clipboard.read_command = Some("printf 'word'".to_string());
+        assert_eq!(
+            clipboard.paste().unwrap().unwrap().kind,
+            RegisterKind::Chars
+        );
+        clipboard.read_command = Some("exit 1".to_string());
+        assert!(clipboard.paste().is_err());
+    }
+}
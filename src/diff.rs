@@ -0,0 +1,77 @@
+/// Line based diff of two texts, using the longest common subsequence.
+/// Unchanged lines are prefixed with `  `, removed lines with `- ` and added
+/// lines with `+ `.
+pub fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // skip the common prefix and suffix, they are usually most of the file
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] = length of the LCS of old_changed[i..] and new_changed[j..]
+    let mut lcs = vec![vec![0usize; new_changed.len() + 1]; old_changed.len() + 1];
+    for i in (0..old_changed.len()).rev() {
+        for j in (0..new_changed.len()).rev() {
+            lcs[i][j] = if old_changed[i] == new_changed[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<String> = old[..prefix].iter().map(|l| format!("  {}", l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_changed.len() || j < new_changed.len() {
+        if i < old_changed.len() && j < new_changed.len() && old_changed[i] == new_changed[j] {
+            lines.push(format!("  {}", old_changed[i]));
+            i += 1;
+            j += 1;
+        } else if j < new_changed.len()
+            && (i == old_changed.len() || lcs[i][j + 1] >= lcs[i + 1][j])
+        {
+            lines.push(format!("+ {}", new_changed[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", old_changed[i]));
+            i += 1;
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|l| format!("  {}", l)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_diff() {
+        let diff = line_diff(
+            "File is read.\nThe hero lied.\nThe end.",
+            "File is read.\nThe hero has lied.\nThe end.\nBensu.",
+        );
+        assert_eq!(
+            diff,
+            vec![
+                "  File is read.",
+                "+ The hero has lied.",
+                "- The hero lied.",
+                "  The end.",
+                "+ Bensu.",
+            ]
+        );
+    }
+}
@@ -1,24 +1,164 @@
 use crate::{
-    buffer::Buffer, file_extension::FileExtension, highlighter::Highlighter,
-    highlighter_rust::HighlighterRust, highlighter_toml::HighlighterToml, position::Position,
-    terminal::Terminal,
+    buffer::{Buffer, DiskState},
+    clipboard::Clipboard,
+    ex_command::{self, Address, ExCommand, LineAddress},
+    file_extension::FileExtension,
+    file_format::FileFormat,
+    highlighter::Highlighter,
+    highlighter_rust::HighlighterRust,
+    highlighter_toml::HighlighterToml,
+    key_notation,
+    marks::{self, FileMark, Jump, JumpList},
+    motion,
+    normal_command::{self, NormalCommand},
+    operator::{self, Operator, TextRange},
+    position::Position,
+    regex::{self, Captures, CaseOptions, Regex},
+    register::{self, Register, RegisterKind, Registers},
+    substitute,
+    terminal::{Input, Terminal},
+    text_object, unicode,
+    window::{Layout, Rect, SplitDirection, Window},
+    word::{self, WordMotion},
 };
 use std::{
     collections::HashMap,
-    env,
-    io::{stdin, Error, Stdin},
+    env, fs,
+    io::{Error, ErrorKind},
     ops::Range,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 use termion::{
     color::{self},
     event::Key,
 };
 
+/// Minimum time between two writes of the swap file while typing.
+const SWAP_FILE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Minimum time between two checks for changes of the file by other programs.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Maximum number of macros playing each other, e.g. a recursive macro.
+const MAX_MACRO_DEPTH: usize = 100;
+
 #[derive(PartialEq)]
 enum EditorMode {
     Normal,
     Insert,
     Command,
+    Prompt,
+    /// Characterwise selection (`v`).
+    Visual,
+    /// Linewise selection (`V`).
+    VisualLine,
+    /// Blockwise selection (`Ctrl-v`).
+    VisualBlock,
+}
+
+/// Question shown in the command line, answered with a single key.
+enum Prompt {
+    /// The file was changed on disk by another program.
+    FileChanged { saving: bool, disk_state: DiskState },
+    /// A swap file of an earlier session was found for a file opened with `:e`.
+    SwapFile,
+    /// `:s` with the `c` flag asks whether to replace a match.
+    Substitute(Box<Substitution>),
+}
+
+/// State of `:s` while it goes through the matches in its range.
+struct Substitution {
+    pattern: String,
+    regex: Regex,
+    replacement: String,
+    global: bool,
+    /// Byte offset the next match is searched from.
+    position: usize,
+    /// End of the last line of the range, before its line break. Matches
+    /// starting after it are not replaced.
+    end: usize,
+    /// Match waiting for an answer (with `c`).
+    pending: Option<Captures>,
+    count: usize,
+    /// Number of lines, in which matches were replaced.
+    lines: usize,
+    /// Line and byte offset of the last replacement.
+    last: Option<(usize, usize)>,
+}
+
+impl Substitution {
+    /// Next match in the range, searching the text from `position`.
+    fn next_match(&self, text: &str) -> Option<Captures> {
+        if self.position > text.len() {
+            return None;
+        }
+        let captures = self.regex.find_at(text, self.position)?;
+        (captures.range().start <= self.end).then_some(captures)
+    }
+
+    /// Byte offset the search continues from after a match: the end of the
+    /// match, or the next line without `g`. After an empty match, the next
+    /// character is skipped.
+    fn next_position(&self, text: &str, range: &Range<usize>) -> usize {
+        if !self.global {
+            if !range.is_empty() && text[..range.end].ends_with('\n') {
+                return range.end;
+            }
+            return text[range.end..]
+                .find('\n')
+                .map_or(text.len() + 1, |i| range.end + i + 1);
+        }
+        match range.is_empty() {
+            true => range.end + text[range.end..].chars().next().map_or(1, char::len_utf8),
+            false => range.end,
+        }
+    }
+
+    /// Count a replacement at the byte offset in line `y`.
+    fn count_replacement(&mut self, y: usize, offset: usize) {
+        self.count += 1;
+        if self.last.is_none_or(|(last_y, _)| last_y != y) {
+            self.lines += 1;
+        }
+        self.last = Some((y, offset));
+    }
+}
+
+/// Buffer in the buffer list, with the scroll offset and cursor it had when
+/// it was shown last.
+struct BufferEntry {
+    buffer: Buffer,
+    offset_y: usize,
+    cursor_position: Position,
+}
+
+/// Change, which `.` repeats.
+#[derive(Clone)]
+enum Change {
+    /// `{operator}{motion}`, e.g. `d3w` or `ci"`.
+    Operator {
+        register: char,
+        operator: Operator,
+        count: Option<usize>,
+        motion: char,
+        argument: Option<char>,
+    },
+    /// `p` or `P`.
+    Put {
+        register: char,
+        count: usize,
+        after: bool,
+    },
+    /// Insert mode started with `i` or `A`.
+    Insert(char),
+}
+
+/// Last change, with the keys typed in Insert mode after it.
+#[derive(Clone)]
+struct RepeatableChange {
+    change: Change,
+    inserted: Vec<Key>,
 }
 
 #[derive(PartialEq)]
@@ -33,13 +173,23 @@ pub struct Editor {
     cursor_position: Position,
     current_line_length: usize,
     status: String,
+    status_message: Option<String>,
     command: String,
     mode: EditorMode,
     retain_command_text: bool,
     running: bool,
     untouched: bool,
     debug_bar: bool,
-    buffer: Buffer,
+    buffers: Vec<BufferEntry>,
+    current_buffer: usize,
+    /// All windows. The state of the current window is kept in `offset_y`,
+    /// `cursor_position` and `current_buffer`, and only written back when
+    /// switching windows or drawing.
+    windows: Vec<Window>,
+    current_window: usize,
+    layout: Layout,
+    /// The window command prefix (Ctrl-x) was pressed.
+    window_prefix: bool,
     highlighters: HashMap<FileExtension, Box<dyn Highlighter>>,
     extensions: HashMap<String, FileExtension>,
     file_extension: FileExtension,
@@ -49,66 +199,330 @@ pub struct Editor {
     search_offset_y: usize,
     search_cursor_position: Position,
     motion_acc: String,
+    swap_file_written_at: Instant,
+    prompt: Option<Prompt>,
+    disk_checked_at: Instant,
+    disk_check_pending: bool,
+    /// Change on disk, which the user chose to ignore.
+    ignored_disk_state: Option<DiskState>,
+    /// Other end of the selection in Visual mode, as a buffer position. The
+    /// cursor is the end, which moves.
+    visual_anchor: Position,
+    /// Yanked and deleted text.
+    registers: Registers,
+    clipboard: Clipboard,
+    /// Start of a changed block and its last line. The text inserted in the
+    /// first line is repeated in the other lines when Insert mode ends.
+    block_insert: Option<(Position, usize)>,
+    /// Change, which `.` repeats.
+    last_change: Option<RepeatableChange>,
+    /// Change, which started the current Insert mode.
+    pending_change: Option<RepeatableChange>,
+    /// Register and keys of the macro being recorded.
+    recording: Option<(char, Vec<Key>)>,
+    /// Register of the macro played last, for `@@`.
+    last_macro: Option<char>,
+    /// Number of macros being played, which started each other.
+    macro_depth: usize,
+    /// Last character search (`f`, `F`, `t` or `T`) and its character, which
+    /// `;` and `,` repeat.
+    last_char_search: Option<(char, char)>,
+    /// File marks (`A` to `Z`) of files, which are not open. The marks of
+    /// open files are kept in their buffers.
+    file_marks: HashMap<char, FileMark>,
+    /// File the file marks are read from and written to on quit.
+    marks_path: Option<PathBuf>,
+    /// Positions before large jumps, for `Ctrl-o` and `Ctrl-i`.
+    jump_list: JumpList,
+    /// Pattern of the last search or substitution, which `:s` with an
+    /// empty pattern uses again.
+    last_pattern: Option<String>,
+    /// Case sensitivity of searches, `:s` and pattern addresses.
+    case_options: CaseOptions,
 }
 
 impl Editor {
     pub fn new(buffer: Buffer) -> Result<Editor, Error> {
+        Ok(Editor::with_terminal(buffer, Terminal::new()?))
+    }
+
+    /// Editor drawing to the terminal, e.g. one of a fixed size in tests.
+    pub fn with_terminal(mut buffer: Buffer, terminal: Terminal) -> Editor {
         let mut extensions = HashMap::new();
         extensions.insert("rs".to_string(), FileExtension::Rust);
         extensions.insert("toml".to_string(), FileExtension::Toml);
         extensions.insert("txt".to_string(), FileExtension::Text);
         extensions.insert("".to_string(), FileExtension::Unknown);
 
-        let file_extension = extensions
-            .get(buffer.file_extension().unwrap_or(""))
-            .unwrap_or(&FileExtension::Unknown)
-            .clone();
-
         let mut highlighters = HashMap::new();
-        match file_extension.as_str() {
-            "rs" => {
-                highlighters.insert(
-                    FileExtension::Rust,
-                    Box::<HighlighterRust>::default() as Box<dyn Highlighter>,
-                );
-            }
-            "toml" => {
-                highlighters.insert(
-                    FileExtension::Toml,
-                    Box::<HighlighterToml>::default() as Box<dyn Highlighter>,
-                );
-            }
-            _ => (),
-        }
+        highlighters.insert(
+            FileExtension::Rust,
+            Box::<HighlighterRust>::default() as Box<dyn Highlighter>,
+        );
+        highlighters.insert(
+            FileExtension::Toml,
+            Box::<HighlighterToml>::default() as Box<dyn Highlighter>,
+        );
 
-        Ok(Editor {
-            terminal: Terminal::new()?,
+        let mut editor = Editor {
+            terminal,
             offset_y: 0,
             cursor_position: Position::default(),
             current_line_length: buffer.get_line_length(0),
             status: String::new(),
+            status_message: buffer.take_message(),
             command: String::new(),
             mode: EditorMode::Normal,
             retain_command_text: false,
             running: true,
             untouched: buffer.file_path().is_none(),
             debug_bar: false,
-            buffer,
+            buffers: vec![],
+            current_buffer: 0,
+            windows: vec![Window::default()],
+            current_window: 0,
+            layout: Layout::Window(0),
+            window_prefix: false,
             highlighters,
             extensions,
-            file_extension,
+            file_extension: FileExtension::Unknown,
             search_mode: SearchMode::None,
             search_occurences: vec![],
             search_occurence_idx: 0,
             search_cursor_position: Position::default(),
             search_offset_y: 0,
             motion_acc: String::new(),
-        })
+            swap_file_written_at: Instant::now(),
+            prompt: None,
+            disk_checked_at: Instant::now(),
+            disk_check_pending: false,
+            ignored_disk_state: None,
+            visual_anchor: Position::default(),
+            registers: Registers::default(),
+            clipboard: Clipboard::from_env(),
+            block_insert: None,
+            last_change: None,
+            pending_change: None,
+            recording: None,
+            last_macro: None,
+            macro_depth: 0,
+            last_char_search: None,
+            file_marks: HashMap::new(),
+            marks_path: None,
+            jump_list: JumpList::default(),
+            last_pattern: None,
+            case_options: CaseOptions::default(),
+        };
+        editor.add_buffer(buffer);
+        editor.switch_buffer(0);
+        editor
+    }
+
+    fn buffer(&self) -> &Buffer {
+        &self.buffers[self.current_buffer].buffer
+    }
+
+    fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.current_buffer].buffer
+    }
+
+    /// Add a buffer to the end of the buffer list, without showing it.
+    pub fn add_buffer(&mut self, buffer: Buffer) {
+        self.buffers.push(BufferEntry {
+            buffer,
+            offset_y: 0,
+            cursor_position: Position::default(),
+        });
+        self.take_file_marks(self.buffers.len() - 1);
     }
 
+    /// Add a buffer to the buffer list and show it. An empty buffer without
+    /// file, like the one shown at startup, is replaced.
     pub fn load_buffer(&mut self, buffer: Buffer) {
-        self.buffer = buffer;
-        self.current_line_length = self.buffer.get_line_length(0);
+        if self.buffer().file_path().is_none() && !self.buffer().is_modified() {
+            self.buffers[self.current_buffer] = BufferEntry {
+                buffer,
+                offset_y: 0,
+                cursor_position: Position::default(),
+            };
+            self.take_file_marks(self.current_buffer);
+            self.offset_y = 0;
+            self.cursor_position = Position::default();
+            self.switch_buffer(self.current_buffer);
+        } else {
+            self.add_buffer(buffer);
+            self.switch_buffer(self.buffers.len() - 1);
+        }
+    }
+
+    /// Read the file marks of earlier sessions. They are written back on quit.
+    pub fn load_file_marks(&mut self) {
+        let Some(path) = marks::marks_path() else {
+            return;
+        };
+        match marks::read(&path) {
+            Ok(file_marks) => self.file_marks = file_marks,
+            Err(e) => self.status_message = Some(format!("Error reading marks: {}", e)),
+        }
+        self.marks_path = Some(path);
+        for index in 0..self.buffers.len() {
+            self.take_file_marks(index);
+        }
+    }
+
+    /// Move the file marks of the file of a buffer into the buffer, so they
+    /// move along with its text.
+    fn take_file_marks(&mut self, index: usize) {
+        let Some(path) = self.buffers[index].buffer.file_path() else {
+            return;
+        };
+        let path = marks::canonical_path(path);
+        let names: Vec<char> = self
+            .file_marks
+            .iter()
+            .filter(|(_, mark)| mark.path == path)
+            .map(|(name, _)| *name)
+            .collect();
+        for name in names {
+            if let Some(mark) = self.file_marks.remove(&name) {
+                self.buffers[index].buffer.set_mark(name, &mark.position);
+            }
+        }
+    }
+
+    /// Write the file marks of all files, if they were read at startup.
+    fn write_file_marks(&self) {
+        let Some(path) = &self.marks_path else {
+            return;
+        };
+        let mut file_marks = self.file_marks.clone();
+        for entry in self.buffers.iter() {
+            let Some(file_path) = entry.buffer.file_path() else {
+                continue;
+            };
+            let file_path = marks::canonical_path(file_path);
+            for (name, position) in entry.buffer.marks() {
+                if marks::is_file_mark(name) {
+                    let path = file_path.clone();
+                    file_marks.insert(name, FileMark { path, position });
+                }
+            }
+        }
+        marks::write(path, &file_marks).ok();
+    }
+
+    /// Show the buffer at `index` in the buffer list. The cursor and scroll
+    /// offset of the current buffer are kept, to restore them when switching
+    /// back.
+    fn switch_buffer(&mut self, index: usize) {
+        let entry = &mut self.buffers[self.current_buffer];
+        entry.offset_y = self.offset_y;
+        entry.cursor_position = self.cursor_position;
+
+        self.current_buffer = index;
+        self.offset_y = self.buffers[index].offset_y;
+        self.cursor_position = self.buffers[index].cursor_position;
+        self.current_line_length = self
+            .buffer()
+            .get_line_length(self.offset_y + self.cursor_position.y);
+        self.update_file_extension();
+        self.search_occurences.clear();
+        self.search_occurence_idx = 0;
+        if let Some(message) = self.buffer_mut().take_message() {
+            self.status_message = Some(message);
+        }
+    }
+
+    fn file_extension_of(&self, buffer: &Buffer) -> FileExtension {
+        self.extensions
+            .get(buffer.file_extension().unwrap_or(""))
+            .unwrap_or(&FileExtension::Unknown)
+            .clone()
+    }
+
+    fn update_file_extension(&mut self) {
+        self.file_extension = self.file_extension_of(self.buffer());
+    }
+
+    /// Open a file in a new buffer, or switch to it if it is open already.
+    /// A file which does not exist yet is created on save.
+    fn edit_file(&mut self, path: &str) {
+        let canonical = fs::canonicalize(path).ok();
+        let open = self.buffers.iter().position(|entry| {
+            entry.buffer.file_path().is_some_and(|file_path| {
+                file_path == path
+                    || (canonical.is_some() && fs::canonicalize(file_path).ok() == canonical)
+            })
+        });
+        if let Some(index) = open {
+            self.switch_buffer(index);
+            return;
+        }
+
+        let buffer = match Buffer::from_file(path) {
+            Ok(buffer) => buffer,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let mut buffer = Buffer::new();
+                buffer.set_file_path(path.to_string());
+                buffer
+            }
+            Err(e) => {
+                self.command = format!("Error opening {}: {}", path, e);
+                self.retain_command_text = true;
+                self.change_mode(EditorMode::Normal);
+                return;
+            }
+        };
+        self.load_buffer(buffer);
+        self.untouched = false;
+
+        if self.buffer().has_swap_file() {
+            self.change_mode(EditorMode::Prompt);
+            self.command = if self.buffer().swap_file_matches() {
+                "-- Found swap file with unsaved changes! [r]ecover, discard [x], <Esc> ignore"
+            } else {
+                "-- Found swap file for an older version of the file! discard [x], <Esc> ignore"
+            }
+            .to_string();
+            self.prompt = Some(Prompt::SwapFile);
+        } else {
+            self.change_mode(EditorMode::Normal);
+        }
+    }
+
+    /// Switch to the next (`step` = 1) or previous (`step` = -1) buffer.
+    fn cycle_buffer(&mut self, step: isize) {
+        let len = self.buffers.len() as isize;
+        let index = (self.current_buffer as isize + step).rem_euclid(len);
+        self.switch_buffer(index as usize);
+    }
+
+    /// List the buffers in the command area: number, `%` for the current
+    /// buffer, `+` for unsaved changes, name and cursor line.
+    fn list_buffers(&mut self) {
+        let lines: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let current = index == self.current_buffer;
+                let line = match current {
+                    true => self.offset_y + self.cursor_position.y,
+                    false => entry.offset_y + entry.cursor_position.y,
+                };
+                format!(
+                    "{:>3} {}{} \"{}\" line {}",
+                    index + 1,
+                    if current { '%' } else { ' ' },
+                    if entry.buffer.is_modified() { '+' } else { ' ' },
+                    entry.buffer.file_path().unwrap_or("[No Name]"),
+                    line + 1
+                )
+            })
+            .collect();
+        self.command = lines.join("\n");
+        self.retain_command_text = true;
+        self.change_mode(EditorMode::Normal);
     }
 
     fn adjusted_cursor_position(&self) -> Position {
@@ -118,91 +532,462 @@ impl Editor {
         )
     }
 
+    /// Cursor position in the buffer, i.e. including the scroll offset.
+    fn buffer_position(&self) -> Position {
+        Position::new(
+            self.cursor_position.x,
+            self.offset_y + self.cursor_position.y,
+        )
+    }
+
+    /// Cursor position in terminal cells. Wide characters take two cells.
+    fn terminal_cursor_position(&self) -> Position {
+        let position = self.adjusted_cursor_position();
+        let rect = self.window_rect();
+        Position::new(
+            rect.x
+                + self
+                    .buffer()
+                    .get_display_column(&Position::new(position.x, self.offset_y + position.y)),
+            rect.y + position.y,
+        )
+    }
+
     pub fn run(&mut self) {
-        let stdin = stdin();
         while self.running {
             self.terminal.clear();
 
-            self.draw_buffer();
-            self.draw_status_bar();
+            self.draw_windows();
             self.draw_command();
             self.draw_debug();
 
             // Position cursor
-            self.terminal.goto(&self.adjusted_cursor_position());
+            self.terminal.goto(&self.terminal_cursor_position());
             self.terminal.flush();
 
-            self.handle_user_input(&stdin);
+            // while waiting for a key, the file on disk is still checked
+            loop {
+                match self.terminal.read_input(DISK_CHECK_INTERVAL).unwrap() {
+                    Some(input) => {
+                        self.handle_user_input(input);
+                        break;
+                    }
+                    None => {
+                        self.write_swap_file();
+                        if self.check_disk_changes() {
+                            break;
+                        }
+                    }
+                }
+            }
+            self.write_swap_file();
+            self.check_disk_changes();
+        }
+    }
+
+    /// Area of every window and the separators between them. The last row of
+    /// the terminal is left for the command line.
+    fn window_rects(&self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let (width, height) = self.terminal.size();
+        let area = Rect::new(0, 0, width as usize, (height as usize).saturating_sub(1));
+        let (mut rects, mut separators) = (vec![], vec![]);
+        self.layout.rects(area, &mut rects, &mut separators);
+        (rects, separators)
+    }
+
+    /// Area of the current window.
+    fn window_rect(&self) -> Rect {
+        self.window_rects()
+            .0
+            .into_iter()
+            .find(|(index, _)| *index == self.current_window)
+            .map(|(_, rect)| rect)
+            .unwrap_or_default()
+    }
+
+    /// Write the state of the current window back into the window list.
+    fn save_window(&mut self) {
+        self.windows[self.current_window] = Window {
+            buffer: self.current_buffer,
+            offset_y: self.offset_y,
+            cursor_position: self.cursor_position,
+        };
+    }
+
+    /// Make the window at `index` the current one.
+    fn switch_window(&mut self, index: usize) {
+        self.save_window();
+        self.current_window = index;
+        self.load_window();
+    }
+
+    fn load_window(&mut self) {
+        let window = self.windows[self.current_window];
+        self.current_buffer = window.buffer;
+        self.offset_y = window.offset_y;
+        self.cursor_position = window.cursor_position;
+        self.current_line_length = self
+            .buffer()
+            .get_line_length(self.offset_y + self.cursor_position.y);
+        self.update_file_extension();
+        self.search_occurences.clear();
+        self.search_occurence_idx = 0;
+    }
+
+    /// Split the current window. The new window shows the same buffer, or
+    /// the file at `path`, and becomes the current one.
+    fn split_window(&mut self, direction: SplitDirection, path: Option<&str>) {
+        self.save_window();
+        self.windows.push(self.windows[self.current_window]);
+        let new_window = self.windows.len() - 1;
+        self.layout
+            .split(self.current_window, new_window, direction);
+        self.switch_window(new_window);
+        self.change_mode(EditorMode::Normal);
+
+        if let Some(path) = path {
+            self.edit_file(path);
+        }
+    }
+
+    /// Close the current window. The last window can't be closed.
+    fn close_window(&mut self) -> bool {
+        if self.windows.len() == 1 {
+            return false;
+        }
+        self.windows.remove(self.current_window);
+        self.layout.remove(self.current_window);
+        self.current_window = std::cmp::min(self.current_window, self.windows.len() - 1);
+        self.load_window();
+        true
+    }
+
+    /// Close all windows but the current one.
+    fn only_window(&mut self) {
+        self.save_window();
+        self.windows = vec![self.windows[self.current_window]];
+        self.current_window = 0;
+        self.layout = Layout::Window(0);
+    }
+
+    /// Move to the window left (`h`), below (`j`), above (`k`) or right (`l`)
+    /// of the current one, next to the cursor if there are several.
+    fn move_to_window(&mut self, direction: char) {
+        let rect = self.window_rect();
+        let cursor = self.terminal_cursor_position();
+        let candidates = match direction {
+            'h' => [
+                (rect.x.wrapping_sub(2), cursor.y),
+                (rect.x.wrapping_sub(2), rect.y),
+            ],
+            'l' => [
+                (rect.x + rect.width + 1, cursor.y),
+                (rect.x + rect.width + 1, rect.y),
+            ],
+            'k' => [
+                (cursor.x, rect.y.wrapping_sub(1)),
+                (rect.x, rect.y.wrapping_sub(1)),
+            ],
+            'j' => [
+                (cursor.x, rect.y + rect.height),
+                (rect.x, rect.y + rect.height),
+            ],
+            _ => return,
+        };
+
+        let rects = self.window_rects().0;
+        let target = candidates.iter().find_map(|(x, y)| {
+            rects
+                .iter()
+                .find(|(_, rect)| rect.contains(*x, *y))
+                .map(|(index, _)| *index)
+        });
+        if let Some(index) = target {
+            self.switch_window(index);
+        }
+    }
+
+    /// Handle the key after the window command prefix Ctrl-x.
+    fn handle_key_window(&mut self, key: Key) {
+        match key {
+            Key::Char(c @ ('h' | 'j' | 'k' | 'l')) => self.move_to_window(c),
+            Key::Left => self.move_to_window('h'),
+            Key::Down => self.move_to_window('j'),
+            Key::Up => self.move_to_window('k'),
+            Key::Right => self.move_to_window('l'),
+            Key::Char('w') | Key::Ctrl('x') => {
+                self.switch_window((self.current_window + 1) % self.windows.len())
+            }
+            Key::Char('s') => self.split_window(SplitDirection::Horizontal, None),
+            Key::Char('v') => self.split_window(SplitDirection::Vertical, None),
+            Key::Char('c' | 'q') => {
+                let closed = self.close_window();
+                if !closed {
+                    self.command = "Cannot close last window".to_string();
+                }
+            }
+            Key::Char('o') => self.only_window(),
+            _ => (),
+        }
+    }
+
+    /// Look for changes of the file by other programs, when the terminal
+    /// regains focus or every `DISK_CHECK_INTERVAL`, also while no keys are
+    /// pressed. Only done in Normal mode, to not interrupt typing. Returns
+    /// true if the user is asked about a change or an error is shown.
+    fn check_disk_changes(&mut self) -> bool {
+        if self.mode != EditorMode::Normal
+            || !(self.disk_check_pending || self.disk_checked_at.elapsed() >= DISK_CHECK_INTERVAL)
+        {
+            return false;
+        }
+        self.disk_check_pending = false;
+        self.disk_checked_at = Instant::now();
+
+        match self.buffer_mut().disk_changed() {
+            Ok(Some(disk_state)) if self.ignored_disk_state.as_ref() != Some(&disk_state) => {
+                self.ask_file_changed(false, disk_state);
+                true
+            }
+            Ok(_) => false,
+            Err(e) => {
+                self.status_message = Some(format!("Error checking file: {}", e));
+                true
+            }
+        }
+    }
+
+    fn ask_file_changed(&mut self, saving: bool, disk_state: DiskState) {
+        self.change_mode(EditorMode::Prompt);
+        self.command = format!(
+            "-- {} changed on disk! [r]eload, [o]verwrite, [k]eep both, <Esc> {}",
+            self.buffer().file_name().unwrap_or_default(),
+            if saving { "cancel" } else { "ignore" }
+        );
+        self.prompt = Some(Prompt::FileChanged { saving, disk_state });
+    }
+
+    fn handle_key_prompt(&mut self, key: Key) {
+        let (saving, disk_state) = match self.prompt.take() {
+            Some(Prompt::FileChanged { saving, disk_state }) => (saving, disk_state),
+            Some(Prompt::SwapFile) => {
+                self.handle_key_swap_file_prompt(key);
+                return;
+            }
+            Some(Prompt::Substitute(substitution)) => {
+                self.handle_key_substitute_prompt(key, *substitution);
+                return;
+            }
+            None => return,
+        };
+
+        match key {
+            Key::Char('r') => {
+                self.change_mode(EditorMode::Normal);
+                self.reload_buffer();
+            }
+            Key::Char('o') => {
+                self.change_mode(EditorMode::Normal);
+                self.write_buffer();
+            }
+            Key::Char('k') => {
+                self.change_mode(EditorMode::Normal);
+                self.command = match self.buffer().save_copy() {
+                    Ok(path) => {
+                        // the buffer stays unsaved, don't ask again for this change
+                        self.ignored_disk_state = Some(disk_state);
+                        format!(
+                            "-- Changes saved to {}, file on disk kept. The buffer still edits {}.",
+                            path,
+                            self.buffer().file_name().unwrap_or_default()
+                        )
+                    }
+                    Err(e) => format!("Error saving file: {}", e),
+                };
+            }
+            Key::Esc => {
+                self.ignored_disk_state = Some(disk_state);
+                self.change_mode(EditorMode::Normal);
+            }
+            _ => self.prompt = Some(Prompt::FileChanged { saving, disk_state }),
+        }
+    }
+
+    fn handle_key_swap_file_prompt(&mut self, key: Key) {
+        match key {
+            Key::Char('r') if self.buffer().swap_file_matches() => {
+                self.change_mode(EditorMode::Normal);
+                self.buffer_mut().recover_swap_file();
+                self.command = "-- Changes recovered.".to_string();
+                self.current_line_length = self
+                    .buffer()
+                    .get_line_length(self.offset_y + self.cursor_position.y);
+            }
+            Key::Char('x') => {
+                self.change_mode(EditorMode::Normal);
+                if let Err(e) = self.buffer_mut().discard_swap_file() {
+                    self.command = format!("Error removing swap file: {}", e);
+                }
+            }
+            Key::Esc => self.change_mode(EditorMode::Normal),
+            _ => self.prompt = Some(Prompt::SwapFile),
+        }
+    }
+
+    /// Replace the buffer with the file on disk, keeping the cursor line if
+    /// possible.
+    fn reload_buffer(&mut self) {
+        if let Err(e) = self.buffer_mut().reload() {
+            self.command = format!("Error reloading file: {}", e);
+            return;
+        }
+
+        self.status_message = self.buffer_mut().take_message();
+        self.command = "-- File reloaded.".to_string();
+        let last_line = self.buffer().get_total_lines() - 1;
+        let line = std::cmp::min(self.offset_y + self.cursor_position.y, last_line);
+        self.move_to_position(Position::new(self.cursor_position.x, line));
+    }
+
+    /// Journal the edits to the swap file. While typing in Insert mode, this
+    /// happens at most every `SWAP_FILE_INTERVAL`, otherwise after every change.
+    fn write_swap_file(&mut self) {
+        if !self
+            .buffers
+            .iter()
+            .any(|entry| entry.buffer.has_unjournaled_edits())
+            || (self.mode == EditorMode::Insert
+                && self.swap_file_written_at.elapsed() < SWAP_FILE_INTERVAL)
+        {
+            return;
+        }
+
+        for entry in self.buffers.iter_mut() {
+            if let Err(e) = entry.buffer.write_swap_file() {
+                self.status_message = Some(format!("Error writing swap file: {}", e));
+            }
+        }
+        self.swap_file_written_at = Instant::now();
+    }
+
+    fn handle_user_input(&mut self, input: Input) {
+        let key = match input {
+            Input::Key(key) => key,
+            Input::FocusGained => {
+                self.disk_check_pending = true;
+                return;
+            }
+        };
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
         }
+        self.handle_key(key);
     }
 
-    fn handle_user_input(&mut self, stdin: &Stdin) {
-        let key = self.terminal.read_key(stdin).unwrap();
+    fn handle_key(&mut self, key: Key) {
         self.untouched = false;
+        self.status_message = None;
+        // a listing over the buffer is only shown until the next key
+        if self.command.contains('\n') {
+            self.command.clear();
+        }
+
+        if self.mode == EditorMode::Prompt {
+            self.handle_key_prompt(key);
+            return;
+        }
+        if self.window_prefix {
+            self.window_prefix = false;
+            self.handle_key_window(key);
+            return;
+        }
+        if key == Key::Char('q')
+            && self.recording.is_some()
+            && self.motion_acc.is_empty()
+            && (self.mode == EditorMode::Normal || self.is_visual())
+        {
+            self.stop_recording();
+            return;
+        }
+        if self.mode == EditorMode::Insert
+            && matches!(key, Key::Char(_) | Key::Backspace | Key::Delete)
+        {
+            self.handle_key_insert_mode(key);
+            return;
+        }
 
         match key {
             Key::Char(c) => {
                 if c == '\n' {
                     match self.mode {
-                        EditorMode::Normal => self.move_down(1),
-                        EditorMode::Insert => {
-                            self.reset_cursor();
-                            self.buffer.insert_new_line(&Position::new(
-                                self.cursor_position.x,
-                                self.offset_y + self.cursor_position.y,
-                            ));
-                            self.cursor_position.x = 0;
-                            self.cursor_position.y += 1;
-                        }
+                        EditorMode::Normal
+                        | EditorMode::Visual
+                        | EditorMode::VisualLine
+                        | EditorMode::VisualBlock => self.move_down(1),
                         EditorMode::Command => self.run_command().unwrap_or(()),
+                        EditorMode::Insert | EditorMode::Prompt => (),
                     }
                 } else if c == ':' && self.mode == EditorMode::Normal {
                     self.change_mode(EditorMode::Command);
                     self.command.push(':');
+                } else if c == ':' && self.is_visual() && self.motion_acc.is_empty() {
+                    // the range of the selected lines
+                    let (start, end) = self.visual_bounds();
+                    self.buffer_mut().set_mark('<', &start);
+                    self.buffer_mut().set_mark('>', &end);
+                    self.change_mode(EditorMode::Command);
+                    self.command.push_str(":'<,'>");
                 } else {
                     match self.mode {
-                        EditorMode::Insert => {
-                            self.reset_cursor();
-                            self.buffer.insert(
-                                c.to_string().as_str(),
-                                &Position::new(
-                                    self.cursor_position.x,
-                                    self.cursor_position.y + self.offset_y,
-                                ),
-                            );
-                            self.cursor_position.x += 1;
-                        }
                         EditorMode::Command => {
                             self.command.push(c);
 
                             if self.command.starts_with('/') && self.command.len() > 1 {
-                                self.search_occurences =
-                                    self.buffer.find(&self.command.as_str()[1..], 0, true);
+                                self.update_search();
                                 self.search_mode = SearchMode::Incremental;
                             } else if c == '/' && self.command.is_empty() {
                                 self.search_offset_y = self.offset_y;
                             }
                         }
+                        EditorMode::Visual | EditorMode::VisualLine | EditorMode::VisualBlock => {
+                            self.handle_key_visual_mode(c)
+                        }
                         _ => self.handle_key_normal_mode(c),
                     }
                 }
                 self.current_line_length = self
-                    .buffer
+                    .buffer()
                     .get_line_length(self.offset_y + self.cursor_position.y);
             }
             Key::Esc => {
-                if self.mode == EditorMode::Insert || self.mode == EditorMode::Command {
+                if self.mode == EditorMode::Insert
+                    || self.mode == EditorMode::Command
+                    || self.is_visual()
+                {
                     self.search_mode = SearchMode::None;
                     self.search_offset_y = 0;
                     self.change_mode(EditorMode::Normal);
                 }
+                self.motion_acc.clear();
             }
             Key::Ctrl(c) => {
                 if c == 'q' {
                     self.quit();
                 }
                 if c == 'w' {
-                    self.save_buffer().unwrap();
+                    self.save_buffer();
+                }
+                if c == 'r' && self.mode == EditorMode::Normal {
+                    self.redo();
+                }
+                if c == 'x' && self.mode == EditorMode::Normal {
+                    self.window_prefix = true;
+                }
+                if c == 'o' && self.mode == EditorMode::Normal {
+                    self.jump_older();
+                }
+                if c == 'v' && (self.mode == EditorMode::Normal || self.is_visual()) {
+                    self.start_visual(EditorMode::VisualBlock);
                 }
             }
             Key::Backspace => {
@@ -213,52 +998,19 @@ impl Editor {
                         self.change_mode(EditorMode::Normal);
                         self.search_occurences.clear();
                     } else if self.command.starts_with('/') && self.command.len() > 1 {
-                        self.search_occurences =
-                            self.buffer.find(&self.command.as_str()[1..], 0, false);
+                        self.update_search();
                     }
                     return;
                 }
 
                 self.reset_cursor();
 
-                if self.mode == EditorMode::Insert {
-                    if self.cursor_position.x > 0 {
-                        self.cursor_position.x -= 1;
-                        self.buffer.delete(
-                            &Position::new(
-                                self.cursor_position.x,
-                                self.cursor_position.y + self.offset_y,
-                            ),
-                            1,
-                        );
-                    } else if self.cursor_position.y > 0 {
-                        let line_len = self
-                            .buffer
-                            .get_line_length(self.offset_y + self.cursor_position.y - 1);
-                        self.cursor_position.x = line_len;
-                        self.cursor_position.y -= 1;
-                        self.buffer.delete(
-                            &Position::new(
-                                self.cursor_position.x,
-                                self.cursor_position.y + self.offset_y,
-                            ),
-                            2,
-                        );
-                        self.current_line_length = line_len;
-                    } else {
-                        // empty
-                        return;
-                    }
-
-                    self.current_line_length = self
-                        .buffer
-                        .get_line_length(self.offset_y + self.cursor_position.y);
-                } else if self.mode == EditorMode::Normal {
+                if self.mode == EditorMode::Normal || self.is_visual() {
                     if self.cursor_position.x > 0 {
                         self.cursor_position.x -= 1;
                     } else if self.cursor_position.y > 0 {
                         let line_len = self
-                            .buffer
+                            .buffer()
                             .get_line_length(self.offset_y + self.cursor_position.y - 1);
                         self.cursor_position.x = line_len;
                         self.cursor_position.y -= 1;
@@ -266,32 +1018,6 @@ impl Editor {
                     }
                 }
             }
-            Key::Delete => {
-                if self.mode == EditorMode::Insert {
-                    if self.cursor_position.x < self.current_line_length {
-                        self.current_line_length -= 1;
-                        self.buffer.delete(
-                            &Position::new(
-                                self.cursor_position.x,
-                                self.cursor_position.y + self.offset_y,
-                            ),
-                            1,
-                        );
-                    } else if self.cursor_position.x == self.current_line_length
-                        && self.cursor_position.x > 0
-                    {
-                        self.current_line_length -= 1;
-                        self.cursor_position.x = self.current_line_length;
-                        self.buffer.delete(
-                            &Position::new(
-                                self.cursor_position.x,
-                                self.cursor_position.y + self.offset_y,
-                            ),
-                            1,
-                        );
-                    }
-                }
-            }
             Key::Left => self.move_left(1),
             Key::Right => self.move_right(1),
             Key::Up => self.move_up(1),
@@ -306,126 +1032,1719 @@ impl Editor {
         }
     }
 
+    /// Handle a key, which changes the text in Insert mode.
+    fn handle_key_insert_mode(&mut self, key: Key) {
+        if let Some(change) = &mut self.pending_change {
+            change.inserted.push(key);
+        }
+
+        match key {
+            Key::Char('\n') => {
+                self.reset_cursor();
+                let position = self.buffer_position();
+                self.buffer_mut().insert_new_line(&position);
+                self.cursor_position.x = 0;
+                self.cursor_position.y += 1;
+            }
+            Key::Char(c) => {
+                self.reset_cursor();
+                let position = self.buffer_position();
+                self.buffer_mut().insert(c.to_string().as_str(), &position);
+                self.cursor_position.x += 1;
+            }
+            Key::Backspace => {
+                self.reset_cursor();
+                if self.cursor_position.x > 0 {
+                    self.cursor_position.x -= 1;
+                    let position = self.buffer_position();
+                    self.buffer_mut().delete(&position, 1);
+                } else if self.cursor_position.y > 0 {
+                    let line_len = self
+                        .buffer()
+                        .get_line_length(self.offset_y + self.cursor_position.y - 1);
+                    self.cursor_position.x = line_len;
+                    self.cursor_position.y -= 1;
+                    let position = self.buffer_position();
+                    self.buffer_mut().delete(&position, 1);
+                } else {
+                    // empty
+                    return;
+                }
+            }
+            Key::Delete => {
+                if self.cursor_position.x < self.current_line_length {
+                    let position = self.buffer_position();
+                    self.buffer_mut().delete(&position, 1);
+                } else if self.cursor_position.x == self.current_line_length
+                    && self.cursor_position.x > 0
+                {
+                    self.cursor_position.x = self.current_line_length - 1;
+                    let position = self.buffer_position();
+                    self.buffer_mut().delete(&position, 1);
+                }
+            }
+            _ => return,
+        }
+        self.current_line_length = self
+            .buffer()
+            .get_line_length(self.offset_y + self.cursor_position.y);
+    }
+
+    /// Find the matches of the pattern typed after `/`. An incomplete
+    /// pattern, e.g. `\(`, finds nothing until it is completed.
+    fn update_search(&mut self) {
+        self.search_occurences = match regex::search_pattern(&self.command[1..], self.case_options)
+        {
+            Ok(regex) => self.buffer().find(&regex, 0, true),
+            Err(_) => vec![],
+        };
+    }
+
     fn run_command(&mut self) -> std::io::Result<()> {
         if self.command.starts_with('/') {
             if !self.search_occurences.is_empty() {
                 self.cursor_position = self.search_cursor_position;
             }
-            self.search_mode = SearchMode::None;
-            self.offset_y = self.search_offset_y;
-            self.search_offset_y = 0;
-            self.change_mode(EditorMode::Normal);
-            return Ok(());
+            self.search_mode = SearchMode::None;
+            self.offset_y = self.search_offset_y;
+            self.search_offset_y = 0;
+            if self.command.len() > 1 {
+                self.last_pattern = Some(self.command[1..].to_string());
+            }
+            self.change_mode(EditorMode::Normal);
+            return Ok(());
+        }
+
+        let Some((pre_command, command)) = self.command.split_once(':') else {
+            return Ok(());
+        };
+        let command = command.to_string();
+        if pre_command.contains("-- Create file") {
+            let path = format!("{}/{}", env::current_dir()?.display(), command);
+            self.buffer_mut().set_file_path(path);
+            self.clear_command();
+            self.save_buffer();
+            return Ok(());
+        }
+
+        if let Err(message) = self.run_ex_command(&command) {
+            self.command = message;
+            self.retain_command_text = true;
+            self.change_mode(EditorMode::Normal);
+        }
+        Ok(())
+    }
+
+    /// Run an ex command, e.g. `q`, `e {file}` or `'<,'>d`. Only the line
+    /// commands take a range.
+    fn run_ex_command(&mut self, text: &str) -> Result<(), String> {
+        let ExCommand {
+            range,
+            name,
+            bang,
+            argument,
+        } = ex_command::parse(text)?;
+        let y = self.offset_y + self.cursor_position.y;
+        let last_line = self.buffer().get_total_lines().saturating_sub(1);
+
+        match name.as_str() {
+            "" => {
+                if let Some(address) = range.last() {
+                    let line = self.resolve_address(address)?;
+                    self.record_jump();
+                    self.move_to_line(line.saturating_sub(1));
+                }
+                self.change_mode(EditorMode::Normal);
+                return Ok(());
+            }
+            "d" | "delete" | "y" | "yank" => {
+                let (first, last) = self.command_lines(&range, (y, y))?;
+                let (register, count) = ex_command::register_and_count(&argument)?;
+                let register = register.unwrap_or(register::UNNAMED);
+                if !Registers::is_valid(register) {
+                    return Err(format!("Invalid register: {}", register));
+                }
+                // a count selects lines from the last line of the range
+                let (first, last) = match count {
+                    Some(count) => (
+                        last,
+                        std::cmp::min(last.saturating_add(count - 1), last_line),
+                    ),
+                    None => (first, last),
+                };
+                let cursor = self.visual_cursor();
+                match name.starts_with('d') {
+                    true => self.apply_operator(
+                        Operator::Delete,
+                        TextRange::Lines(first, last),
+                        register,
+                    ),
+                    false => {
+                        self.apply_operator(
+                            Operator::Yank,
+                            TextRange::Lines(first, last),
+                            register,
+                        );
+                        self.move_to_position(cursor);
+                    }
+                }
+                return Ok(());
+            }
+            shift if shift.starts_with(['>', '<']) => {
+                let (first, last) = self.command_lines(&range, (y, y))?;
+                self.shift_lines(first, last, shift);
+                self.change_mode(EditorMode::Normal);
+                return Ok(());
+            }
+            "m" | "move" | "t" | "co" | "copy" => {
+                let (first, last) = self.command_lines(&range, (y, y))?;
+                let (Some(target), "") = ex_command::parse_address(&argument)? else {
+                    return Err(format!("Invalid address: {}", argument));
+                };
+                let target = self.resolve_address(&target)?;
+                if target > last_line + 1 {
+                    return Err("Invalid range".to_string());
+                }
+                match name.starts_with('m') {
+                    true => self.move_lines(first, last, target)?,
+                    false => self.copy_lines(first, last, target),
+                }
+                self.change_mode(EditorMode::Normal);
+                return Ok(());
+            }
+            "j" | "join" => {
+                let (mut first, mut last) = self.command_lines(&range, (y, y + 1))?;
+                let (None, count) = ex_command::register_and_count(&argument)? else {
+                    return Err(format!("Trailing characters: {}", argument));
+                };
+                if let Some(count) = count {
+                    (first, last) = (last, last.saturating_add(count - 1));
+                } else if first == last {
+                    last += 1;
+                }
+                self.join_lines(first, std::cmp::min(last, last_line), bang);
+                self.change_mode(EditorMode::Normal);
+                return Ok(());
+            }
+            "s" | "substitute" => {
+                let (first, last) = self.command_lines(&range, (y, y))?;
+                return self.substitute(first, last, &argument);
+            }
+            "norm" | "normal" => {
+                if argument.is_empty() {
+                    return Err("Argument required".to_string());
+                }
+                let (first, last) = self.command_lines(&range, (y, y))?;
+                self.change_mode(EditorMode::Normal);
+                self.run_normal(first, last, &argument);
+                return Ok(());
+            }
+            _ if !range.is_empty() => return Err("No range allowed".to_string()),
+            _ => (),
+        }
+
+        let argument = Some(argument).filter(|argument| !argument.is_empty());
+        match name.as_str() {
+            "sp" | "split" => self.split_window(SplitDirection::Horizontal, argument.as_deref()),
+            "vs" | "vsplit" => self.split_window(SplitDirection::Vertical, argument.as_deref()),
+            "q" | "quit" => {
+                if !self.close_window() {
+                    self.quit();
+                }
+                self.change_mode(EditorMode::Normal);
+            }
+            "clo" | "close" => {
+                if !self.close_window() {
+                    return Err("Cannot close last window".to_string());
+                }
+                self.change_mode(EditorMode::Normal);
+            }
+            "on" | "only" => {
+                self.only_window();
+                self.change_mode(EditorMode::Normal);
+            }
+            "w" | "write" => {
+                self.save_buffer();
+            }
+            "wq" => {
+                if self.save_buffer() {
+                    self.quit();
+                }
+            }
+            "bn" | "bnext" => {
+                self.cycle_buffer(1);
+                self.change_mode(EditorMode::Normal);
+            }
+            "bp" | "bprevious" => {
+                self.cycle_buffer(-1);
+                self.change_mode(EditorMode::Normal);
+            }
+            "ls" | "buffers" => self.list_buffers(),
+            "reg" | "registers" => self.list_registers(),
+            "marks" => self.list_marks(),
+            "help" => self.print_help(),
+            "debug" => self.toggle_debug_bar(),
+            "e" | "edit" => {
+                let path = argument.ok_or_else(|| "Argument required".to_string())?;
+                self.edit_file(&path);
+            }
+            "b" | "buffer" => {
+                let number = argument.unwrap_or_default();
+                match number.parse::<usize>() {
+                    Ok(n) if (1..=self.buffers.len()).contains(&n) => self.switch_buffer(n - 1),
+                    _ => return Err(format!("No buffer {}", number)),
+                }
+                self.change_mode(EditorMode::Normal);
+            }
+            "set" => {
+                let option = argument.unwrap_or_default();
+                self.set_option(&option);
+            }
+            _ => return Err(format!("Not an editor command: {}", text.trim())),
+        }
+        Ok(())
+    }
+
+    /// Line number (counting from 1) of an address of an ex command. 0 is
+    /// before the first line.
+    fn resolve_address(&self, line: &LineAddress) -> Result<usize, String> {
+        let buffer = self.buffer();
+        let total_lines = buffer.get_total_lines();
+        let y = self.offset_y + self.cursor_position.y;
+        let number = match &line.address {
+            Address::Number(number) => *number,
+            Address::Current => y + 1,
+            Address::Last => total_lines,
+            Address::Mark(name) => match self.mark_position(*name) {
+                Some(position) => position.y + 1,
+                None => return Err(format!("Mark not set: {}", name)),
+            },
+            Address::Pattern { pattern, forward } => {
+                let regex = regex::search_pattern(pattern, self.case_options)?;
+                // the search starts after the cursor line and wraps around
+                let found = (1..=total_lines)
+                    .map(|i| match forward {
+                        true => (y + i) % total_lines,
+                        false => (y + total_lines - i) % total_lines,
+                    })
+                    .find(|y| regex.find_at(&buffer.get_line(*y), 0).is_some());
+                match found {
+                    Some(y) => y + 1,
+                    None => return Err(format!("Pattern not found: {}", pattern)),
+                }
+            }
+        };
+        number
+            .checked_add_signed(line.offset)
+            .ok_or_else(|| "Invalid range".to_string())
+    }
+
+    /// Lines `first..=last` of the range of an ex command, or `default`
+    /// without a range. A backwards range is swapped.
+    fn command_lines(
+        &self,
+        range: &[LineAddress],
+        default: (usize, usize),
+    ) -> Result<(usize, usize), String> {
+        let (first, last) = match range {
+            [] => return Ok(default),
+            [line] => {
+                let number = self.resolve_address(line)?;
+                (number, number)
+            }
+            [first, last, ..] => (self.resolve_address(first)?, self.resolve_address(last)?),
+        };
+        let total_lines = self.buffer().get_total_lines();
+        if first == 0 || last == 0 || first > total_lines || last > total_lines {
+            return Err("Invalid range".to_string());
+        }
+        Ok((
+            std::cmp::min(first, last) - 1,
+            std::cmp::max(first, last) - 1,
+        ))
+    }
+
+    /// Indent (`>`) or outdent (`<`) the lines once for every character of
+    /// `shift`, e.g. twice for `>>`.
+    fn shift_lines(&mut self, first: usize, last: usize, shift: &str) {
+        self.buffer_mut().begin_undo_group();
+        for y in first..=last {
+            let mut line = self.buffer().get_line(y);
+            for ch in shift.chars() {
+                line = match ch {
+                    '>' => operator::indent(&line),
+                    _ => operator::outdent(&line).to_string(),
+                };
+            }
+            self.replace_line_columns(y, &(0..usize::MAX), &line);
+        }
+        self.buffer_mut().end_undo_group();
+        self.move_to_line(last);
+    }
+
+    /// Move lines `first..=last` below line number `target` (`:m`), which
+    /// counts from 1. Moving them below 0 moves them to the top.
+    fn move_lines(&mut self, first: usize, last: usize, target: usize) -> Result<(), String> {
+        if (first + 1..=last).contains(&target) {
+            return Err("Cannot move a range of lines into itself".to_string());
+        }
+        let count = last - first + 1;
+        // directly above or below the lines, nothing changes
+        if target != first && target != last + 1 {
+            let text = self.range_text(&TextRange::Lines(first, last));
+            let y = if target > last {
+                target - count
+            } else {
+                target
+            };
+            self.buffer_mut().begin_undo_group();
+            self.delete_text(&TextRange::Lines(first, last), false);
+            self.insert_lines(y, &text);
+            self.buffer_mut().end_undo_group();
+            self.move_to_line(y + count - 1);
+        } else {
+            self.move_to_line(last);
+        }
+        Ok(())
+    }
+
+    /// Copy lines `first..=last` below line number `target` (`:t`).
+    fn copy_lines(&mut self, first: usize, last: usize, target: usize) {
+        let text = self.range_text(&TextRange::Lines(first, last));
+        self.buffer_mut().begin_undo_group();
+        self.insert_lines(target, &text);
+        self.buffer_mut().end_undo_group();
+        self.move_to_line(target + last - first);
+    }
+
+    /// Join lines `first..=last` into one line (`:j`). Leading white space of
+    /// the joined lines is replaced by a space, unless `keep_spaces` (`:j!`)
+    /// keeps the lines as they are.
+    fn join_lines(&mut self, first: usize, last: usize, keep_spaces: bool) {
+        if first >= last {
+            return;
+        }
+        self.buffer_mut().begin_undo_group();
+        let mut join = Position::new(0, first);
+        for _ in first..last {
+            let buffer = self.buffer();
+            let line = buffer.get_line(first);
+            let next = buffer.get_line(first + 1);
+            join = Position::new(buffer.get_line_length(first), first);
+            let Some(start) = buffer.get_offset_from_position(&join) else {
+                break;
+            };
+            let next_start = buffer
+                .line_range(first + 1)
+                .map_or(start, |range| range.start);
+            let (removed, separator) = match keep_spaces {
+                true => (0, ""),
+                false => {
+                    let trimmed = next.trim_start();
+                    let separator = match trimmed.is_empty()
+                        || trimmed.starts_with(')')
+                        || line.ends_with(char::is_whitespace)
+                        || line.is_empty()
+                    {
+                        true => "",
+                        false => " ",
+                    };
+                    (next.len() - trimmed.len(), separator)
+                }
+            };
+            self.buffer_mut().delete_range(start..next_start + removed);
+            self.buffer_mut().insert_at(separator, start);
+        }
+        self.buffer_mut().end_undo_group();
+        self.move_to_position(join);
+    }
+
+    /// Replace matches of a pattern in lines `first..=last`
+    /// (`:s/pattern/replacement/[flags]`). With the `c` flag, every match is
+    /// highlighted and the user is asked whether to replace it. All
+    /// replacements are undone together.
+    fn substitute(&mut self, first: usize, last: usize, argument: &str) -> Result<(), String> {
+        let substitute = substitute::parse(argument)?;
+        let pattern = match substitute.pattern.is_empty() {
+            true => self
+                .last_pattern
+                .clone()
+                .ok_or_else(|| "No previous regular expression".to_string())?,
+            false => substitute.pattern,
+        };
+        // the `i` and `I` flags override the options
+        let case_options = match substitute.ignore_case {
+            Some(ignore_case) => CaseOptions {
+                ignore_case,
+                smart_case: false,
+            },
+            None => self.case_options,
+        };
+        let regex = regex::search_pattern(&pattern, case_options)?;
+        self.last_pattern = Some(pattern.clone());
+
+        let buffer = self.buffer();
+        let end = Position::new(buffer.get_line_length(last), last);
+        let mut substitution = Substitution {
+            pattern,
+            regex,
+            replacement: substitute.replacement,
+            global: substitute.global,
+            position: buffer.line_range(first).map_or(0, |range| range.start),
+            end: buffer
+                .get_offset_from_position(&end)
+                .unwrap_or(buffer.len()),
+            pending: None,
+            count: 0,
+            lines: 0,
+            last: None,
+        };
+        self.buffer_mut().begin_undo_group();
+        if substitute.confirm {
+            self.ask_substitution(substitution);
+        } else {
+            self.replace_all_matches(&mut substitution);
+            self.finish_substitution(substitution);
+        }
+        Ok(())
+    }
+
+    /// Replace the remaining matches of a substitution. The matches are
+    /// replaced from the last one, so the offsets of the others stay valid.
+    fn replace_all_matches(&mut self, substitution: &mut Substitution) {
+        let buffer = self.buffer();
+        let text = buffer.get(&Position::new(0, 0), None);
+        let mut replacements = vec![];
+        // offsets move by the length changes of the replacements before them
+        let mut moved: isize = 0;
+        while let Some(captures) = substitution.next_match(&text) {
+            let range = captures.range();
+            let replacement = substitute::expand(&substitution.replacement, &text, &captures);
+            let y = buffer.get_position_from_offset(range.start).y;
+            substitution.count_replacement(y, range.start.saturating_add_signed(moved));
+            substitution.position = substitution.next_position(&text, &range);
+            moved += replacement.len() as isize - range.len() as isize;
+            replacements.push((range, replacement));
+        }
+        for (range, replacement) in replacements.into_iter().rev() {
+            self.buffer_mut().delete_range(range.clone());
+            self.buffer_mut().insert_at(&replacement, range.start);
+        }
+    }
+
+    /// Show the next match of a substitution and ask whether to replace it,
+    /// or finish the substitution if there are no more matches.
+    fn ask_substitution(&mut self, mut substitution: Substitution) {
+        let text = self.buffer().get(&Position::new(0, 0), None);
+        let Some(captures) = substitution.next_match(&text) else {
+            self.finish_substitution(substitution);
+            return;
+        };
+        let position = self
+            .buffer()
+            .get_position_from_offset(captures.range().start);
+        self.move_to_position(position);
+        self.change_mode(EditorMode::Prompt);
+        self.command = format!("replace with {} (y/n/a/q/l)?", substitution.replacement);
+        substitution.pending = Some(captures);
+        self.prompt = Some(Prompt::Substitute(Box::new(substitution)));
+    }
+
+    /// Answer for a match of `:s` with `c`: `y` replaces it, `n` skips it,
+    /// `a` replaces it and all following matches, `l` replaces it and stops,
+    /// `q` or Esc stop.
+    fn handle_key_substitute_prompt(&mut self, key: Key, mut substitution: Substitution) {
+        let Some(captures) = substitution.pending.take() else {
+            self.finish_substitution(substitution);
+            return;
+        };
+        let text = self.buffer().get(&Position::new(0, 0), None);
+        let range = captures.range();
+        let next = substitution.next_position(&text, &range);
+        match key {
+            Key::Char('y' | 'a' | 'l') => {
+                let replacement = substitute::expand(&substitution.replacement, &text, &captures);
+                let y = self.buffer().get_position_from_offset(range.start).y;
+                substitution.count_replacement(y, range.start);
+                self.buffer_mut().delete_range(range.clone());
+                self.buffer_mut().insert_at(&replacement, range.start);
+
+                let moved = replacement.len() as isize - range.len() as isize;
+                substitution.position = next.saturating_add_signed(moved);
+                substitution.end = match substitution.end >= range.end {
+                    true => substitution.end.saturating_add_signed(moved),
+                    false => range.start + replacement.len(),
+                };
+                match key {
+                    Key::Char('a') => {
+                        self.replace_all_matches(&mut substitution);
+                        self.finish_substitution(substitution);
+                    }
+                    Key::Char('l') => self.finish_substitution(substitution),
+                    _ => self.ask_substitution(substitution),
+                }
+            }
+            Key::Char('n') => {
+                substitution.position = next;
+                self.ask_substitution(substitution);
+            }
+            Key::Char('q') | Key::Esc => self.finish_substitution(substitution),
+            _ => {
+                substitution.pending = Some(captures);
+                self.prompt = Some(Prompt::Substitute(Box::new(substitution)));
+            }
+        }
+    }
+
+    /// End the undo step of a substitution, move to the line of the last
+    /// replacement and report the number of replacements.
+    fn finish_substitution(&mut self, substitution: Substitution) {
+        self.buffer_mut().end_undo_group();
+        self.change_mode(EditorMode::Normal);
+        self.command = match substitution.last {
+            Some((_, offset)) => {
+                let y = self.buffer().get_position_from_offset(offset).y;
+                self.move_to_line(y);
+                let plural = |count: usize| if count == 1 { "" } else { "s" };
+                format!(
+                    "{} substitution{} on {} line{}",
+                    substitution.count,
+                    plural(substitution.count),
+                    substitution.lines,
+                    plural(substitution.lines)
+                )
+            }
+            None => format!("Pattern not found: {}", substitution.pattern),
+        };
+        self.retain_command_text = true;
+    }
+
+    /// Type the keys in Normal mode on each line `first..=last` (`:normal`),
+    /// starting at the first column. An unfinished command, or Insert mode,
+    /// ends after the keys.
+    fn run_normal(&mut self, first: usize, mut last: usize, keys: &str) {
+        // `:normal` typing itself stops at some point
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            return;
+        }
+        let keys = key_notation::from_text(keys);
+        self.macro_depth += 1;
+        let mut y = first;
+        while y <= last && y < self.buffer().get_total_lines() {
+            let total_lines = self.buffer().get_total_lines();
+            self.move_to_position(Position::new(0, y));
+            for key in keys.iter() {
+                self.handle_key(*key);
+            }
+            self.handle_key(Key::Esc);
+            // the next line moves with lines added or deleted by the keys
+            let added = self.buffer().get_total_lines() as isize - total_lines as isize;
+            y = (y + 1).saturating_add_signed(added);
+            last = match last.checked_add_signed(added) {
+                Some(last) => last,
+                None => break,
+            };
+        }
+        self.macro_depth -= 1;
+    }
+
+    /// Set an editor option, given as `name=value`.
+    fn set_option(&mut self, option: &str) {
+        let flag = match option.trim() {
+            "ignorecase" | "ic" => Some(("ignorecase", &mut self.case_options.ignore_case, true)),
+            "noignorecase" | "noic" => {
+                Some(("ignorecase", &mut self.case_options.ignore_case, false))
+            }
+            "smartcase" | "scs" => Some(("smartcase", &mut self.case_options.smart_case, true)),
+            "nosmartcase" | "noscs" => {
+                Some(("smartcase", &mut self.case_options.smart_case, false))
+            }
+            _ => None,
+        };
+        if let Some((name, flag, value)) = flag {
+            *flag = value;
+            self.command = format!("-- {}{}", if value { "" } else { "no" }, name);
+            self.retain_command_text = true;
+            self.change_mode(EditorMode::Normal);
+            return;
+        }
+
+        self.command = match option.trim().split_once('=') {
+            Some(("fileformat" | "ff", value)) => match FileFormat::from_name(value) {
+                Some(file_format) => {
+                    self.buffer_mut().set_file_format(file_format);
+                    format!("-- fileformat={}", file_format.as_str())
+                }
+                None => format!("Invalid fileformat: {}", value),
+            },
+            Some(("clipboardcmd", value)) => {
+                self.clipboard.read_command = Some(value.to_string()).filter(|v| !v.is_empty());
+                format!("-- clipboardcmd={}", value)
+            }
+            _ => format!("Unknown option: {}", option),
+        };
+        self.retain_command_text = true;
+        self.change_mode(EditorMode::Normal);
+    }
+
+    fn quit(&mut self) {
+        for entry in self.buffers.iter() {
+            entry.buffer.remove_swap_file().ok();
+        }
+        self.write_file_marks();
+        self.terminal.clear();
+        self.running = false;
+    }
+
+    /// Save the buffer and report the result in the command line. If the file
+    /// was changed by another program, asks what to do instead.
+    /// Returns true if the file was written.
+    fn save_buffer(&mut self) -> bool {
+        if self.buffer().file_path().is_none() {
+            self.command = "-- Create file:".to_string();
+            self.change_mode(EditorMode::Command);
+            return false;
+        }
+
+        match self.buffer_mut().disk_changed() {
+            Ok(None) => self.write_buffer(),
+            Ok(Some(disk_state)) => {
+                self.ask_file_changed(true, disk_state);
+                false
+            }
+            Err(e) => {
+                self.command = format!("Error checking file: {}", e);
+                self.retain_command_text = true;
+                self.change_mode(EditorMode::Normal);
+                false
+            }
+        }
+    }
+
+    /// Save the buffer, without checking for changes on disk.
+    fn write_buffer(&mut self) -> bool {
+        match self.buffer_mut().save_file() {
+            Ok(file_path) => {
+                self.command = format!("-- File saved to {}.", file_path);
+                true
+            }
+            Err(e) => {
+                self.command = format!("Error saving file: {}", e);
+                self.retain_command_text = true;
+                self.change_mode(EditorMode::Normal);
+                false
+            }
+        }
+    }
+
+    fn handle_key_normal_mode(&mut self, key: char) {
+        self.motion_acc.push(key);
+        let (register, count) = match normal_command::parse(&self.motion_acc) {
+            NormalCommand::Pending => return,
+            NormalCommand::Key {
+                count,
+                key,
+                argument: Some(argument),
+                ..
+            } => {
+                self.motion_acc.clear();
+                match key {
+                    'q' => self.start_recording(argument),
+                    '@' => self.play_macro(argument, count.unwrap_or(1)),
+                    'm' => self.set_mark(argument),
+                    '\'' | '`' => self.jump_to_mark(argument, key == '\''),
+                    'g' => {
+                        if let Some(y) = self.line_motion(key, Some(argument), count) {
+                            self.record_jump();
+                            self.move_to_line(y);
+                        } else if let Some((motion, big)) = WordMotion::from_key(argument, true) {
+                            self.move_by_word(motion, big, count.unwrap_or(1));
+                        }
+                    }
+                    _ => {
+                        if let Some((search, ch, repeat)) = self.char_search(key, Some(argument)) {
+                            self.find_char(search, ch, count.unwrap_or(1), repeat);
+                        }
+                    }
+                }
+                return;
+            }
+            NormalCommand::Key {
+                register, count, ..
+            } => (register.unwrap_or(register::UNNAMED), count),
+            NormalCommand::Operator {
+                register,
+                operator,
+                count,
+                motion,
+                argument,
+            } => {
+                self.motion_acc.clear();
+                let register = register.unwrap_or(register::UNNAMED);
+                if let Some(range) = self.motion_range(operator, motion, argument, count) {
+                    self.apply_operator(operator, range, register);
+                    if operator != Operator::Yank {
+                        self.record_change(Change::Operator {
+                            register,
+                            operator,
+                            count,
+                            motion,
+                            argument,
+                        });
+                    }
+                }
+                return;
+            }
+            NormalCommand::Invalid => {
+                self.motion_acc.clear();
+                return;
+            }
+        };
+        self.motion_acc.clear();
+        let times = count.unwrap_or(1);
+
+        match key {
+            'i' => {
+                self.change_mode(EditorMode::Insert);
+                self.record_change(Change::Insert('i'));
+            }
+            'k' => self.move_up(times),
+            'j' => self.move_down(times),
+            'h' => self.move_left(times),
+            'l' => self.move_right(times),
+            '0' => self.move_to_sol(),
+            '$' => self.move_to_eol(),
+            'w' | 'W' | 'b' | 'B' | 'e' | 'E' => {
+                if let Some((motion, big)) = WordMotion::from_key(key, false) {
+                    self.move_by_word(motion, big, times);
+                }
+            }
+            'G' | 'H' | 'M' | 'L' => {
+                if let Some(y) = self.line_motion(key, None, count) {
+                    self.record_jump();
+                    self.move_to_line(y);
+                }
+            }
+            '{' | '}' | '(' | ')' | '%' => {
+                if let Some(offset) = self.block_motion(key, times) {
+                    self.record_jump();
+                    let position = self.buffer().get_position_from_offset(offset);
+                    self.move_to_position(position);
+                }
+            }
+            'A' => {
+                self.move_to_eol();
+                self.change_mode(EditorMode::Insert);
+                self.record_change(Change::Insert('A'));
+            }
+            '/' => {
+                self.change_mode(EditorMode::Command);
+                self.command.push('/');
+            }
+            'n' => self.search_next(),
+            'N' => self.search_previous(),
+            'u' => self.undo(),
+            'v' => self.start_visual(EditorMode::Visual),
+            'V' => self.start_visual(EditorMode::VisualLine),
+            'p' | 'P' => {
+                let after = key == 'p';
+                self.put(register, times, after);
+                self.record_change(Change::Put {
+                    register,
+                    count: times,
+                    after,
+                });
+            }
+            '.' => self.repeat_change(count),
+            // Ctrl-i
+            '\t' => self.jump_newer(),
+            ';' | ',' => {
+                if let Some((search, ch, repeat)) = self.char_search(key, None) {
+                    self.find_char(search, ch, times, repeat);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Start recording the keys typed into a register (`q{register}`).
+    fn start_recording(&mut self, register: char) {
+        if !register.is_ascii_alphabetic() {
+            self.command = format!("Invalid register: {}", register);
+            return;
+        }
+        self.recording = Some((register, vec![]));
+    }
+
+    /// Store the recorded keys, without the `q` which ended the recording.
+    fn stop_recording(&mut self) {
+        let Some((register, mut keys)) = self.recording.take() else {
+            return;
+        };
+        keys.pop();
+        self.registers.set_named(
+            register,
+            Register {
+                text: key_notation::to_text(&keys),
+                kind: RegisterKind::Chars,
+            },
+        );
+    }
+
+    /// Play the keys in a register `count` times. `@` plays the register
+    /// played last.
+    fn play_macro(&mut self, register: char, count: usize) {
+        let register = match register {
+            '@' => match self.last_macro {
+                Some(register) => register,
+                None => {
+                    self.command = "No previous macro".to_string();
+                    return;
+                }
+            },
+            _ => register,
+        };
+        let Some(contents) = self.registers.get(register) else {
+            self.command = format!("Nothing in register {}", register);
+            return;
+        };
+        // a macro playing itself stops at some point
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            return;
+        }
+
+        let keys = key_notation::from_text(&contents.text);
+        self.last_macro = Some(register);
+        self.macro_depth += 1;
+        for _ in 0..count {
+            for key in keys.iter() {
+                self.handle_key(*key);
+            }
+        }
+        self.macro_depth -= 1;
+    }
+
+    /// Remember a change for `.`. A change, which started Insert mode, is
+    /// complete when Insert mode ends.
+    fn record_change(&mut self, change: Change) {
+        let change = RepeatableChange {
+            change,
+            inserted: vec![],
+        };
+        match self.mode {
+            EditorMode::Insert => self.pending_change = Some(change),
+            _ => self.last_change = Some(change),
+        }
+    }
+
+    /// Repeat the last change, including the keys typed in Insert mode after
+    /// it. A count replaces the count of the change.
+    fn repeat_change(&mut self, count: Option<usize>) {
+        let Some(RepeatableChange { change, inserted }) = self.last_change.clone() else {
+            return;
+        };
+
+        // a count repeats the text of `i` and `A`
+        let mut insert_times = 1;
+        match change {
+            Change::Operator {
+                register,
+                operator,
+                count: change_count,
+                motion,
+                argument,
+            } => {
+                let count = count.or(change_count);
+                let Some(range) = self.motion_range(operator, motion, argument, count) else {
+                    return;
+                };
+                self.apply_operator(operator, range, register);
+            }
+            Change::Put {
+                register,
+                count: change_count,
+                after,
+            } => self.put(register, count.unwrap_or(change_count), after),
+            Change::Insert(key) => {
+                if key == 'A' {
+                    self.move_to_eol();
+                }
+                self.change_mode(EditorMode::Insert);
+                insert_times = count.unwrap_or(1);
+            }
+        }
+
+        if self.mode == EditorMode::Insert {
+            for _ in 0..insert_times {
+                for key in inserted.iter() {
+                    self.handle_key_insert_mode(*key);
+                }
+            }
+            self.change_mode(EditorMode::Normal);
+        }
+    }
+
+    /// Text from the cursor to where the motion moves it, for an operator.
+    /// The motions `j`, `k`, `G`, `gg`, `H`, `M`, `L` and `'{mark}`, or the
+    /// operator key itself, select whole lines. `i` and `a` with an argument
+    /// select a text object.
+    fn motion_range(
+        &mut self,
+        operator: Operator,
+        motion: char,
+        argument: Option<char>,
+        count: Option<usize>,
+    ) -> Option<TextRange> {
+        if let (Some(object), 'i' | 'a') = (argument, motion) {
+            return self.text_object(object, motion == 'a');
+        }
+        let y = self.offset_y + self.cursor_position.y;
+        let target = match (motion, argument) {
+            ('\'', Some(name)) => Some(self.mark_position(name)?.y),
+            _ => self.line_motion(motion, argument, count),
+        };
+        if let Some(target) = target {
+            return Some(TextRange::Lines(
+                std::cmp::min(y, target),
+                std::cmp::max(y, target),
+            ));
+        }
+        let count = count.unwrap_or(1);
+        let last_line = self.buffer().get_total_lines().saturating_sub(1);
+        match motion {
+            'j' => return Some(TextRange::Lines(y, std::cmp::min(y + count, last_line))),
+            'k' => return Some(TextRange::Lines(y.saturating_sub(count), y)),
+            _ if Operator::from_key(motion) == Some(operator) => {
+                return Some(TextRange::Lines(y, std::cmp::min(y + count - 1, last_line)))
+            }
+            _ => (),
+        }
+
+        // run the motion and go back
+        let saved = (
+            self.offset_y,
+            self.cursor_position,
+            self.current_line_length,
+        );
+        let from = self.visual_cursor();
+        // the motion includes the character it moves onto
+        let mut inclusive = false;
+        match motion {
+            'h' => self.move_left(count),
+            'l' => self.move_right(count),
+            'w' | 'W' | 'b' | 'B' | 'e' | 'E' | 'g' => {
+                let (word_motion, big) = match argument {
+                    Some(argument) => WordMotion::from_key(argument, true)?,
+                    None => WordMotion::from_key(motion, false)?,
+                };
+                self.move_by_word(word_motion, big, count);
+                inclusive = word_motion.is_inclusive();
+            }
+            '0' => self.move_to_sol(),
+            '$' => self.move_to_eol(),
+            '{' | '}' | '(' | ')' | '%' => {
+                let offset = self.block_motion(motion, count)?;
+                let position = self.buffer().get_position_from_offset(offset);
+                self.move_to_position(position);
+                inclusive = motion == '%';
+            }
+            '`' => {
+                let position = self.mark_position(argument?)?;
+                self.move_to_position(position);
+            }
+            'f' | 'F' | 't' | 'T' | ';' | ',' => {
+                let (search, ch, repeat) = self.char_search(motion, argument)?;
+                if !self.find_char(search, ch, count, repeat) {
+                    return None;
+                }
+                inclusive = matches!(search, 'f' | 't');
+            }
+            _ => return None,
+        }
+        let mut to = self.visual_cursor();
+        (
+            self.offset_y,
+            self.cursor_position,
+            self.current_line_length,
+        ) = saved;
+
+        let buffer = self.buffer();
+        if matches!(motion, 'w' | 'W') && to.y > from.y {
+            // like vim, the last word of a line ends at the line end, not at
+            // the next word of the following line
+            to = Position::new(buffer.get_line_length(to.y - 1), to.y - 1);
+        }
+        let from = buffer.get_offset_from_position(&from)?;
+        let to = buffer.get_offset_from_position(&to)?;
+        let mut range = std::cmp::min(from, to)..std::cmp::max(from, to);
+        if inclusive {
+            range.end += buffer
+                .chars(range.end)
+                .next()
+                .filter(|ch| *ch != '\r' && *ch != '\n')
+                .map_or(0, char::len_utf8);
+        }
+        if operator == Operator::Change && matches!(motion, 'w' | 'W') {
+            // like vim, `cw` keeps the white space after the word
+            let text = buffer.get_range(range.clone());
+            if !text.starts_with(char::is_whitespace) {
+                range.end = range.start + text.trim_end().len();
+            }
+        }
+        match range.is_empty() {
+            true => None,
+            false => Some(TextRange::Chars(range)),
+        }
+    }
+
+    /// Character search (`f`, `F`, `t` or `T`) and its character for a
+    /// motion, and whether it repeats the last search. `;` repeats the last
+    /// search, `,` repeats it in the other direction.
+    fn char_search(&mut self, motion: char, argument: Option<char>) -> Option<(char, char, bool)> {
+        match (motion, argument) {
+            ('f' | 'F' | 't' | 'T', Some(ch)) => {
+                self.last_char_search = Some((motion, ch));
+                Some((motion, ch, false))
+            }
+            (';', _) => self.last_char_search.map(|(search, ch)| (search, ch, true)),
+            (',', _) => self.last_char_search.map(|(search, ch)| {
+                let reverse = match search {
+                    'f' => 'F',
+                    'F' => 'f',
+                    't' => 'T',
+                    _ => 't',
+                };
+                (reverse, ch, true)
+            }),
+            _ => None,
+        }
+    }
+
+    /// Move to the `count`th occurrence of a character in the cursor line:
+    /// onto it (`f`, forward and `F`, backward), or next to it (`t` and `T`).
+    /// Returns false without moving, if there are not enough occurrences.
+    fn find_char(&mut self, search: char, ch: char, count: usize, repeat: bool) -> bool {
+        let position = self.visual_cursor();
+        let line = self.buffer().get_line(position.y);
+        let line: Vec<&str> = unicode::graphemes(&line).collect();
+        let forward = matches!(search, 'f' | 't');
+        let till = matches!(search, 't' | 'T');
+        // a repeated `t` does not stop before the same character again
+        let skip = usize::from(till && repeat);
+
+        // a character matches with its combining marks
+        let matches = line
+            .iter()
+            .enumerate()
+            .filter(|(_, found)| found.starts_with(ch))
+            .map(|(x, _)| x);
+        let found = match forward {
+            true => matches.filter(|x| *x > position.x + skip).nth(count - 1),
+            false => matches
+                .rev()
+                .filter(|x| *x + skip < position.x)
+                .nth(count - 1),
+        };
+        let Some(x) = found else {
+            return false;
+        };
+        self.cursor_position.x = match (forward, till) {
+            (true, true) => x - 1,
+            (false, true) => x + 1,
+            _ => x,
+        };
+        true
+    }
+
+    fn handle_key_visual_mode(&mut self, key: char) {
+        // `"x` selects the register for the operator
+        if key == '"' || self.motion_acc.ends_with('"') {
+            self.motion_acc.push(key);
+            return;
+        }
+        let keys = &self.motion_acc;
+        let pending = match keys[..keys.len().saturating_sub(1)].ends_with('"') {
+            true => None,
+            false => keys.chars().last(),
+        };
+        match pending {
+            Some('i' | 'a') => {
+                let around = pending == Some('a');
+                self.motion_acc.clear();
+                self.select_text_object(key, around);
+                return;
+            }
+            Some('f' | 'F' | 't' | 'T' | 'g' | 'm' | '\'' | '`') => {
+                self.handle_key_normal_mode(key);
+                return;
+            }
+            _ => (),
+        }
+        if normal_command::motion_needs_argument(key) || key == 'm' {
+            self.motion_acc.push(key);
+            return;
+        }
+        if let Some(operator) = Operator::from_visual_key(key) {
+            let register = normal_command::register(&self.motion_acc);
+            self.motion_acc.clear();
+            let range = self.visual_range();
+            self.apply_operator(operator, range, register.unwrap_or(register::UNNAMED));
+            return;
         }
 
-        let tokens: Vec<&str> = self.command.split(':').collect();
-        if tokens.len() <= 1 {
-            return Ok(());
+        match key {
+            'v' => self.start_visual(EditorMode::Visual),
+            'V' => self.start_visual(EditorMode::VisualLine),
+            'o' => {
+                let cursor = self.visual_cursor();
+                let anchor = std::mem::replace(&mut self.visual_anchor, cursor);
+                self.move_to_position(anchor);
+            }
+            '0'..='9'
+            | 'h'
+            | 'j'
+            | 'k'
+            | 'l'
+            | 'w'
+            | 'W'
+            | 'b'
+            | 'B'
+            | 'e'
+            | 'E'
+            | '$'
+            | 'n'
+            | 'N'
+            | ';'
+            | ','
+            | 'G'
+            | 'H'
+            | 'M'
+            | 'L'
+            | '{'
+            | '}'
+            | '('
+            | ')'
+            | '%' => self.handle_key_normal_mode(key),
+            _ => {}
         }
+    }
 
-        let pre_command = tokens[0];
-        let command = tokens[1];
+    /// Text object `i{object}` or `a{object}` (with `around`) at the cursor.
+    fn text_object(&self, object: char, around: bool) -> Option<TextRange> {
+        let buffer = self.buffer();
+        let text = buffer.get(&Position::new(0, 0), None);
+        let offset = buffer.get_offset_from_position(&self.visual_cursor())?;
+        let strings = text_object::string_ranges(&text, &self.file_extension);
+        text_object::find(&text, offset, object, around, &strings)
+    }
 
-        match command {
-            "q" => self.quit(),
-            "w" => self.save_buffer()?,
-            "wq" => {
-                self.save_buffer()?;
-                self.quit();
+    /// Select a text object in Visual mode. Objects of whole lines (`ip`)
+    /// switch to linewise Visual mode, others to characterwise.
+    fn select_text_object(&mut self, object: char, around: bool) {
+        match self.text_object(object, around) {
+            Some(TextRange::Chars(range)) if !range.is_empty() => {
+                let buffer = self.buffer();
+                let last = buffer.get_range(range.clone()).chars().last();
+                let end = range.end - last.map_or(0, char::len_utf8);
+                let start = buffer.get_position_from_offset(range.start);
+                let end = buffer.get_position_from_offset(end);
+                self.visual_anchor = start;
+                self.move_to_position(end);
+                if self.mode != EditorMode::Visual {
+                    self.change_mode(EditorMode::Visual);
+                }
             }
-            "help" => self.print_help(),
-            "debug" => self.toggle_debug_bar(),
+            Some(TextRange::Lines(first, last)) => {
+                self.visual_anchor = Position::new(0, first);
+                self.move_to_position(Position::new(0, last));
+                if self.mode != EditorMode::VisualLine {
+                    self.change_mode(EditorMode::VisualLine);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn is_visual(&self) -> bool {
+        matches!(
+            self.mode,
+            EditorMode::Visual | EditorMode::VisualLine | EditorMode::VisualBlock
+        )
+    }
+
+    /// Enter a Visual mode, or switch between them. The key of the current
+    /// Visual mode ends it.
+    fn start_visual(&mut self, mode: EditorMode) {
+        if self.mode == mode {
+            self.change_mode(EditorMode::Normal);
+            return;
+        }
+        if !self.is_visual() {
+            self.visual_anchor = self.visual_cursor();
+        }
+        self.change_mode(mode);
+    }
+
+    /// Cursor position in the buffer, limited to the line length.
+    fn visual_cursor(&self) -> Position {
+        let position = self.adjusted_cursor_position();
+        Position::new(position.x, self.offset_y + position.y)
+    }
+
+    /// Start and end of the selection, both inclusive, in text order.
+    fn visual_bounds(&self) -> (Position, Position) {
+        let cursor = self.visual_cursor();
+        let anchor = self.visual_anchor;
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        }
+    }
+
+    /// Selected character columns of line `y`. The end is past the end of
+    /// the line, if the line break is selected as well.
+    fn visual_columns(&self, y: usize) -> Option<Range<usize>> {
+        let (start, end) = self.visual_bounds();
+        if y < start.y || y > end.y {
+            return None;
+        }
+        match self.mode {
+            EditorMode::Visual => {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x + 1 } else { usize::MAX };
+                Some(from..to)
+            }
+            EditorMode::VisualLine => Some(0..usize::MAX),
+            EditorMode::VisualBlock => {
+                let cursor = self.visual_cursor();
+                let left = std::cmp::min(cursor.x, self.visual_anchor.x);
+                let right = std::cmp::max(cursor.x, self.visual_anchor.x);
+                Some(left..right + 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Text selected in the current Visual mode.
+    fn visual_range(&self) -> TextRange {
+        let (start, end) = self.visual_bounds();
+        match self.mode {
+            EditorMode::VisualLine => TextRange::Lines(start.y, end.y),
+            EditorMode::VisualBlock => TextRange::Block {
+                first: start.y,
+                last: end.y,
+                columns: self.visual_columns(start.y).unwrap_or_default(),
+            },
             _ => {
-                if pre_command.contains("-- Create file") {
-                    let path = format!("{}/{}", env::current_dir()?.display(), tokens[1]);
-                    self.buffer.set_file_path(path);
-                    self.clear_command();
-                    self.save_buffer()?;
+                let buffer = self.buffer();
+                let from = buffer.get_offset_from_position(&start).unwrap_or(0);
+                // the selection includes the character under the cursor
+                let to = if end.x >= buffer.get_line_length(end.y) {
+                    buffer
+                        .line_range(end.y)
+                        .map_or(buffer.len(), |range| range.end)
                 } else {
-                    self.command = "Command not found!".to_string();
-                    self.retain_command_text = true;
-                    self.change_mode(EditorMode::Normal);
-                }
+                    buffer
+                        .get_offset_from_position(&Position::new(end.x + 1, end.y))
+                        .unwrap_or(buffer.len())
+                };
+                TextRange::Chars(from..to)
             }
         }
-        Ok(())
     }
 
-    fn quit(&mut self) {
-        self.terminal.clear();
-        self.running = false;
+    /// Byte range of characters `columns` in line `y`, limited to the line.
+    fn line_columns_range(&self, y: usize, columns: &Range<usize>) -> Range<usize> {
+        let buffer = self.buffer();
+        let length = buffer.get_line_length(y);
+        let start = std::cmp::min(columns.start, length);
+        let end = std::cmp::min(columns.end, length);
+        let start = buffer.get_offset_from_position(&Position::new(start, y));
+        let end = buffer.get_offset_from_position(&Position::new(end, y));
+        start.unwrap_or(0)..end.unwrap_or(0)
     }
 
-    fn save_buffer(&mut self) -> std::io::Result<()> {
-        if let Ok(file_path) = self.buffer.save_file() {
-            self.command = format!("-- File saved to {}.", file_path);
+    fn range_text(&self, range: &TextRange) -> String {
+        let buffer = self.buffer();
+        match range {
+            TextRange::Chars(range) => buffer.get_range(range.clone()),
+            TextRange::Lines(first, last) => (*first..=*last)
+                .map(|y| buffer.get_line(y) + "\r\n")
+                .collect(),
+            TextRange::Block {
+                first,
+                last,
+                columns,
+            } => (*first..=*last)
+                .map(|y| buffer.get_range(self.line_columns_range(y, columns)))
+                .collect::<Vec<String>>()
+                .join("\r\n"),
+        }
+    }
+
+    /// Position of the first character of a range. For lines, this is the
+    /// first non-blank character.
+    fn range_start(&self, range: &TextRange) -> Position {
+        match range {
+            TextRange::Chars(range) => self.buffer().get_position_from_offset(range.start),
+            TextRange::Lines(first, _) => {
+                let indent = unicode::graphemes(&self.buffer().get_line(*first))
+                    .take_while(|grapheme| grapheme.chars().all(char::is_whitespace))
+                    .count();
+                Position::new(indent, *first)
+            }
+            TextRange::Block { first, columns, .. } => Position::new(columns.start, *first),
+        }
+    }
+
+    /// Apply an operator to a range of text, as a single undo step. The
+    /// cursor moves to the start of the range. Deleted or yanked text is
+    /// stored in `register`.
+    fn apply_operator(&mut self, operator: Operator, range: TextRange, register: char) {
+        let start = self.range_start(&range);
+        if operator != Operator::Yank {
+            self.buffer_mut().begin_undo_group();
+        }
+
+        match operator {
+            Operator::Yank | Operator::Delete | Operator::Change => {
+                let text = Register {
+                    text: self.range_text(&range),
+                    kind: range.kind(),
+                };
+                if register == register::CLIPBOARD {
+                    self.terminal.copy_to_clipboard(&text.text);
+                    self.clipboard.copy(&text.text);
+                }
+                match operator {
+                    Operator::Yank => self.registers.yank(register, text),
+                    _ => self.registers.delete(register, text),
+                }
+                if operator != Operator::Yank {
+                    self.delete_text(&range, operator == Operator::Change);
+                }
+            }
+            Operator::Indent | Operator::Outdent => {
+                let (first, last) = match &range {
+                    TextRange::Chars(bytes) => (
+                        self.buffer().get_position_from_offset(bytes.start).y,
+                        self.buffer().get_position_from_offset(bytes.end).y,
+                    ),
+                    TextRange::Lines(first, last) => (*first, *last),
+                    TextRange::Block { first, last, .. } => (*first, *last),
+                };
+                for y in first..=last {
+                    let line = self.buffer().get_line(y);
+                    let shifted = match operator {
+                        Operator::Indent => operator::indent(&line),
+                        _ => operator::outdent(&line).to_string(),
+                    };
+                    self.replace_line_columns(y, &(0..usize::MAX), &shifted);
+                }
+            }
+            Operator::ToggleCase | Operator::Lowercase | Operator::Uppercase => match &range {
+                TextRange::Chars(bytes) => {
+                    let text = self.buffer().get_range(bytes.clone());
+                    let changed = operator.change_case(&text).unwrap_or(text);
+                    self.buffer_mut().delete_range(bytes.clone());
+                    self.buffer_mut().insert_at(&changed, bytes.start);
+                }
+                TextRange::Lines(first, last) => {
+                    for y in *first..=*last {
+                        let line = self.buffer().get_line(y);
+                        let changed = operator.change_case(&line).unwrap_or(line);
+                        self.replace_line_columns(y, &(0..usize::MAX), &changed);
+                    }
+                }
+                TextRange::Block {
+                    first,
+                    last,
+                    columns,
+                } => {
+                    for y in *first..=*last {
+                        let text = self.buffer().get_range(self.line_columns_range(y, columns));
+                        let changed = operator.change_case(&text).unwrap_or(text);
+                        self.replace_line_columns(y, columns, &changed);
+                    }
+                }
+            },
+        }
+
+        let last_line = self.buffer().get_total_lines().saturating_sub(1);
+        // the first line changed, or was deleted
+        let start = match range {
+            TextRange::Lines(first, _) => {
+                self.range_start(&TextRange::Lines(first.min(last_line), 0))
+            }
+            _ => start,
+        };
+        self.move_to_position(start);
+        if operator == Operator::Change {
+            if let TextRange::Block { first, last, .. } = range {
+                if last > first {
+                    self.block_insert = Some((start, last));
+                }
+            }
+            // Insert mode continues the undo group
+            self.change_mode(EditorMode::Insert);
         } else {
-            self.command = "-- Create file:".to_string();
-            self.change_mode(EditorMode::Command);
+            if operator != Operator::Yank {
+                self.buffer_mut().end_undo_group();
+            }
+            self.change_mode(EditorMode::Normal);
         }
-        Ok(())
     }
 
-    fn handle_key_normal_mode(&mut self, key: char) {
-        if key.is_numeric() {
-            self.motion_acc.push(key);
+    /// Put the text of a register `count` times after or before the cursor.
+    /// Lines are put below or above the cursor line.
+    fn put(&mut self, register: char, count: usize, after: bool) {
+        let contents = match register {
+            register::CLIPBOARD => match self.clipboard.paste() {
+                Ok(contents) => contents,
+                Err(error) => {
+                    self.command = format!("Cannot read the clipboard: {}", error);
+                    return;
+                }
+            },
+            _ => self.registers.get(register).cloned(),
+        };
+        let Some(Register { text, kind }) = contents else {
+            self.command = format!("Nothing in register {}", register);
             return;
-        }
+        };
+        let cursor = self.visual_cursor();
+        let line_length = self.buffer().get_line_length(cursor.y);
+        self.buffer_mut().begin_undo_group();
+        let position = match kind {
+            RegisterKind::Chars => {
+                let x = match after {
+                    true => std::cmp::min(cursor.x + 1, line_length),
+                    false => cursor.x,
+                };
+                let text = text.repeat(count);
+                let position = Position::new(x, cursor.y);
+                self.buffer_mut().insert(&text, &position);
+                // the cursor ends on the last character put
+                let offset = self.buffer().get_offset_from_position(&position);
+                let end = offset.unwrap_or(0) + text.len();
+                let mut end = self.buffer().get_position_from_offset(end);
+                end.x = end.x.saturating_sub(1);
+                end
+            }
+            RegisterKind::Lines => {
+                let y = if after { cursor.y + 1 } else { cursor.y };
+                self.insert_lines(y, &text.repeat(count));
+                self.range_start(&TextRange::Lines(y, y))
+            }
+            RegisterKind::Block => {
+                let x = match after && line_length > 0 {
+                    true => cursor.x + 1,
+                    false => cursor.x,
+                };
+                for (i, line) in text.split("\r\n").enumerate() {
+                    let y = cursor.y + i;
+                    if y >= self.buffer().get_total_lines() {
+                        let end = self.buffer().len();
+                        self.buffer_mut().insert_at("\r\n", end);
+                    }
+                    // lines shorter than the block are padded with spaces
+                    let length = self.buffer().get_line_length(y);
+                    let padding = " ".repeat(x.saturating_sub(length));
+                    let text = padding + &line.repeat(count);
+                    let position = Position::new(std::cmp::min(x, length), y);
+                    self.buffer_mut().insert(&text, &position);
+                }
+                Position::new(x, cursor.y)
+            }
+        };
+        self.buffer_mut().end_undo_group();
+        self.move_to_position(position);
+    }
 
-        let times = match self.motion_acc.parse::<usize>() {
-            Ok(t) => {
-                self.motion_acc.clear();
-                t
+    /// Insert lines, each ending with a line break, so the first of them
+    /// becomes line `y`.
+    fn insert_lines(&mut self, y: usize, text: &str) {
+        let mut text = text.to_string();
+        let offset = match self.buffer().line_range(y) {
+            Some(range) => range.start,
+            None => {
+                // below the last line, which has no line break
+                text.insert_str(0, "\r\n");
+                text.truncate(text.len() - 2);
+                self.buffer().len()
             }
-            _ => 1,
         };
+        self.buffer_mut().insert_at(&text, offset);
+    }
 
-        match key {
-            'i' => self.change_mode(EditorMode::Insert),
-            'k' => self.move_up(times),
-            'j' => self.move_down(times),
-            'h' => self.move_left(times),
-            'l' => self.move_right(times),
-            '0' => self.move_to_sol(),
-            '$' => self.move_to_eol(),
-            'w' => self.move_right_by_word(times),
-            'b' => self.move_left_by_word(times),
-            'A' => {
-                self.move_to_eol();
-                self.change_mode(EditorMode::Insert);
+    /// List the registers in the command area.
+    fn list_registers(&mut self) {
+        let width = self.terminal.size().0 as usize;
+        let mut lines = vec!["Type Name Content".to_string()];
+        for (name, register) in self.registers.list() {
+            let kind = match register.kind {
+                RegisterKind::Chars => 'c',
+                RegisterKind::Lines => 'l',
+                RegisterKind::Block => 'b',
+            };
+            let text = register.text.replace("\r\n", "^J");
+            let line = format!("  {}  \"{}   {}", kind, name, text);
+            lines.push(unicode::truncate(&line, width).to_string());
+        }
+        self.command = lines.join("\n");
+        self.retain_command_text = true;
+        self.change_mode(EditorMode::Normal);
+    }
+
+    /// List the marks of the current buffer and the file marks in the command
+    /// area.
+    fn list_marks(&mut self) {
+        let width = self.terminal.size().0 as usize;
+        let mut lines = vec!["Mark Line  Col File/Text".to_string()];
+        let buffer = self.buffer();
+        let mut marks: Vec<(char, Position, String)> = buffer
+            .marks()
+            .into_iter()
+            .map(|(name, position)| (name, position, buffer.get_line(position.y)))
+            .collect();
+        for entry in self.buffers.iter() {
+            if std::ptr::eq(&entry.buffer, buffer) {
+                continue;
             }
-            '/' => {
-                self.change_mode(EditorMode::Command);
-                self.command.push('/');
+            let path = entry.buffer.file_path().unwrap_or("[No Name]");
+            for (name, position) in entry.buffer.marks() {
+                if marks::is_file_mark(name) {
+                    marks.push((name, position, path.to_string()));
+                }
+            }
+        }
+        for (name, mark) in self.file_marks.iter() {
+            marks.push((*name, mark.position, mark.path.clone()));
+        }
+        marks.sort_by_key(|(name, _, _)| *name);
+
+        for (name, position, text) in marks {
+            let line = format!(
+                " {}  {:>5} {:>4} {}",
+                name,
+                position.y + 1,
+                position.x,
+                text.trim()
+            );
+            lines.push(unicode::truncate(&line, width).to_string());
+        }
+        self.command = lines.join("\n");
+        self.retain_command_text = true;
+        self.change_mode(EditorMode::Normal);
+    }
+
+    /// Replace characters `columns` of line `y` with the text.
+    fn replace_line_columns(&mut self, y: usize, columns: &Range<usize>, text: &str) {
+        let range = self.line_columns_range(y, columns);
+        if self.buffer().get_range(range.clone()) == text {
+            return;
+        }
+        self.buffer_mut().delete_range(range.clone());
+        self.buffer_mut().insert_at(text, range.start);
+    }
+
+    /// Delete a range of text. With `keep_line`, deleted lines leave one
+    /// empty line behind, to insert the replacement into.
+    fn delete_text(&mut self, range: &TextRange, keep_line: bool) {
+        match range {
+            TextRange::Chars(bytes) => self.buffer_mut().delete_range(bytes.clone()),
+            TextRange::Lines(first, last) => {
+                let buffer = self.buffer();
+                let line_end = |y: usize| {
+                    buffer
+                        .get_offset_from_position(&Position::new(buffer.get_line_length(y), y))
+                        .unwrap_or(0)
+                };
+                let mut start = buffer.line_range(*first).map_or(0, |range| range.start);
+                let mut end = buffer.line_range(*last).map_or(start, |range| range.end);
+                if keep_line {
+                    end = line_end(*last);
+                } else if *last + 1 >= buffer.get_total_lines() && *first > 0 {
+                    // the last line has no line break, remove the one before
+                    start = line_end(*first - 1);
+                }
+                self.buffer_mut().delete_range(start..end);
+            }
+            TextRange::Block {
+                first,
+                last,
+                columns,
+            } => {
+                for y in *first..=*last {
+                    let range = self.line_columns_range(y, columns);
+                    self.buffer_mut().delete_range(range);
+                }
+            }
+        }
+    }
+
+    /// Repeat the text inserted in the first line of a changed block in the
+    /// other lines of the block.
+    fn finish_block_insert(&mut self) {
+        let Some((start, last)) = self.block_insert.take() else {
+            return;
+        };
+        let cursor = self.buffer_position();
+        if cursor.y != start.y || cursor.x <= start.x {
+            return;
+        }
+        let text: String = unicode::graphemes(&self.buffer().get_line(start.y))
+            .skip(start.x)
+            .take(cursor.x - start.x)
+            .collect();
+        for y in start.y + 1..=last {
+            if self.buffer().get_line_length(y) >= start.x {
+                self.buffer_mut().insert(&text, &Position::new(start.x, y));
             }
-            'n' => self.search_next(),
-            'N' => self.search_previous(),
-            _ => {}
         }
     }
 
     fn clear_command(&mut self) {
         if !self.retain_command_text {
             self.command.clear();
-            self.retain_command_text = false;
         }
+        self.retain_command_text = false;
     }
 
     fn change_mode(&mut self, mode: EditorMode) {
         match mode {
             EditorMode::Insert => {
                 self.command = "-- INSERT --".to_string();
+                if self.mode != EditorMode::Insert {
+                    self.buffer_mut().begin_undo_group();
+                }
             }
             EditorMode::Normal => {
-                if self.mode == EditorMode::Command {
-                    self.clear_command();
-                } else if self.mode == EditorMode::Insert {
+                if self.mode != EditorMode::Normal {
                     self.clear_command();
                 }
+                if self.mode == EditorMode::Insert {
+                    self.finish_block_insert();
+                    self.buffer_mut().end_undo_group();
+                    if let Some(change) = self.pending_change.take() {
+                        self.last_change = Some(change);
+                    }
+                }
             }
+            EditorMode::Visual => self.command = "-- VISUAL --".to_string(),
+            EditorMode::VisualLine => self.command = "-- VISUAL LINE --".to_string(),
+            EditorMode::VisualBlock => self.command = "-- VISUAL BLOCK --".to_string(),
             EditorMode::Command => {
                 self.clear_command();
             }
+            EditorMode::Prompt => {
+                self.clear_command();
+                // answering the prompt returns to Normal mode
+                if self.mode == EditorMode::Insert {
+                    self.buffer_mut().end_undo_group();
+                }
+            }
         }
         self.mode = mode;
     }
@@ -438,7 +2757,7 @@ impl Editor {
                 self.cursor_position.y -= 1;
             }
             self.current_line_length = self
-                .buffer
+                .buffer()
                 .get_line_length(self.offset_y + self.cursor_position.y);
             repeat_times -= 1;
         }
@@ -453,7 +2772,7 @@ impl Editor {
                 self.cursor_position.y += 1;
             }
             self.current_line_length = self
-                .buffer
+                .buffer()
                 .get_line_length(self.offset_y + self.cursor_position.y);
             repeat_times -= 1;
         }
@@ -483,10 +2802,198 @@ impl Editor {
         }
     }
 
+    /// Line `G`, `gg`, `H`, `M` or `L` moves to. `[count]G` and `[count]gg`
+    /// move to line `count`, `G` without a count to the last line. `H` and `L`
+    /// move to the top and bottom line of the window (or `count` lines below
+    /// the top or above the bottom), `M` to its middle line.
+    fn line_motion(
+        &self,
+        key: char,
+        argument: Option<char>,
+        count: Option<usize>,
+    ) -> Option<usize> {
+        let last_line = self.buffer().get_total_lines().saturating_sub(1);
+        let top = self.offset_y;
+        let bottom = std::cmp::min(self.offset_y + self.draw_terminal_size().1, last_line);
+        let lines_inside = count.unwrap_or(1).saturating_sub(1);
+        let y = match (key, argument) {
+            ('G', None) => count.map_or(last_line, |n| n.saturating_sub(1)),
+            ('g', Some('g')) => lines_inside,
+            ('H', None) => std::cmp::min(top + lines_inside, bottom),
+            ('L', None) => std::cmp::max(bottom.saturating_sub(lines_inside), top),
+            ('M', None) => top + (bottom - top) / 2,
+            _ => return None,
+        };
+        Some(std::cmp::min(y, last_line))
+    }
+
+    /// Byte offset `{`, `}` (paragraphs), `(`, `)` (sentences) or `%` (the
+    /// matching bracket) moves to, repeated `count` times. Without more
+    /// paragraphs or sentences, they move to the start or end of the buffer.
+    /// In Rust and TOML files, `%` skips brackets in strings and comments.
+    fn block_motion(&self, key: char, count: usize) -> Option<usize> {
+        let buffer = self.buffer();
+        let cursor = self.motion_cursor();
+        let mut offset = buffer.get_offset_from_position(&cursor)?;
+        let forward = matches!(key, '}' | ')');
+        let end = match forward {
+            true => buffer.len(),
+            false => 0,
+        };
+        match key {
+            '{' | '}' => {
+                let total_lines = buffer.get_total_lines();
+                let is_empty = |y: usize| buffer.get_line_length(y) == 0;
+                let mut y = cursor.y;
+                for _ in 0..count {
+                    match motion::paragraph(y, total_lines, forward, is_empty) {
+                        Some(next) => y = next,
+                        None => return Some(end),
+                    }
+                }
+                buffer.get_offset_from_position(&Position::new(0, y))
+            }
+            '(' | ')' => {
+                let text = buffer.get(&Position::new(0, 0), None);
+                for _ in 0..count {
+                    match motion::sentence(&text, offset, forward) {
+                        Some(next) => offset = next,
+                        None => return Some(end),
+                    }
+                }
+                Some(offset)
+            }
+            '%' => {
+                let text = buffer.get(&Position::new(0, 0), None);
+                let mut skip = text_object::string_ranges(&text, &self.file_extension);
+                skip.extend(text_object::comment_ranges(&text, &self.file_extension));
+                motion::matching_bracket(&text, offset, &skip)
+            }
+            _ => None,
+        }
+    }
+
+    /// Move to the first non-blank character of line `y`, scrolling the
+    /// window if the line is not visible.
+    fn move_to_line(&mut self, y: usize) {
+        let last_line = self.buffer().get_total_lines().saturating_sub(1);
+        let y = std::cmp::min(y, last_line);
+        let position = self.range_start(&TextRange::Lines(y, y));
+        self.move_to_position(position);
+    }
+
+    /// Set a mark at the cursor (`m{name}`). A file mark (`A` to `Z`) is
+    /// removed from the file it was set in before.
+    fn set_mark(&mut self, name: char) {
+        if !marks::is_valid(name) {
+            self.command = format!("Invalid mark: {}", name);
+            return;
+        }
+        if marks::is_file_mark(name) {
+            self.file_marks.remove(&name);
+            for entry in self.buffers.iter_mut() {
+                entry.buffer.remove_mark(name);
+            }
+        }
+        let position = self.visual_cursor();
+        self.buffer_mut().set_mark(name, &position);
+    }
+
+    /// Position of a mark of the current buffer. `` ` `` is the same mark as
+    /// `'`, the position before the latest jump.
+    fn mark_position(&self, name: char) -> Option<Position> {
+        let name = if name == '`' { marks::LAST_JUMP } else { name };
+        self.buffer().mark(name)
+    }
+
+    /// Jump to a mark: to the first non-blank character of its line with
+    /// `'{name}` (`linewise`), or exactly to it with `` `{name} ``. A file
+    /// mark switches to its buffer, or opens its file.
+    fn jump_to_mark(&mut self, name: char, linewise: bool) {
+        let name = if name == '`' { marks::LAST_JUMP } else { name };
+        let index = match marks::is_file_mark(name) {
+            true => self
+                .buffers
+                .iter()
+                .position(|entry| entry.buffer.mark(name).is_some()),
+            false => Some(self.current_buffer),
+        };
+        let target = index.and_then(|index| Some((index, self.buffers[index].buffer.mark(name)?)));
+        let position = match (target, self.file_marks.get(&name)) {
+            (Some((index, position)), _) => {
+                self.record_jump();
+                if index != self.current_buffer {
+                    self.switch_buffer(index);
+                }
+                position
+            }
+            (None, Some(mark)) => {
+                let path = mark.path.clone();
+                self.record_jump();
+                self.edit_file(&path);
+                match self.buffer().mark(name) {
+                    Some(position) => position,
+                    None => return,
+                }
+            }
+            (None, None) => {
+                self.command = format!("Mark not set: {}", name);
+                return;
+            }
+        };
+        match linewise {
+            true => self.move_to_line(position.y),
+            false => self.move_to_position(position),
+        }
+    }
+
+    /// Remember the cursor before a large jump, for `Ctrl-o` and the mark `'`.
+    fn record_jump(&mut self) {
+        let position = self.visual_cursor();
+        self.buffer_mut().set_mark(marks::LAST_JUMP, &position);
+        self.jump_list.push(Jump {
+            buffer: self.current_buffer,
+            position,
+        });
+    }
+
+    /// Go back to the position before the previous jump (`Ctrl-o`).
+    fn jump_older(&mut self) {
+        let current = Jump {
+            buffer: self.current_buffer,
+            position: self.visual_cursor(),
+        };
+        match self.jump_list.back(current) {
+            Some(jump) => self.go_to_jump(jump),
+            None => self.command = "Already at oldest jump.".to_string(),
+        }
+    }
+
+    /// Go forward again after `Ctrl-o` (`Ctrl-i`).
+    fn jump_newer(&mut self) {
+        match self.jump_list.forward() {
+            Some(jump) => self.go_to_jump(jump),
+            None => self.command = "Already at newest jump.".to_string(),
+        }
+    }
+
+    fn go_to_jump(&mut self, jump: Jump) {
+        if jump.buffer >= self.buffers.len() {
+            return;
+        }
+        if jump.buffer != self.current_buffer {
+            self.switch_buffer(jump.buffer);
+        }
+        // the text may have changed since the jump
+        let last_line = self.buffer().get_total_lines().saturating_sub(1);
+        let y = std::cmp::min(jump.position.y, last_line);
+        self.move_to_position(Position::new(jump.position.x, y));
+    }
+
     fn move_page_up(&mut self) {
-        let height = self.terminal.size().1 - 3;
-        if self.offset_y > height as usize {
-            self.offset_y -= height as usize;
+        let height = self.draw_terminal_size().1 + 1;
+        if self.offset_y > height {
+            self.offset_y -= height;
         } else if self.offset_y == 0 {
             self.cursor_position = Position::default();
         } else {
@@ -494,114 +3001,79 @@ impl Editor {
         }
 
         self.current_line_length = self
-            .buffer
+            .buffer()
             .get_line_length(self.offset_y + self.cursor_position.y);
     }
 
     fn move_page_down(&mut self) {
-        let total_lines = self.buffer.get_total_lines();
-        let height = self.terminal.size().1 - 3;
-        if (self.offset_y + height as usize) < total_lines {
-            self.offset_y += height as usize;
+        let total_lines = self.buffer().get_total_lines();
+        let height = self.draw_terminal_size().1 + 1;
+        if (self.offset_y + height) < total_lines {
+            self.offset_y += height;
         } else {
             self.cursor_position = Position::new(0, total_lines - self.offset_y - 1);
         }
 
         self.current_line_length = self
-            .buffer
+            .buffer()
             .get_line_length(self.offset_y + self.cursor_position.y);
     }
 
-    fn move_right_by_word(&mut self, mut repeat_times: usize) {
-        while repeat_times > 0 {
-            let mut offset = self
-                .buffer
-                .get_offset_from_position(&Position::new(
-                    self.cursor_position.x,
-                    self.offset_y + self.cursor_position.y,
-                ))
-                .unwrap_or(0);
-
-            let buffer = self.buffer.get(&Position::new(0, 0), None);
-            let eval_type = |ch: char| -> u16 {
-                if ch.is_alphanumeric() {
-                    0
-                } else if ch == ' ' {
-                    1
-                } else if ch.is_ascii_punctuation() {
-                    2
-                } else {
-                    3
-                }
-            };
-
-            let mut ch = buffer.chars().nth(offset).unwrap();
-            let mut consecutive_type = eval_type(ch);
-            let mut init_type = consecutive_type;
+    /// Buffer position motions start from. Like in vim, a cursor after the end
+    /// of the line is on its last character.
+    fn motion_cursor(&self) -> Position {
+        let mut position = self.visual_cursor();
+        let line_length = self.buffer().get_line_length(position.y);
+        position.x = std::cmp::min(position.x, line_length.saturating_sub(1));
+        position
+    }
 
-            while init_type == consecutive_type {
-                self.move_right(1);
-                offset += 1;
-                ch = buffer.chars().nth(offset).unwrap();
-                consecutive_type = eval_type(ch);
-                if consecutive_type == 1 && init_type != consecutive_type {
-                    init_type = consecutive_type;
+    /// Move `count` words with `w`, `b`, `e` or `ge`, or WORDs (`big`) with
+    /// `W`, `B`, `E` or `gE`. Without more words, `w` moves to the end of the
+    /// buffer, `b` and `ge` to the start.
+    fn move_by_word(&mut self, motion: WordMotion, big: bool, count: usize) {
+        let buffer = self.buffer();
+        let Some(mut offset) = buffer.get_offset_from_position(&self.motion_cursor()) else {
+            return;
+        };
+        for _ in 0..count {
+            match word::find(buffer, offset, motion, big) {
+                Some(next) => offset = next,
+                None => {
+                    offset = match motion {
+                        WordMotion::Forward => buffer.len(),
+                        WordMotion::ForwardEnd => offset,
+                        WordMotion::Backward | WordMotion::BackwardEnd => 0,
+                    };
+                    break;
                 }
             }
-            repeat_times -= 1;
         }
+        let position = buffer.get_position_from_offset(offset);
+        self.move_to_position(position);
     }
 
-    fn move_left_by_word(&mut self, mut repeat_times: usize) {
-        while repeat_times > 0 {
-            let mut offset = self
-                .buffer
-                .get_offset_from_position(&Position::new(
-                    self.cursor_position.x,
-                    self.offset_y + self.cursor_position.y,
-                ))
-                .unwrap_or(0);
-
-            let buffer = self.buffer.get(&Position::new(0, 0), None);
-            let eval_type = |ch: char| -> u16 {
-                if ch.is_alphanumeric() {
-                    0
-                } else if ch == ' ' {
-                    1
-                } else if ch.is_ascii_punctuation() {
-                    2
-                } else {
-                    3
-                }
-            };
-
-            let mut ch = buffer.chars().nth(offset).unwrap();
-            let mut consecutive_type = eval_type(ch);
-            let mut init_type = consecutive_type;
-            let mut exit = false;
-
-            while init_type == consecutive_type {
-                self.move_left(1);
-                offset -= 1;
-                ch = buffer.chars().nth(offset).unwrap();
-                consecutive_type = eval_type(ch);
+    /// Moves the cursor to a buffer position and scrolls the view, if the
+    /// position is not visible.
+    fn move_to_position(&mut self, pos: Position) {
+        if pos.y < self.offset_y || pos.y > self.offset_y + self.draw_terminal_size().1 {
+            self.offset_y = pos.y.saturating_sub(self.draw_terminal_size().1);
+        }
+        self.cursor_position = Position::new(pos.x, pos.y.saturating_sub(self.offset_y));
+        self.current_line_length = self.buffer().get_line_length(pos.y);
+    }
 
-                if init_type != consecutive_type && exit {
-                    self.move_right(1);
-                    break;
-                }
+    fn undo(&mut self) {
+        match self.buffer_mut().undo() {
+            Some(pos) => self.move_to_position(pos),
+            None => self.command = "Already at oldest change.".to_string(),
+        }
+    }
 
-                if consecutive_type == 1 && init_type != consecutive_type {
-                    init_type = consecutive_type;
-                } else if (consecutive_type == 0 || consecutive_type == 2)
-                    && init_type != consecutive_type
-                    && !exit
-                {
-                    init_type = consecutive_type;
-                    exit = true;
-                }
-            }
-            repeat_times -= 1;
+    fn redo(&mut self) {
+        match self.buffer_mut().redo() {
+            Some(pos) => self.move_to_position(pos),
+            None => self.command = "Already at newest change.".to_string(),
         }
     }
 
@@ -623,7 +3095,7 @@ impl Editor {
 
     /// Check if the buffer contains the line. Use 0-based alignment.
     fn is_valid_line(&self, line: usize) -> bool {
-        line < self.buffer.get_total_lines()
+        line < self.buffer().get_total_lines()
     }
 
     /// Check if the buffer contains the column for the line. Use 0-based alignment.
@@ -633,7 +3105,7 @@ impl Editor {
             return false;
         }
 
-        if position.y + 1 == self.buffer.get_total_lines()
+        if position.y + 1 == self.buffer().get_total_lines()
             && position.x == 0
             && self.current_line_length == 0
         {
@@ -647,22 +3119,43 @@ impl Editor {
         true
     }
 
-    fn draw_buffer(&mut self) {
+    /// Draw all windows and the separators between windows next to each other.
+    fn draw_windows(&mut self) {
+        self.save_window();
+        let (rects, separators) = self.window_rects();
+        for (index, rect) in rects {
+            self.draw_buffer(index, rect);
+            self.draw_status_bar(index, rect);
+        }
+        for separator in separators {
+            for y in separator.y..separator.y + separator.height {
+                self.terminal.goto(&Position::new(separator.x, y));
+                self.terminal.write_with_color("│", &color::LightBlack);
+            }
+        }
+    }
+
+    fn draw_buffer(&mut self, index: usize, rect: Rect) {
         self.terminal.hide_cursor();
-        self.terminal.goto(&Position::default());
+        self.terminal.goto(&Position::new(rect.x, rect.y));
 
+        let window = self.windows[index];
+        let is_current = index == self.current_window;
         let offset_y = match self.search_mode {
-            SearchMode::None => self.offset_y,
-            SearchMode::Incremental => self.search_offset_y,
+            SearchMode::Incremental if is_current => self.search_offset_y,
+            _ => window.offset_y,
         };
 
-        let buffer = &self.buffer.get(
-            &Position::new(0, offset_y),
-            Some(&Position::new(
-                0,
-                offset_y + self.draw_terminal_size().1 + 1,
-            )),
+        // the last row of the window is the status bar
+        let buffer = &self.buffers[window.buffer].buffer;
+        let last_line = std::cmp::min(
+            offset_y + rect.height.saturating_sub(1),
+            buffer.get_total_lines(),
         );
+        let text = (offset_y..last_line)
+            .map(|y| unicode::truncate(&buffer.get_line(y), rect.width).to_string())
+            .collect::<Vec<String>>()
+            .join("\r\n");
 
         if self.untouched {
             let text = "Nimbus Text Editor";
@@ -687,79 +3180,184 @@ impl Editor {
             pos.y += 2;
             self.terminal.goto(&pos);
             self.terminal.write_with_color(help, &color::White);
-        } else if let Some(highlighter) = self.highlighters.get(&self.file_extension) {
-            highlighter.highlight(buffer, &self.terminal);
         } else {
-            self.terminal.write(buffer);
+            self.terminal.set_line_start(rect.x);
+            let file_extension = self.file_extension_of(buffer);
+            if let Some(highlighter) = self.highlighters.get(&file_extension) {
+                highlighter.highlight(&text, &self.terminal);
+            } else {
+                self.terminal.write(&text);
+            }
+            self.terminal.set_line_start(0);
+        }
+
+        if is_current && self.is_visual() {
+            self.draw_selection(offset_y..last_line, rect);
         }
 
-        if self.search_mode == SearchMode::Incremental && !self.search_occurences.is_empty() {
-            let occurences = vec![self.search_occurences[self.search_occurence_idx].clone()];
-            let offset = self
-                .buffer
-                .get_offset_from_position(&Position::new(0, offset_y))
-                .unwrap_or(0);
+        if is_current
+            && self.search_mode == SearchMode::Incremental
+            && !self.search_occurences.is_empty()
+        {
+            let range = self.search_occurences[self.search_occurence_idx].clone();
+            let buffer = &self.buffers[window.buffer].buffer;
+            let mut pos = buffer.get_position_from_offset(range.start);
+
+            if pos.y < offset_y || pos.y > offset_y + self.draw_terminal_size().1 {
+                self.search_offset_y = pos.y.saturating_sub(self.draw_terminal_size().1);
+                return;
+            }
+
+            let end = buffer.get_position_from_offset(range.end);
+            let column = buffer.get_display_column(&pos);
+            let occurence = buffer.get(&pos, Some(&end));
+            let occurence = unicode::truncate(&occurence, rect.width.saturating_sub(column));
+            pos.y = pos.y.saturating_sub(offset_y);
+            self.search_cursor_position = pos;
+            self.terminal
+                .goto(&Position::new(rect.x + column, rect.y + pos.y));
+
+            if self.mode == EditorMode::Command {
+                self.terminal
+                    .write_with_color_bg(occurence, &color::Black, &color::LightYellow);
+            } else {
+                self.terminal.write_with_color(occurence, &color::Reset);
+            }
+        }
 
-            for range in occurences.iter() {
-                let mut pos = self.buffer.get_position_from_offset(range.start);
+        if is_current {
+            self.draw_substitute_match(offset_y, rect);
+        }
 
-                if pos.y < offset_y || pos.y > offset_y + self.draw_terminal_size().1 {
-                    self.search_offset_y = pos.y.saturating_sub(self.draw_terminal_size().1);
-                    return;
-                }
+        self.terminal.show_cursor();
+    }
 
-                let start = range.start - offset;
-                let end = range.end - offset;
+    /// Highlight the match `:s` with the `c` flag asks about, in its first
+    /// line.
+    fn draw_substitute_match(&mut self, offset_y: usize, rect: Rect) {
+        let Some(Prompt::Substitute(substitution)) = &self.prompt else {
+            return;
+        };
+        let Some(captures) = &substitution.pending else {
+            return;
+        };
+        let buffer = self.buffer();
+        let range = captures.range();
+        let position = buffer.get_position_from_offset(range.start);
+        // the last row of the window is the status bar
+        if position.y < offset_y || position.y >= offset_y + rect.height.saturating_sub(1) {
+            return;
+        }
+        let text = buffer.get_range(range);
+        let text = text.split(['\r', '\n']).next().unwrap_or_default();
+        let column = buffer.get_display_column(&position);
+        let text = unicode::truncate(text, rect.width.saturating_sub(column)).to_string();
+        self.terminal.goto(&Position::new(
+            rect.x + column,
+            rect.y + position.y - offset_y,
+        ));
+        self.terminal
+            .write_with_color_bg(&text, &color::Black, &color::LightYellow);
+    }
 
-                pos.y = pos.y.saturating_sub(offset_y);
-                self.search_cursor_position = pos;
-                self.terminal.goto(&pos);
+    /// Draw the selected text of the current window over the lines `lines`.
+    fn draw_selection(&mut self, lines: Range<usize>, rect: Rect) {
+        for y in lines.clone() {
+            let Some(columns) = self.visual_columns(y) else {
+                continue;
+            };
+            let line = self.buffer().get_line(y);
+            let line: Vec<&str> = unicode::graphemes(&line).collect();
+            let start = std::cmp::min(columns.start, line.len());
+            let end = std::cmp::min(columns.end, line.len());
+            let mut text = line[start..end].concat();
+            // a selected line break is shown as a space
+            if columns.end > line.len() {
+                text.push(' ');
+            }
 
-                if self.mode == EditorMode::Command {
-                    self.terminal.write_with_color_bg(
-                        &buffer[start..end],
-                        &color::Black,
-                        &color::LightYellow,
-                    );
-                } else {
-                    self.terminal
-                        .write_with_color(&buffer[start..end], &color::Reset);
-                }
+            let column = unicode::str_width(&line[..start].concat());
+            if column >= rect.width {
+                continue;
             }
+            let text = unicode::truncate(&text, rect.width - column);
+            self.terminal
+                .goto(&Position::new(rect.x + column, rect.y + y - lines.start));
+            self.terminal
+                .write_with_color_bg(text, &color::Black, &color::LightBlue);
         }
-
-        self.terminal.show_cursor();
     }
 
+    /// Draw the command line. Messages with several lines (e.g. `:ls`) are
+    /// drawn above it, over the buffer.
     fn draw_command(&mut self) {
-        self.terminal.goto(&Position {
-            x: 0,
-            y: self.terminal.size().1 as usize - 1,
-        });
-        self.terminal.write(&self.command);
+        let lines: Vec<&str> = self.command.lines().collect();
+        let last_row = self.terminal.size().1 as usize - 1;
+        for (i, line) in lines.iter().enumerate() {
+            self.terminal.goto(&Position {
+                x: 0,
+                y: (last_row + i + 1).saturating_sub(lines.len()),
+            });
+            self.terminal.clear_line();
+            self.terminal.write(line);
+        }
+
+        if let Some((register, _)) = self.recording {
+            let column = lines.last().map_or(0, |line| unicode::str_width(line));
+            if lines.is_empty() {
+                self.terminal.goto(&Position::new(0, last_row));
+                self.terminal.clear_line();
+            }
+            self.terminal.goto(&Position::new(column, last_row));
+            self.terminal.write(&format!("recording @{}", register));
+        }
     }
 
-    fn draw_status_bar(&mut self) {
+    fn draw_status_bar(&mut self, index: usize, rect: Rect) {
         if self.untouched {
             return;
         }
 
-        let left_side = self.buffer.file_path().unwrap_or("[No Name]").to_string();
+        let window = self.windows[index];
+        let is_current = index == self.current_window;
+        let buffer = &self.buffers[window.buffer].buffer;
+
+        let mut left_side = buffer.file_path().unwrap_or("[No Name]").to_string();
+        if buffer.is_modified() {
+            left_side.push_str(" [+]");
+        }
+        if let Some(message) = self.status_message.as_ref().filter(|_| is_current) {
+            left_side = format!("{} | {}", left_side, message);
+        }
+        let mixed = if buffer.has_mixed_line_endings() {
+            " (mixed)"
+        } else {
+            ""
+        };
         let right_side = format!(
-            "{} | {}:{}",
-            &self.file_extension.as_str(),
-            self.cursor_position.y,
-            self.cursor_position.x,
+            "{} | {}{} | {}:{}",
+            self.file_extension_of(buffer).as_str(),
+            buffer.file_format().as_str(),
+            mixed,
+            window.cursor_position.y,
+            window.cursor_position.x,
         );
-        let pad_left = self.terminal.size().0 as usize - left_side.len() - 2;
-        self.status = format!(" {}{:>width$} ", left_side, right_side, width = pad_left);
+        let pad_left = rect.width.saturating_sub(left_side.len()).saturating_sub(2);
+        let status = format!(" {}{:>width$} ", left_side, right_side, width = pad_left);
+        let status = unicode::truncate(&status, rect.width);
 
-        self.terminal.goto(&Position {
-            x: 0,
-            y: self.terminal.size().1 as usize - 2,
-        });
-        self.terminal
-            .write_with_color_bg(&self.status, &color::Black, &color::Magenta);
+        self.terminal.goto(&Position::new(
+            rect.x,
+            rect.y + rect.height.saturating_sub(1),
+        ));
+        if is_current {
+            self.terminal
+                .write_with_color_bg(status, &color::Black, &color::Magenta);
+            self.status = status.to_string();
+        } else {
+            self.terminal
+                .write_with_color_bg(status, &color::Black, &color::LightBlack);
+        }
     }
 
     fn draw_debug(&mut self) {
@@ -788,7 +3386,7 @@ impl Editor {
 
     /// Print the keybind information into the command bar.
     fn print_help(&mut self) {
-        self.command = "<C-q> - Exit, <C-w> - Save | Command: :q - quit, :w - write, :debug - toggle debug bar".to_string();
+        self.command = "<C-q> - Exit, <C-w> - Save | Command: :q - quit, :w - write, :{n} - go to line, :[range]d/y/m/t/j/>/</normal - line commands, :[range]s/pat/rep/[gci] - substitute, :debug - toggle debug bar, :e file - open, :bn/:bp/:b N - switch buffer, :ls - list buffers, :sp/:vs - split, <C-x> hjkl - move to window, v/V/<C-v> - visual mode, p/P - put, . - repeat, f/F/t/T{char} ;/, - find char, q{reg}/@{reg} - macros, m{a-z} '{a-z} - marks, <C-o>/<C-i> - jump list, :reg - registers, :set ff=unix|dos - line endings, :set ic/scs - ignore case/smartcase".to_string();
     }
 
     fn toggle_debug_bar(&mut self) {
@@ -797,6 +3395,10 @@ impl Editor {
     }
 
     fn search_next(&mut self) {
+        if self.search_occurences.is_empty() {
+            return;
+        }
+        self.record_jump();
         if self.search_occurence_idx < self.search_occurences.len() - 1 {
             self.search_occurence_idx += 1;
         } else {
@@ -804,7 +3406,7 @@ impl Editor {
         }
 
         let range = &self.search_occurences[self.search_occurence_idx];
-        let pos = self.buffer.get_position_from_offset(range.start);
+        let pos = self.buffer().get_position_from_offset(range.start);
         if pos.y < self.offset_y || pos.y > self.offset_y + self.draw_terminal_size().1 {
             self.offset_y = pos.y.saturating_sub(self.draw_terminal_size().1);
         }
@@ -812,33 +3414,46 @@ impl Editor {
     }
 
     fn search_previous(&mut self) {
+        if self.search_occurences.is_empty() {
+            return;
+        }
+        self.record_jump();
         if self.search_occurence_idx > 0 {
             self.search_occurence_idx -= 1;
         } else {
             self.search_occurence_idx = self.search_occurences.len() - 1;
         }
         let range = &self.search_occurences[self.search_occurence_idx];
-        let pos = self.buffer.get_position_from_offset(range.start);
+        let pos = self.buffer().get_position_from_offset(range.start);
         if pos.y < self.offset_y || pos.y > self.offset_y + self.draw_terminal_size().1 {
             self.offset_y = pos.y;
         }
         self.cursor_position = Position::new(pos.x, pos.y.saturating_sub(self.offset_y));
     }
 
+    /// Width of the current window and the last row, in which the cursor can
+    /// be without scrolling.
     fn draw_terminal_size(&self) -> (usize, usize) {
-        let (w, h) = self.terminal.size();
-        (w.into(), (h - 4).into())
+        let rect = self.window_rect();
+        (rect.width, rect.height.saturating_sub(2))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_dir::TestDir;
+
+    /// Editor with a terminal of 120 columns and 40 rows, which does not
+    /// need a TTY.
+    fn test_editor(buffer: Buffer) -> Editor {
+        Editor::with_terminal(buffer, Terminal::with_size((120, 40)))
+    }
 
     #[test]
     fn test_is_valid_column() {
         let buffer = Buffer::from_string("File is read.\r\nThe hero lied.\r\nThe end.".to_string());
-        let mut editor = Editor::new(buffer).unwrap();
+        let mut editor = test_editor(buffer);
 
         assert!(editor.is_valid_column(&Position::new(0, 0)));
         assert!(editor.is_valid_column(&Position::new(13, 0)));
@@ -860,7 +3475,7 @@ mod tests {
     #[test]
     fn test_is_valid_line() {
         let buffer = Buffer::from_string("File is read.\r\nThe hero lied.\r\nThe end.".to_string());
-        let editor = Editor::new(buffer).unwrap();
+        let editor = test_editor(buffer);
 
         assert!(editor.is_valid_line(0));
         assert!(editor.is_valid_line(1));
@@ -870,7 +3485,7 @@ mod tests {
 
         let buffer =
             Buffer::from_string("File is read.\r\nThe hero lied.\r\nThe end.\r\n".to_string());
-        let editor = Editor::new(buffer).unwrap();
+        let editor = test_editor(buffer);
 
         assert!(editor.is_valid_line(3));
         assert!(!editor.is_valid_line(4));
@@ -880,16 +3495,16 @@ mod tests {
     fn test_editor_complex_1() {
         let buffer =
             Buffer::from_string("File is read.\r\nThe hero lied.\r\nThe end.\r\n".to_string());
-        let mut editor = Editor::new(buffer).unwrap();
+        let mut editor = test_editor(buffer);
 
-        editor.buffer.delete(&Position::new(14, 1), 2);
+        editor.buffer_mut().delete(&Position::new(14, 1), 1);
         assert_eq!(
-            editor.buffer.get(&Position::new(0, 0), None),
+            editor.buffer().get(&Position::new(0, 0), None),
             "File is read.\r\nThe hero lied.The end.\r\n"
         );
 
-        assert_eq!(editor.buffer.get_line_length(1), 22);
-        assert_eq!(editor.buffer.get_total_lines(), 3);
+        assert_eq!(editor.buffer().get_line_length(1), 22);
+        assert_eq!(editor.buffer().get_total_lines(), 3);
 
         editor.move_down(1);
         assert!(editor.is_valid_column(&Position { x: 22, y: 1 }));
@@ -898,4 +3513,987 @@ mod tests {
         assert!(editor.is_valid_line(2));
         assert!(!editor.is_valid_line(3));
     }
+
+    #[test]
+    fn test_undo_insert_session() {
+        let buffer = Buffer::from_string("File is read.\r\nThe hero lied.\r\n".to_string());
+        let mut editor = test_editor(buffer);
+
+        editor.move_down(1);
+        editor.move_right(9);
+        editor.change_mode(EditorMode::Insert);
+        for c in "has ".chars() {
+            let position = editor.buffer_position();
+            editor.buffer_mut().insert(&c.to_string(), &position);
+            editor.cursor_position.x += 1;
+        }
+        editor.change_mode(EditorMode::Normal);
+        editor.move_to_sol();
+        editor.move_up(1);
+
+        editor.undo();
+        assert_eq!(
+            editor.buffer().get(&Position::new(0, 0), None),
+            "File is read.\r\nThe hero lied.\r\n"
+        );
+        assert_eq!(editor.cursor_position, Position::new(9, 1));
+
+        editor.redo();
+        assert_eq!(
+            editor.buffer().get(&Position::new(0, 0), None),
+            "File is read.\r\nThe hero has lied.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_move_by_word_multi_byte() {
+        let buffer = Buffer::from_string("Grüße 🦀 lied.".to_string());
+        let mut editor = test_editor(buffer);
+
+        editor.move_by_word(WordMotion::Forward, false, 1);
+        assert_eq!(editor.cursor_position, Position::new(6, 0));
+        assert_eq!(editor.terminal_cursor_position(), Position::new(6, 0));
+        editor.move_by_word(WordMotion::Forward, false, 1);
+        assert_eq!(editor.cursor_position, Position::new(8, 0));
+        assert_eq!(editor.terminal_cursor_position(), Position::new(9, 0));
+        editor.move_by_word(WordMotion::Backward, false, 1);
+        assert_eq!(editor.cursor_position, Position::new(6, 0));
+        editor.move_by_word(WordMotion::Backward, false, 1);
+        assert_eq!(editor.cursor_position, Position::new(0, 0));
+    }
+
+    #[test]
+    fn test_combining_characters() {
+        let buffer = Buffer::from_string("e\u{301}te\u{301} fa\u{308}r".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+
+        // a letter and its accent are one column
+        keys(&mut editor, "ll");
+        assert_eq!(editor.cursor_position, Position::new(2, 0));
+        assert_eq!(editor.terminal_cursor_position(), Position::new(2, 0));
+        keys(&mut editor, "dl");
+        assert_eq!(editor.buffer().get_line(0), "e\u{301}t fa\u{308}r");
+        keys(&mut editor, "$");
+        assert_eq!(editor.cursor_position, Position::new(6, 0));
+        keys(&mut editor, "Fadl");
+        assert_eq!(editor.cursor_position, Position::new(4, 0));
+        assert_eq!(editor.buffer().get_line(0), "e\u{301}t fr");
+        keys(&mut editor, "0w");
+        assert_eq!(editor.cursor_position, Position::new(3, 0));
+        keys(&mut editor, "bdiw");
+        assert_eq!(editor.buffer().get_line(0), " fr");
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let buffer = Buffer::from_string("let a = b.c;\r\n\r\n  end".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+
+        keys(&mut editor, "5w");
+        assert_eq!(editor.cursor_position, Position::new(10, 0));
+        keys(&mut editor, "ww");
+        assert_eq!(editor.cursor_position, Position::new(0, 1));
+        // `w` on the last word stops at the end of the buffer
+        keys(&mut editor, "www");
+        assert_eq!(editor.cursor_position, Position::new(5, 2));
+        keys(&mut editor, "ge");
+        assert_eq!(editor.cursor_position, Position::new(0, 1));
+        keys(&mut editor, "gE");
+        assert_eq!(editor.cursor_position, Position::new(11, 0));
+        keys(&mut editor, "B");
+        assert_eq!(editor.cursor_position, Position::new(8, 0));
+        keys(&mut editor, "bbE");
+        assert_eq!(editor.cursor_position, Position::new(6, 0));
+
+        keys(&mut editor, "0de");
+        assert_eq!(editor.buffer().get_line(0), " a = b.c;");
+        // the last word of a line is deleted up to the line end
+        keys(&mut editor, "$BdW");
+        assert_eq!(editor.buffer().get_line(0), " a = ");
+        assert_eq!(editor.buffer().get_total_lines(), 3);
+        keys(&mut editor, "0dW");
+        assert_eq!(editor.buffer().get_line(0), "a = ");
+        keys(&mut editor, "wdge");
+        assert_eq!(editor.buffer().get_line(0), " ");
+    }
+
+    #[test]
+    fn test_line_motions() {
+        let text: Vec<String> = (1..=100).map(|n| format!("  line {}", n)).collect();
+        let buffer = Buffer::from_string(text.join("\r\n"));
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+        let height = editor.draw_terminal_size().1;
+
+        keys(&mut editor, "G");
+        assert_eq!(editor.buffer_position(), Position::new(2, 99));
+        assert_eq!(editor.offset_y, 99 - height);
+        assert_eq!(editor.current_line_length, 10);
+        keys(&mut editor, "H");
+        assert_eq!(editor.buffer_position(), Position::new(2, 99 - height));
+        keys(&mut editor, "3L");
+        assert_eq!(editor.buffer_position(), Position::new(2, 97));
+        keys(&mut editor, "M");
+        assert_eq!(
+            editor.buffer_position(),
+            Position::new(2, 99 - height + height / 2)
+        );
+        keys(&mut editor, "gg");
+        assert_eq!(editor.buffer_position(), Position::new(2, 0));
+        assert_eq!(editor.offset_y, 0);
+        keys(&mut editor, "12G");
+        assert_eq!(editor.buffer_position(), Position::new(2, 11));
+        keys(&mut editor, "500gg");
+        assert_eq!(editor.buffer_position(), Position::new(2, 99));
+
+        editor.command = ":50".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.buffer_position(), Position::new(2, 49));
+        editor.command = ":$".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.buffer_position(), Position::new(2, 99));
+
+        keys(&mut editor, "3ggd2gg");
+        assert_eq!(editor.buffer().get_line(0), "  line 1");
+        assert_eq!(editor.buffer().get_line(1), "  line 4");
+        keys(&mut editor, "95GdG");
+        assert_eq!(editor.buffer().get_total_lines(), 94);
+        assert_eq!(editor.buffer().get_line(93), "  line 96");
+    }
+
+    #[test]
+    fn test_block_motions() {
+        let buffer = Buffer::from_string(
+            "fn f() {\r\n    g(\")\"); // )\r\n}\r\n\r\nOne. Two.\r\nThree.".to_string(),
+        );
+        let mut editor = test_editor(buffer);
+        editor.file_extension = FileExtension::Rust;
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+
+        keys(&mut editor, "}");
+        assert_eq!(editor.buffer_position(), Position::new(0, 3));
+        keys(&mut editor, "}");
+        assert_eq!(editor.buffer_position(), Position::new(6, 5));
+        keys(&mut editor, "2{");
+        assert_eq!(editor.buffer_position(), Position::new(0, 0));
+
+        // brackets in strings and comments are skipped
+        keys(&mut editor, "%");
+        assert_eq!(editor.buffer_position(), Position::new(5, 0));
+        keys(&mut editor, "j0%");
+        assert_eq!(editor.buffer_position(), Position::new(9, 1));
+        keys(&mut editor, "%");
+        assert_eq!(editor.buffer_position(), Position::new(5, 1));
+        keys(&mut editor, "k$%");
+        assert_eq!(editor.buffer_position(), Position::new(0, 2));
+
+        keys(&mut editor, ")");
+        assert_eq!(editor.buffer_position(), Position::new(0, 3));
+        keys(&mut editor, "2)");
+        assert_eq!(editor.buffer_position(), Position::new(5, 4));
+        keys(&mut editor, "(");
+        assert_eq!(editor.buffer_position(), Position::new(0, 4));
+
+        keys(&mut editor, "d)");
+        assert_eq!(editor.buffer().get_line(4), "Two.");
+        keys(&mut editor, "gg0d%");
+        assert_eq!(editor.buffer().get_line(0), " {");
+        keys(&mut editor, "d}");
+        assert_eq!(editor.buffer().get_line(0), "");
+        assert_eq!(editor.buffer().get_line(1), "Two.");
+    }
+
+    #[test]
+    fn test_marks_and_jumps() {
+        let buffer = Buffer::from_string("one\r\ntwo\r\nthree\r\nfour\r\nfive".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            key_notation::from_text(keys)
+                .into_iter()
+                .for_each(|key| editor.handle_key(key))
+        };
+
+        keys(&mut editor, "jlmaG");
+        assert_eq!(editor.buffer_position(), Position::new(0, 4));
+        keys(&mut editor, "'a");
+        assert_eq!(editor.buffer_position(), Position::new(0, 1));
+        keys(&mut editor, "G`a");
+        assert_eq!(editor.buffer_position(), Position::new(1, 1));
+        // `` goes back to the position before the jump
+        keys(&mut editor, "``");
+        assert_eq!(editor.buffer_position(), Position::new(0, 4));
+        keys(&mut editor, "``");
+        assert_eq!(editor.buffer_position(), Position::new(1, 1));
+
+        keys(&mut editor, "<C-o>");
+        assert_eq!(editor.buffer_position(), Position::new(0, 4));
+        keys(&mut editor, "<C-o>");
+        assert_eq!(editor.buffer_position(), Position::new(0, 4));
+        keys(&mut editor, "\t");
+        assert_eq!(editor.buffer_position(), Position::new(1, 1));
+
+        // marks move along with the text
+        keys(&mut editor, "ggdd`a");
+        assert_eq!(editor.buffer_position(), Position::new(1, 0));
+        keys(&mut editor, "Gmbgg$d`a");
+        assert_eq!(editor.buffer().get_line(0), "t");
+        keys(&mut editor, "jd'b");
+        assert_eq!(editor.buffer().get_total_lines(), 1);
+        keys(&mut editor, "'z");
+        assert_eq!(editor.command, "Mark not set: z");
+
+        // file marks switch the buffer
+        let mut editor = test_editor(Buffer::from_string("x\r\ny".to_string()));
+        editor.add_buffer(Buffer::from_string("z".to_string()));
+        editor.cycle_buffer(1);
+        keys(&mut editor, "mA");
+        editor.cycle_buffer(1);
+        keys(&mut editor, "j'A");
+        assert_eq!(editor.current_buffer, 1);
+        keys(&mut editor, "<C-o>");
+        assert_eq!(editor.current_buffer, 0);
+        assert_eq!(editor.buffer_position(), Position::new(0, 1));
+        keys(&mut editor, "mA");
+        assert_eq!(editor.buffers[1].buffer.mark('A'), None);
+    }
+
+    #[test]
+    fn test_ex_commands() {
+        let buffer = Buffer::from_string("one\r\ntwo\r\nthree\r\nfour\r\nfive".to_string());
+        let mut editor = test_editor(buffer);
+        let run = |editor: &mut Editor, command: &str| {
+            editor.mode = EditorMode::Command;
+            editor.command = format!(":{}", command);
+            editor.run_command().unwrap();
+        };
+        let lines = |editor: &Editor| -> Vec<String> {
+            (0..editor.buffer().get_total_lines())
+                .map(|y| editor.buffer().get_line(y))
+                .collect()
+        };
+
+        run(&mut editor, "2,3d");
+        assert_eq!(lines(&editor), ["one", "four", "five"]);
+        assert_eq!(editor.registers.get('"').unwrap().text, "two\r\nthree\r\n");
+        run(&mut editor, "$t0");
+        assert_eq!(lines(&editor), ["five", "one", "four", "five"]);
+        assert_eq!(editor.buffer_position(), Position::new(0, 0));
+        run(&mut editor, "1m$");
+        assert_eq!(lines(&editor), ["one", "four", "five", "five"]);
+        assert_eq!(editor.buffer_position(), Position::new(0, 3));
+        run(&mut editor, "/four/,$j");
+        assert_eq!(lines(&editor), ["one", "four five five"]);
+        run(&mut editor, "%normal A;");
+        assert_eq!(lines(&editor), ["one;", "four five five;"]);
+        run(&mut editor, "2>>");
+        assert_eq!(lines(&editor), ["one;", "        four five five;"]);
+        run(&mut editor, ".<");
+        assert_eq!(lines(&editor), ["one;", "    four five five;"]);
+
+        // a command is undone in one step
+        run(&mut editor, "1t$");
+        assert_eq!(editor.buffer().get_total_lines(), 3);
+        editor.undo();
+        assert_eq!(editor.buffer().get_total_lines(), 2);
+
+        // a huge count reaches to the last line
+        run(&mut editor, "1j 18446744073709551615");
+        assert_eq!(lines(&editor), ["one; four five five;"]);
+        editor.undo();
+        run(&mut editor, "d 18446744073709551615");
+        assert_eq!(lines(&editor), [""]);
+        editor.undo();
+        assert_eq!(lines(&editor), ["one;", "    four five five;"]);
+
+        run(&mut editor, "'x,.d");
+        assert_eq!(editor.command, "Mark not set: x");
+        run(&mut editor, "1,2m1");
+        assert_eq!(editor.command, "Cannot move a range of lines into itself");
+        run(&mut editor, "5d");
+        assert_eq!(editor.command, "Invalid range");
+        run(&mut editor, "1ls");
+        assert_eq!(editor.command, "No range allowed");
+        run(&mut editor, "foo");
+        assert_eq!(editor.command, "Not an editor command: foo");
+
+        // `:` in Visual mode starts a command for the selected lines
+        key_notation::from_text("ggVj:")
+            .into_iter()
+            .for_each(|key| editor.handle_key(key));
+        assert_eq!(editor.command, ":'<,'>");
+        editor.command.push('d');
+        editor.run_command().unwrap();
+        assert_eq!(lines(&editor), [""]);
+    }
+
+    #[test]
+    fn test_substitute() {
+        let buffer = Buffer::from_string("a = 1; b = 2;\r\nc = 3;\r\nno\r\nd = 4;".to_string());
+        let mut editor = test_editor(buffer);
+        let run = |editor: &mut Editor, command: &str| {
+            editor.mode = EditorMode::Command;
+            editor.command = format!(":{}", command);
+            editor.run_command().unwrap();
+        };
+        let lines = |editor: &Editor| -> Vec<String> {
+            (0..editor.buffer().get_total_lines())
+                .map(|y| editor.buffer().get_line(y))
+                .collect()
+        };
+
+        run(&mut editor, "%s/\\(\\w\\) = \\(\\d\\)/\\2 = \\u\\1/");
+        assert_eq!(lines(&editor), ["1 = A; b = 2;", "3 = C;", "no", "4 = D;"]);
+        assert_eq!(editor.command, "3 substitutions on 3 lines");
+        assert_eq!(editor.buffer_position(), Position::new(0, 3));
+        // all replacements are undone in one step
+        editor.undo();
+        assert_eq!(lines(&editor), ["a = 1; b = 2;", "c = 3;", "no", "d = 4;"]);
+
+        run(&mut editor, "1s/ = /=/g");
+        assert_eq!(lines(&editor)[0], "a=1; b=2;");
+        run(&mut editor, "2,3s/;$/\\r/");
+        assert_eq!(lines(&editor), ["a=1; b=2;", "c = 3", "", "no", "d = 4;"]);
+        run(&mut editor, "s/x/y/");
+        assert_eq!(editor.command, "Pattern not found: x");
+        run(&mut editor, "%s//-/");
+        assert_eq!(editor.command, "Pattern not found: x");
+
+        // `c` asks for every match
+        let buffer = Buffer::from_string("x x\r\nx\r\nx x".to_string());
+        let mut editor = test_editor(buffer);
+        run(&mut editor, "%s/x/yy/gc");
+        assert!(editor.mode == EditorMode::Prompt);
+        assert_eq!(editor.command, "replace with yy (y/n/a/q/l)?");
+        for key in "ynz".chars() {
+            editor.handle_key(Key::Char(key));
+        }
+        assert_eq!(editor.buffer_position(), Position::new(0, 1));
+        editor.handle_key(Key::Char('a'));
+        assert!(editor.mode == EditorMode::Normal);
+        assert_eq!(lines(&editor), ["yy x", "yy", "yy yy"]);
+        assert_eq!(editor.command, "4 substitutions on 3 lines");
+        editor.undo();
+        assert_eq!(lines(&editor), ["x x", "x", "x x"]);
+
+        run(&mut editor, "%s/x/z/c");
+        editor.handle_key(Key::Char('l'));
+        assert_eq!(lines(&editor), ["z x", "x", "x x"]);
+        run(&mut editor, "%s/x/z/c");
+        editor.handle_key(Key::Char('y'));
+        editor.handle_key(Key::Esc);
+        assert_eq!(lines(&editor), ["z z", "x", "x x"]);
+        assert_eq!(editor.command, "1 substitution on 1 line");
+    }
+
+    #[test]
+    fn test_search() {
+        let buffer = Buffer::from_string("Let x = 1;\r\nlet y = 22;".to_string());
+        let mut editor = test_editor(buffer);
+        let search = |editor: &mut Editor, keys: &str| {
+            key_notation::from_text(keys)
+                .into_iter()
+                .for_each(|key| editor.handle_key(key));
+            editor.search_occurences.clone()
+        };
+
+        assert_eq!(search(&mut editor, "/let"), vec![12..15]);
+        assert_eq!(search(&mut editor, "\\c"), vec![0..3, 12..15]);
+        assert_eq!(search(&mut editor, "<Esc>/\\d\\+;"), vec![8..10, 20..23]);
+        // an incomplete pattern finds nothing
+        assert_eq!(search(&mut editor, "<Esc>/\\("), vec![]);
+        assert_eq!(search(&mut editor, "x\\)"), vec![4..5]);
+
+        search(&mut editor, "<Esc>:set ic<CR>:set scs<CR>");
+        assert_eq!(search(&mut editor, "/let"), vec![0..3, 12..15]);
+        assert_eq!(search(&mut editor, "<BS><BS><BS>Let"), vec![0..3]);
+        assert_eq!(search(&mut editor, "\\C"), vec![0..3]);
+
+        // `:s` with an empty pattern uses the last search
+        search(&mut editor, "<CR>:%s//Const/<CR>");
+        assert_eq!(editor.buffer().get_line(0), "Const x = 1;");
+        assert_eq!(editor.buffer().get_line(1), "let y = 22;");
+    }
+
+    #[test]
+    fn test_set_fileformat() {
+        let buffer = Buffer::from_string("File is read.\r\nThe end.\n".to_string());
+        let mut editor = test_editor(buffer);
+        assert_eq!(editor.buffer().file_format(), FileFormat::Unix);
+
+        editor.command = ":set fileformat=dos".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.buffer().file_format(), FileFormat::Dos);
+        assert!(!editor.buffer().has_mixed_line_endings());
+        assert_eq!(editor.command, "-- fileformat=dos");
+
+        editor.command = ":set ff=mac".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.buffer().file_format(), FileFormat::Dos);
+        assert_eq!(editor.command, "Invalid fileformat: mac");
+    }
+
+    #[test]
+    fn test_save_file_changed_on_disk() {
+        let dir = TestDir::new("editor-disk");
+        let path = dir.0.join("file.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "File is read.\n").unwrap();
+
+        let mut editor = test_editor(Buffer::from_file_with_undo_dir(path, None).unwrap());
+        editor.buffer_mut().insert("!", &Position::new(13, 0));
+        std::fs::write(path, "File was formatted.\nThe end.\n").unwrap();
+
+        // saving asks first, Esc cancels
+        assert!(!editor.save_buffer());
+        assert!(editor.mode == EditorMode::Prompt);
+        editor.handle_key_prompt(Key::Char('x'));
+        assert!(editor.mode == EditorMode::Prompt);
+        editor.handle_key_prompt(Key::Esc);
+        assert!(editor.mode == EditorMode::Normal);
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "File was formatted.\nThe end.\n"
+        );
+
+        // the ignored change is not reported again by the background check
+        editor.disk_check_pending = true;
+        editor.check_disk_changes();
+        assert!(editor.mode == EditorMode::Normal);
+
+        editor.save_buffer();
+        editor.handle_key_prompt(Key::Char('r'));
+        assert_eq!(editor.buffer().get_line(1), "The end.");
+        assert_eq!(editor.command, "-- File reloaded.");
+
+        editor.buffer_mut().insert("!", &Position::new(8, 1));
+        assert!(editor.save_buffer());
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "File was formatted.\nThe end.!\n"
+        );
+
+        // keeping both saves a copy, the buffer stays with the file
+        std::fs::write(path, "File was changed.\n").unwrap();
+        editor.buffer_mut().insert("?", &Position::new(0, 0));
+        assert!(!editor.save_buffer());
+        editor.handle_key_prompt(Key::Char('k'));
+        let copy_path = format!("{}.mine", path);
+        assert_eq!(
+            std::fs::read_to_string(&copy_path).unwrap(),
+            "?File was formatted.\nThe end.!\n"
+        );
+        assert_eq!(editor.buffer().file_path(), Some(path));
+        assert!(editor.command.contains("The buffer still edits"));
+        editor.disk_check_pending = true;
+        editor.check_disk_changes();
+        assert!(editor.mode == EditorMode::Normal);
+    }
+
+    #[test]
+    fn test_buffer_list() {
+        let dir = TestDir::new("editor-buffers");
+        let rust_path = dir.0.join("main.rs");
+        let toml_path = dir.0.join("Cargo.toml");
+        fs::write(&rust_path, "fn main() {\n}\n").unwrap();
+        fs::write(&toml_path, "[package]\nname = \"nimbus\"\n").unwrap();
+
+        let mut editor = test_editor(
+            Buffer::from_file_with_undo_dir(rust_path.to_str().unwrap(), None).unwrap(),
+        );
+        editor.add_buffer(
+            Buffer::from_file_with_undo_dir(toml_path.to_str().unwrap(), None).unwrap(),
+        );
+        assert!(editor.file_extension == FileExtension::Rust);
+
+        editor.cursor_position = Position::new(1, 1);
+        editor.command = ":bn".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.current_buffer, 1);
+        assert!(editor.file_extension == FileExtension::Toml);
+        assert_eq!(editor.cursor_position, Position::new(0, 0));
+
+        let position = editor.buffer_position();
+        editor.buffer_mut().insert("# ", &position);
+        editor.command = ":bn".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.current_buffer, 0);
+        assert_eq!(editor.cursor_position, Position::new(1, 1));
+
+        editor.command = ":ls".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(
+            editor.command,
+            format!(
+                "  1 %  \"{}\" line 2\n  2  + \"{}\" line 1",
+                rust_path.display(),
+                toml_path.display()
+            )
+        );
+
+        editor.command = ":b 3".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.command, "No buffer 3");
+        editor.command = ":b 2".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.buffer().get_line(0), "# [package]");
+
+        // an open file is not loaded twice, a new file is created on save
+        editor.command = format!(":e {}", rust_path.display());
+        editor.run_command().unwrap();
+        assert_eq!(editor.current_buffer, 0);
+        let new_path = dir.0.join("notes.txt");
+        editor.command = format!(":e {}", new_path.display());
+        editor.run_command().unwrap();
+        assert_eq!(editor.buffers.len(), 3);
+        assert!(editor.file_extension == FileExtension::Text);
+        assert!(!new_path.exists());
+    }
+
+    #[test]
+    fn test_split_windows() {
+        let buffer = Buffer::from_string("File is read.\nThe hero lied.\nThe end.".to_string());
+        let mut editor = test_editor(buffer);
+        let (width, height) = editor.terminal.size();
+        let (width, height) = (width as usize, height as usize);
+        editor.move_down(2);
+
+        editor.command = ":vsplit".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.windows.len(), 2);
+        assert_eq!(editor.current_window, 1);
+        let rect = editor.window_rect();
+        assert_eq!(rect.x, (width - 1) / 2 + 1);
+        assert_eq!(rect.height, height - 1);
+
+        // each window has its own cursor
+        assert_eq!(editor.cursor_position, Position::new(0, 2));
+        editor.move_up(2);
+        assert_eq!(editor.terminal_cursor_position(), Position::new(rect.x, 0));
+        editor.handle_key_window(Key::Char('h'));
+        assert_eq!(editor.current_window, 0);
+        assert_eq!(editor.cursor_position, Position::new(0, 2));
+
+        // a split below, inside of the left window
+        editor.command = ":split".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.current_window, 2);
+        assert_eq!(editor.window_rect().y, (height - 1) / 2);
+        editor.handle_key_window(Key::Char('k'));
+        assert_eq!(editor.current_window, 0);
+        editor.handle_key_window(Key::Char('l'));
+        assert_eq!(editor.current_window, 1);
+        assert_eq!(editor.cursor_position, Position::new(0, 0));
+
+        editor.command = ":q".to_string();
+        editor.run_command().unwrap();
+        assert!(editor.running);
+        assert_eq!(editor.windows.len(), 2);
+        editor.handle_key_window(Key::Char('o'));
+        assert_eq!(editor.windows.len(), 1);
+        assert_eq!(editor.window_rect(), Rect::new(0, 0, width, height - 1));
+        editor.handle_key_window(Key::Char('c'));
+        assert_eq!(editor.command, "Cannot close last window");
+    }
+
+    #[test]
+    fn test_visual_mode() {
+        let buffer = Buffer::from_string("File is read.\r\nThe hero lied.\r\nThe end.".to_string());
+        let mut editor = test_editor(buffer);
+
+        // characterwise, across the line break
+        editor.move_right(5);
+        editor.handle_key_normal_mode('v');
+        assert_eq!(editor.command, "-- VISUAL --");
+        editor.move_down(1);
+        editor.move_left(2);
+        assert_eq!(editor.visual_columns(0), Some(5..usize::MAX));
+        assert_eq!(editor.visual_columns(1), Some(0..4));
+        editor.handle_key_visual_mode('d');
+        assert!(editor.mode == EditorMode::Normal);
+        assert_eq!(editor.buffer().get_line(0), "File hero lied.");
+        assert_eq!(
+            editor.registers.get(register::UNNAMED).unwrap().text,
+            "is read.\r\nThe "
+        );
+        assert_eq!(editor.cursor_position, Position::new(5, 0));
+        editor.undo();
+        assert_eq!(editor.buffer().get_line(0), "File is read.");
+
+        // linewise, with the cursor before the anchor
+        editor.move_to_position(Position::new(3, 2));
+        editor.handle_key_normal_mode('V');
+        editor.handle_key_visual_mode('k');
+        editor.handle_key_visual_mode('>');
+        assert_eq!(editor.buffer().get_line(1), "    The hero lied.");
+        assert_eq!(editor.buffer().get_line(2), "    The end.");
+        assert_eq!(editor.cursor_position, Position::new(4, 1));
+
+        editor.handle_key_normal_mode('V');
+        editor.handle_key_visual_mode('j');
+        editor.handle_key_visual_mode('y');
+        let register = editor.registers.get(register::UNNAMED).cloned().unwrap();
+        assert_eq!(register.text, "    The hero lied.\r\n    The end.\r\n");
+        assert_eq!(register.kind, RegisterKind::Lines);
+
+        // deleting the last lines removes the line break before them
+        editor.handle_key_normal_mode('V');
+        editor.handle_key_visual_mode('j');
+        editor.handle_key_visual_mode('d');
+        assert_eq!(editor.buffer().get_total_lines(), 1);
+        assert_eq!(editor.buffer().get_line(0), "File is read.");
+        assert_eq!(editor.cursor_position, Position::new(0, 0));
+
+        // `o` swaps the ends, `U` upper cases
+        editor.handle_key_normal_mode('v');
+        editor.handle_key_visual_mode('l');
+        editor.handle_key_visual_mode('o');
+        assert_eq!(editor.cursor_position, Position::new(0, 0));
+        assert_eq!(editor.visual_anchor, Position::new(1, 0));
+        editor.handle_key_visual_mode('U');
+        assert_eq!(editor.buffer().get_line(0), "FIle is read.");
+    }
+
+    #[test]
+    fn test_visual_block_mode() {
+        let buffer = Buffer::from_string("let a = 1;\r\nlet b = 2;\r\nlet c = 3;".to_string());
+        let mut editor = test_editor(buffer);
+
+        editor.move_right(4);
+        editor.start_visual(EditorMode::VisualBlock);
+        assert_eq!(editor.command, "-- VISUAL BLOCK --");
+        editor.handle_key_visual_mode('2');
+        editor.handle_key_visual_mode('j');
+        assert_eq!(editor.visual_columns(1), Some(4..5));
+        editor.handle_key_visual_mode('~');
+        assert_eq!(editor.buffer().get_line(2), "let C = 3;");
+
+        // the text typed into the first line is repeated in the other lines
+        editor.start_visual(EditorMode::VisualBlock);
+        editor.handle_key_visual_mode('2');
+        editor.handle_key_visual_mode('j');
+        editor.handle_key_visual_mode('c');
+        assert!(editor.mode == EditorMode::Insert);
+        assert_eq!(editor.buffer().get_line(1), "let  = 2;");
+        editor.buffer_mut().insert("xy", &Position::new(4, 0));
+        editor.cursor_position.x += 2;
+        editor.change_mode(EditorMode::Normal);
+        assert_eq!(editor.buffer().get_line(0), "let xy = 1;");
+        assert_eq!(editor.buffer().get_line(2), "let xy = 3;");
+
+        // the change is a single undo step
+        editor.undo();
+        assert_eq!(editor.buffer().get_line(1), "let B = 2;");
+    }
+
+    #[test]
+    fn test_operators() {
+        let buffer = Buffer::from_string(
+            "let value = compute(a, b);\r\nlet x = 1;\r\nlet y = 2;\r\nlet z = 3;".to_string(),
+        );
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+
+        keys(&mut editor, "d2w");
+        assert_eq!(editor.buffer().get_line(0), "= compute(a, b);");
+        editor.undo();
+        keys(&mut editor, "4l");
+        keys(&mut editor, "cw");
+        assert!(editor.mode == EditorMode::Insert);
+        assert_eq!(editor.buffer().get_line(0), "let  = compute(a, b);");
+        editor.change_mode(EditorMode::Normal);
+        keys(&mut editor, "c$");
+        assert_eq!(editor.buffer().get_line(0), "let ");
+        editor.change_mode(EditorMode::Normal);
+        editor.undo();
+        assert_eq!(editor.buffer().get_line(0), "let  = compute(a, b);");
+
+        keys(&mut editor, "y2j");
+        let register = editor.registers.get(register::UNNAMED).cloned().unwrap();
+        assert_eq!(register.kind, RegisterKind::Lines);
+        assert_eq!(
+            register.text,
+            "let  = compute(a, b);\r\nlet x = 1;\r\nlet y = 2;\r\n"
+        );
+
+        keys(&mut editor, "j2dd");
+        assert_eq!(editor.buffer().get_total_lines(), 2);
+        assert_eq!(editor.buffer().get_line(1), "let z = 3;");
+        keys(&mut editor, "3yy");
+        assert_eq!(
+            editor
+                .registers
+                .get(register::UNNAMED)
+                .cloned()
+                .unwrap()
+                .text,
+            "let z = 3;\r\n"
+        );
+
+        keys(&mut editor, "cc");
+        assert_eq!(editor.buffer().get_line(1), "");
+        assert_eq!(editor.buffer().get_total_lines(), 2);
+        editor.change_mode(EditorMode::Normal);
+
+        keys(&mut editor, "k>>");
+        assert_eq!(editor.buffer().get_line(0), "    let  = compute(a, b);");
+        assert_eq!(editor.cursor_position, Position::new(4, 0));
+    }
+
+    #[test]
+    fn test_registers_and_put() {
+        let buffer = Buffer::from_string("one two\r\nthree".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+
+        // characterwise, after and before the cursor
+        keys(&mut editor, "\"ayw");
+        keys(&mut editor, "$p");
+        assert_eq!(editor.buffer().get_line(0), "one twoone ");
+        assert_eq!(editor.cursor_position, Position::new(10, 0));
+        keys(&mut editor, "0\"a2P");
+        assert_eq!(editor.buffer().get_line(0), "one one one twoone ");
+        editor.undo();
+        assert_eq!(editor.buffer().get_line(0), "one twoone ");
+
+        // linewise, below the last line and above the cursor line
+        keys(&mut editor, "\"Ayy");
+        assert_eq!(
+            editor.registers.get('a').unwrap().text,
+            "one \r\none twoone \r\n"
+        );
+        keys(&mut editor, "jyyp");
+        assert_eq!(editor.buffer().get_total_lines(), 3);
+        assert_eq!(editor.buffer().get_line(2), "three");
+        assert_eq!(editor.cursor_position, Position::new(0, 2));
+        keys(&mut editor, "\"aP");
+        assert_eq!(editor.buffer().get_line(2), "one ");
+        assert_eq!(editor.buffer().get_line(3), "one twoone ");
+        assert_eq!(editor.buffer().get_line(4), "three");
+
+        // deletes go to the delete history, the black hole keeps nothing
+        keys(&mut editor, "dd");
+        keys(&mut editor, "\"_dd");
+        assert_eq!(editor.registers.get('1').unwrap().text, "one \r\n");
+        assert_eq!(
+            editor.registers.get(register::UNNAMED).unwrap().text,
+            "one \r\n"
+        );
+        assert_eq!(editor.buffer().get_line(2), "three");
+
+        editor.command = ":registers".to_string();
+        editor.run_command().unwrap();
+        assert_eq!(editor.command.lines().nth(1), Some("  l  \"\"   one ^J"));
+        assert_eq!(
+            editor.command.lines().nth(3),
+            Some("  l  \"a   one ^Jone twoone ^J")
+        );
+    }
+
+    #[test]
+    fn test_clipboard_register() {
+        let buffer = Buffer::from_string("one\r\ntwo".to_string());
+        let mut editor = test_editor(buffer);
+        editor.clipboard = Clipboard::default();
+
+        // without a read command, the text copied last is pasted
+        "\"+yyj\"+p"
+            .chars()
+            .for_each(|key| editor.handle_key_normal_mode(key));
+        assert_eq!(editor.buffer().get_line(2), "one");
+
+        // a fake clipboard program
+        editor.set_option("clipboardcmd=printf 'pasted\\n'");
+        assert_eq!(
+            editor.clipboard.read_command.as_deref(),
+            Some("printf 'pasted\\n'")
+        );
+        "\"+P"
+            .chars()
+            .for_each(|key| editor.handle_key_normal_mode(key));
+        assert_eq!(editor.buffer().get_line(2), "pasted");
+        assert_eq!(editor.buffer().get_line(3), "one");
+
+        editor.set_option("clipboardcmd=false");
+        "\"+p"
+            .chars()
+            .for_each(|key| editor.handle_key_normal_mode(key));
+        assert!(editor.command.starts_with("Cannot read the clipboard"));
+    }
+
+    #[test]
+    fn test_text_objects() {
+        let buffer = Buffer::from_string(
+            "let s = call(\"(x)\", y);\r\nlet t = 1;\r\n\r\nlet u = 2;".to_string(),
+        );
+        let mut editor = test_editor(buffer);
+        editor.file_extension = FileExtension::Rust;
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+
+        keys(&mut editor, "yiw");
+        assert_eq!(editor.registers.get(register::UNNAMED).unwrap().text, "let");
+        keys(&mut editor, "4wci\"");
+        assert_eq!(editor.buffer().get_line(0), "let s = call(\"\", y);");
+        assert!(editor.mode == EditorMode::Insert);
+        editor.change_mode(EditorMode::Normal);
+        editor.undo();
+
+        // the brackets in the string are skipped
+        editor.move_to_position(Position::new(20, 0));
+        keys(&mut editor, "da(");
+        assert_eq!(editor.buffer().get_line(0), "let s = call;");
+
+        keys(&mut editor, "dap");
+        assert_eq!(editor.buffer().get_line(0), "let u = 2;");
+        editor.undo();
+
+        editor.handle_key_normal_mode('v');
+        editor.handle_key_visual_mode('i');
+        editor.handle_key_visual_mode('p');
+        assert!(editor.mode == EditorMode::VisualLine);
+        assert_eq!(editor.visual_columns(1), Some(0..usize::MAX));
+        assert_eq!(editor.visual_columns(2), None);
+        editor.handle_key_visual_mode('a');
+        editor.handle_key_visual_mode('w');
+        assert!(editor.mode == EditorMode::Visual);
+        assert_eq!(
+            editor.visual_bounds(),
+            (Position::new(0, 1), Position::new(3, 1))
+        );
+    }
+
+    #[test]
+    fn test_repeat_change() {
+        let buffer = Buffer::from_string("one two three four\r\nfive".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            keys.chars()
+                .for_each(|key| editor.handle_key_normal_mode(key))
+        };
+        let insert = |editor: &mut Editor, text: &str| {
+            text.chars()
+                .for_each(|key| editor.handle_key_insert_mode(Key::Char(key)));
+            editor.change_mode(EditorMode::Normal);
+        };
+
+        keys(&mut editor, "dw.");
+        assert_eq!(editor.buffer().get_line(0), "three four");
+
+        // a change repeats with the typed text
+        keys(&mut editor, "cw");
+        insert(&mut editor, "3");
+        keys(&mut editor, "w.");
+        assert_eq!(editor.buffer().get_line(0), "3 3");
+        editor.undo();
+        assert_eq!(editor.buffer().get_line(0), "3 four");
+
+        // a new count replaces the count of the change
+        keys(&mut editor, "A");
+        insert(&mut editor, "!");
+        keys(&mut editor, "3.");
+        assert_eq!(editor.buffer().get_line(0), "3 four!!!!");
+
+        keys(&mut editor, "yyjp2.");
+        assert_eq!(editor.buffer().get_total_lines(), 5);
+        assert_eq!(editor.buffer().get_line(4), "3 four!!!!");
+    }
+
+    #[test]
+    fn test_char_search() {
+        let buffer = Buffer::from_string("call(a, b, c), (d)\r\nx".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            key_notation::from_text(keys)
+                .into_iter()
+                .for_each(|key| editor.handle_key(key))
+        };
+
+        keys(&mut editor, "2f,");
+        assert_eq!(editor.cursor_position, Position::new(9, 0));
+        keys(&mut editor, ";");
+        assert_eq!(editor.cursor_position, Position::new(13, 0));
+        keys(&mut editor, ",");
+        assert_eq!(editor.cursor_position, Position::new(9, 0));
+        keys(&mut editor, "Fa");
+        assert_eq!(editor.cursor_position, Position::new(5, 0));
+        // a repeated `t` moves past the character it stopped before
+        keys(&mut editor, "t,;");
+        assert_eq!(editor.cursor_position, Position::new(8, 0));
+        keys(&mut editor, "T(");
+        assert_eq!(editor.cursor_position, Position::new(5, 0));
+        // not found: the cursor stays
+        keys(&mut editor, "fz3f(");
+        assert_eq!(editor.cursor_position, Position::new(5, 0));
+
+        keys(&mut editor, "dt,");
+        assert_eq!(editor.buffer().get_line(0), "call(, b, c), (d)");
+        keys(&mut editor, "0cf(f(<Esc>");
+        assert_eq!(editor.buffer().get_line(0), "f(, b, c), (d)");
+        keys(&mut editor, "f,.");
+        assert_eq!(editor.buffer().get_line(0), "f(, bf(d)");
+        keys(&mut editor, "f)vF(d");
+        assert_eq!(editor.buffer().get_line(0), "f(, bf");
+    }
+
+    #[test]
+    fn test_macros() {
+        let buffer = Buffer::from_string("a = 1\r\nb = 2\r\nc = 3\r\nd = 4".to_string());
+        let mut editor = test_editor(buffer);
+        let keys = |editor: &mut Editor, keys: &str| {
+            key_notation::from_text(keys)
+                .into_iter()
+                .for_each(|key| editor.handle_key(key))
+        };
+
+        keys(&mut editor, "qa");
+        assert_eq!(editor.recording, Some(('a', vec![])));
+        // keys are recorded as they are read from the terminal
+        for key in key_notation::from_text("A;<Esc>jq") {
+            editor.recording.as_mut().unwrap().1.push(key);
+            editor.handle_key(key);
+        }
+        assert_eq!(editor.recording, None);
+        assert_eq!(editor.registers.get('a').unwrap().text, "A;<Esc>j");
+        assert_eq!(editor.registers.get(register::UNNAMED), None);
+        assert_eq!(editor.buffer().get_line(0), "a = 1;");
+
+        keys(&mut editor, "@a");
+        assert_eq!(editor.buffer().get_line(1), "b = 2;");
+        keys(&mut editor, "2@@");
+        assert_eq!(editor.buffer().get_line(2), "c = 3;");
+        assert_eq!(editor.buffer().get_line(3), "d = 4;");
+
+        // a recursive macro stops
+        editor.registers.set_named(
+            'b',
+            Register {
+                text: "x@b".to_string(),
+                kind: RegisterKind::Chars,
+            },
+        );
+        keys(&mut editor, "@b");
+        assert_eq!(editor.macro_depth, 0);
+    }
 }
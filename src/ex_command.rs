@@ -0,0 +1,283 @@
+use crate::normal_command::MAX_COUNT;
+
+/// Line of the range of an ex command, before it is looked up in the buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    /// Line number, counting from 1. 0 is before the first line, e.g. for
+    /// `:m 0`.
+    Number(usize),
+    /// `.`, the cursor line.
+    Current,
+    /// `$`, the last line.
+    Last,
+    /// `'x`, the line of a mark.
+    Mark(char),
+    /// `/pattern/` (`forward`) or `?pattern?`: the next or previous line
+    /// containing the pattern.
+    Pattern { pattern: String, forward: bool },
+}
+
+/// Address with the lines added by `+N` and `-N` after it, e.g. `.+2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineAddress {
+    pub address: Address,
+    pub offset: isize,
+}
+
+impl LineAddress {
+    pub fn new(address: Address) -> LineAddress {
+        LineAddress { address, offset: 0 }
+    }
+}
+
+/// Command typed after `:`, e.g. `'a,'bd x` or `%normal A;`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExCommand {
+    /// Addresses of the range: none, a single line, or the first and the
+    /// last line. `%` is `1,$`.
+    pub range: Vec<LineAddress>,
+    /// Name of the command, empty for a range alone (`:12` goes to line 12).
+    pub name: String,
+    /// `!` after the name, e.g. `:j!`.
+    pub bang: bool,
+    pub argument: String,
+}
+
+/// Parse `[range]{name}[!] [argument]`.
+pub fn parse(text: &str) -> Result<ExCommand, String> {
+    let text = text.trim_start();
+    let (range, rest) = match text.strip_prefix('%') {
+        Some(rest) => (
+            vec![
+                LineAddress::new(Address::Number(1)),
+                LineAddress::new(Address::Last),
+            ],
+            rest,
+        ),
+        None => match parse_address(text)? {
+            (None, rest) => (vec![], rest),
+            (Some(first), rest) => match rest.strip_prefix(',') {
+                Some(rest) => {
+                    let (last, rest) = parse_address(rest)?;
+                    let last = last.unwrap_or(LineAddress::new(Address::Current));
+                    (vec![first, last], rest)
+                }
+                None => (vec![first], rest),
+            },
+        },
+    };
+
+    let rest = rest.trim_start();
+    let name_length = match rest.chars().next() {
+        Some(ch) if ch.is_ascii_alphabetic() => rest
+            .find(|ch: char| !ch.is_ascii_alphabetic())
+            .unwrap_or(rest.len()),
+        // `:>>` shifts twice
+        Some(shift @ ('>' | '<')) => rest.find(|ch| ch != shift).unwrap_or(rest.len()),
+        Some(ch) => ch.len_utf8(),
+        None => 0,
+    };
+    let (name, rest) = rest.split_at(name_length);
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    Ok(ExCommand {
+        range,
+        name: name.to_string(),
+        bang,
+        argument: rest.trim_start().to_string(),
+    })
+}
+
+/// Parse an address at the start of the text, e.g. `.`, `'a+1` or `/fn/`.
+/// Returns the address, if there is one, and the rest of the text.
+pub fn parse_address(text: &str) -> Result<(Option<LineAddress>, &str), String> {
+    let text = text.trim_start();
+    let (address, mut rest) = match text.chars().next() {
+        Some('.') => (Some(Address::Current), &text[1..]),
+        Some('$') => (Some(Address::Last), &text[1..]),
+        Some('\'') => {
+            let mark = text[1..]
+                .chars()
+                .next()
+                .ok_or_else(|| "Missing mark".to_string())?;
+            (Some(Address::Mark(mark)), &text[1 + mark.len_utf8()..])
+        }
+        Some(delimiter @ ('/' | '?')) => {
+            let (pattern, rest) = split_pattern(&text[1..], delimiter);
+            let forward = delimiter == '/';
+            (Some(Address::Pattern { pattern, forward }), rest)
+        }
+        Some(ch) if ch.is_ascii_digit() => {
+            let (number, rest) = split_number(text);
+            let number = number.parse().map_err(|_| "Invalid range".to_string())?;
+            (Some(Address::Number(number)), rest)
+        }
+        _ => (None, text),
+    };
+
+    let mut offset: isize = 0;
+    while let Some(sign) = rest.chars().next().filter(|ch| matches!(ch, '+' | '-')) {
+        let (number, after) = split_number(&rest[1..]);
+        let lines: isize = match number {
+            "" => 1,
+            _ => number.parse().map_err(|_| "Invalid range".to_string())?,
+        };
+        offset += if sign == '+' { lines } else { -lines };
+        rest = after;
+    }
+
+    // `+N` alone counts from the cursor line
+    let address = match (address, offset) {
+        (None, 0) => return Ok((None, text)),
+        (address, _) => address.unwrap_or(Address::Current),
+    };
+    Ok((Some(LineAddress { address, offset }), rest))
+}
+
+/// Split the digits at the start of the text from the rest.
+fn split_number(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(text.len());
+    text.split_at(end)
+}
+
+/// Split a pattern ending with `delimiter` from the rest of the text. A
+/// delimiter inside of the pattern is escaped with `\`. Without a closing
+/// delimiter, the pattern goes to the end of the text.
+pub fn split_pattern(text: &str, delimiter: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        if ch == delimiter {
+            return (pattern, &text[i + ch.len_utf8()..]);
+        }
+        if ch == '\\' {
+            match chars.next() {
+                Some((_, escaped)) if escaped == delimiter => pattern.push(escaped),
+                Some((_, escaped)) => {
+                    pattern.push(ch);
+                    pattern.push(escaped);
+                }
+                None => pattern.push(ch),
+            }
+            continue;
+        }
+        pattern.push(ch);
+    }
+    (pattern, "")
+}
+
+/// Register and count given to `:d` and `:y`, e.g. `x 3`.
+pub fn register_and_count(argument: &str) -> Result<(Option<char>, Option<usize>), String> {
+    let argument = argument.trim();
+    let (register, rest) = match argument.chars().next() {
+        Some(ch) if !ch.is_ascii_digit() => (Some(ch), argument[ch.len_utf8()..].trim_start()),
+        _ => (None, argument),
+    };
+    // a large count is cut down, as in Normal mode
+    let count = match rest {
+        "" => None,
+        _ => match rest.parse::<usize>() {
+            Ok(count) if count > 0 => Some(std::cmp::min(count, MAX_COUNT)),
+            Err(_) if rest.bytes().all(|b| b.is_ascii_digit()) => Some(MAX_COUNT),
+            _ => return Err(format!("Trailing characters: {}", rest)),
+        },
+    };
+    Ok((register, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("12").unwrap(),
+            ExCommand {
+                range: vec![LineAddress::new(Address::Number(12))],
+                name: String::new(),
+                bang: false,
+                argument: String::new(),
+            }
+        );
+        assert_eq!(
+            parse("'a,'b-1d x").unwrap(),
+            ExCommand {
+                range: vec![
+                    LineAddress::new(Address::Mark('a')),
+                    LineAddress {
+                        address: Address::Mark('b'),
+                        offset: -1
+                    },
+                ],
+                name: "d".to_string(),
+                bang: false,
+                argument: "x".to_string(),
+            }
+        );
+
+        let command = parse("%normal A;").unwrap();
+        assert_eq!(command.range.len(), 2);
+        assert_eq!(command.range[1].address, Address::Last);
+        assert_eq!(command.name, "normal");
+        assert_eq!(command.argument, "A;");
+
+        let command = parse(".,+2>>").unwrap();
+        assert_eq!(
+            command.range[1],
+            LineAddress {
+                address: Address::Current,
+                offset: 2
+            }
+        );
+        assert_eq!(command.name, ">>");
+
+        let command = parse("/a\\/b/,?c?j!").unwrap();
+        assert_eq!(
+            command.range[0].address,
+            Address::Pattern {
+                pattern: "a/b".to_string(),
+                forward: true
+            }
+        );
+        assert_eq!(
+            command.range[1].address,
+            Address::Pattern {
+                pattern: "c".to_string(),
+                forward: false
+            }
+        );
+        assert_eq!(command.name, "j");
+        assert!(command.bang);
+
+        let command = parse("m0").unwrap();
+        assert_eq!(command.name, "m");
+        assert_eq!(
+            parse_address(&command.argument).unwrap(),
+            (Some(LineAddress::new(Address::Number(0))), "")
+        );
+        assert_eq!(parse("'").unwrap_err(), "Missing mark");
+    }
+
+    #[test]
+    fn test_register_and_count() {
+        assert_eq!(register_and_count(""), Ok((None, None)));
+        assert_eq!(register_and_count("a"), Ok((Some('a'), None)));
+        assert_eq!(register_and_count("a 3"), Ok((Some('a'), Some(3))));
+        assert_eq!(register_and_count("3"), Ok((None, Some(3))));
+        assert!(register_and_count("a b").is_err());
+        assert!(register_and_count("0").is_err());
+        assert_eq!(
+            register_and_count("18446744073709551615"),
+            Ok((None, Some(MAX_COUNT)))
+        );
+        assert_eq!(
+            register_and_count("a 99999999999999999999999"),
+            Ok((Some('a'), Some(MAX_COUNT)))
+        );
+    }
+}
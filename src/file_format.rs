@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+
+/// Line ending style of a file. Buffers always store `\r\n` internally and
+/// convert to the file format when saving.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    #[default]
+    Unix,
+    Dos,
+}
+
+impl FileFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FileFormat> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            _ => None,
+        }
+    }
+
+    /// Detect the file format of the text. Returns the most used line ending
+    /// and whether both kinds of line endings are used.
+    /// Text without line breaks is treated as unix.
+    pub fn detect(text: &str) -> (FileFormat, bool) {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+
+        let format = if crlf > lf {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        };
+        (format, crlf > 0 && lf > 0)
+    }
+
+    /// Convert text in the internal representation (`\r\n`) to this format.
+    pub fn convert(&self, text: &str) -> String {
+        match self {
+            FileFormat::Unix => text.replace("\r\n", "\n"),
+            FileFormat::Dos => text.to_string(),
+        }
+    }
+
+    /// Convert text of a file with mixed line endings. The line breaks at
+    /// `lf_line_breaks` (offsets of their `\r\n`) are written as `\n`, all
+    /// others in this format.
+    pub fn convert_mixed(&self, text: &str, lf_line_breaks: &BTreeSet<usize>) -> String {
+        let mut converted = String::with_capacity(text.len());
+        let mut last = 0;
+        for (offset, _) in text.match_indices("\r\n") {
+            converted.push_str(&text[last..offset]);
+            converted.push_str(match lf_line_breaks.contains(&offset) {
+                true => "\n",
+                false => self.line_ending(),
+            });
+            last = offset + 2;
+        }
+        converted.push_str(&text[last..]);
+        converted
+    }
+
+    fn line_ending(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+        }
+    }
+}
+
+/// Offsets of the line breaks, which are a single `\n` in the text, after
+/// it is normalized.
+pub fn lf_line_breaks(text: &str) -> BTreeSet<usize> {
+    let mut line_breaks = BTreeSet::new();
+    let mut added = 0;
+    for (offset, _) in text.match_indices('\n') {
+        if !text[..offset].ends_with('\r') {
+            line_breaks.insert(offset + added);
+            added += 1;
+        }
+    }
+    line_breaks
+}
+
+/// Convert all line endings to the internal representation (`\r\n`).
+pub fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut last = '\0';
+    for ch in text.chars() {
+        if ch == '\n' && last != '\r' {
+            normalized.push('\r');
+        }
+        normalized.push(ch);
+        last = ch;
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(FileFormat::detect("a\nb\n"), (FileFormat::Unix, false));
+        assert_eq!(FileFormat::detect("a\r\nb\r\n"), (FileFormat::Dos, false));
+        assert_eq!(FileFormat::detect("a\r\nb\r\nc\n"), (FileFormat::Dos, true));
+        assert_eq!(FileFormat::detect("a\r\nb\nc\n"), (FileFormat::Unix, true));
+        assert_eq!(FileFormat::detect("a"), (FileFormat::Unix, false));
+    }
+
+    #[test]
+    fn test_normalize_and_convert() {
+        let text = "a\nb\r\nc\n";
+        assert_eq!(normalize(text), "a\r\nb\r\nc\r\n");
+        assert_eq!(FileFormat::Unix.convert(&normalize(text)), "a\nb\nc\n");
+        assert_eq!(FileFormat::Dos.convert(&normalize(text)), "a\r\nb\r\nc\r\n");
+
+        let line_breaks = lf_line_breaks(text);
+        assert_eq!(line_breaks, BTreeSet::from([1, 7]));
+        assert_eq!(
+            FileFormat::Dos.convert_mixed(&normalize(text), &line_breaks),
+            text
+        );
+        assert_eq!(
+            FileFormat::Unix.convert_mixed(&normalize(text), &BTreeSet::new()),
+            "a\nb\nc\n"
+        );
+    }
+}
@@ -3,4 +3,3 @@ use crate::terminal::Terminal;
 pub trait Highlighter {
     fn highlight(&self, content: &str, terminal: &Terminal);
 }
-
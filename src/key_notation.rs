@@ -0,0 +1,123 @@
+use termion::event::Key;
+
+/// Names of the keys without a character, as written in angle brackets.
+const NAMES: [(&str, Key); 15] = [
+    ("Esc", Key::Esc),
+    ("CR", Key::Char('\n')),
+    ("BS", Key::Backspace),
+    ("Del", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("S-Tab", Key::BackTab),
+    ("lt", Key::Char('<')),
+];
+
+/// Keys as text, e.g. for a recorded macro in a register. Keys without a
+/// character are written like in vim: `<Esc>`, `<CR>`, `<C-r>`, `<F5>`.
+/// `<` is written as `<lt>`.
+pub fn to_text(keys: &[Key]) -> String {
+    let mut text = String::new();
+    for key in keys {
+        if let Some((name, _)) = NAMES.iter().find(|(_, named)| named == key) {
+            text.push_str(&format!("<{}>", name));
+            continue;
+        }
+        match key {
+            Key::Char(ch) => text.push(*ch),
+            Key::Ctrl(ch) => text.push_str(&format!("<C-{}>", ch)),
+            Key::Alt(ch) => text.push_str(&format!("<M-{}>", ch)),
+            Key::F(n) => text.push_str(&format!("<F{}>", n)),
+            _ => (),
+        }
+    }
+    text
+}
+
+/// Keys of a text written by `to_text`. Other text is typed as it is,
+/// except for carriage returns.
+pub fn from_text(text: &str) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        rest = &rest[ch.len_utf8()..];
+        if ch == '<' {
+            let key = rest
+                .find('>')
+                .and_then(|end| Some((parse_name(&rest[..end])?, end)));
+            if let Some((key, end)) = key {
+                keys.push(key);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        if ch != '\r' {
+            keys.push(Key::Char(ch));
+        }
+    }
+    keys
+}
+
+fn parse_name(name: &str) -> Option<Key> {
+    if let Some((_, key)) = NAMES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+    {
+        return Some(*key);
+    }
+
+    let single_char = |text: &str| {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(ch),
+            _ => None,
+        }
+    };
+    if let Some(ch) = name.strip_prefix("C-").and_then(single_char) {
+        return Some(Key::Ctrl(ch));
+    }
+    if let Some(ch) = name.strip_prefix("M-").and_then(single_char) {
+        return Some(Key::Alt(ch));
+    }
+    name.strip_prefix('F')?.parse().ok().map(Key::F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_notation() {
+        let keys = vec![
+            Key::Char('c'),
+            Key::Char('w'),
+            Key::Char('<'),
+            Key::Char('\n'),
+            Key::Esc,
+            Key::Ctrl('r'),
+            Key::F(5),
+            Key::Down,
+        ];
+        let text = to_text(&keys);
+        assert_eq!(text, "cw<lt><CR><Esc><C-r><F5><Down>");
+        assert_eq!(from_text(&text), keys);
+
+        assert_eq!(
+            from_text("a<b>\r\n<esc>"),
+            vec![
+                Key::Char('a'),
+                Key::Char('<'),
+                Key::Char('b'),
+                Key::Char('>'),
+                Key::Char('\n'),
+                Key::Esc
+            ]
+        );
+    }
+}
@@ -1,13 +1,34 @@
 pub mod buffer;
+pub mod clipboard;
+pub mod diff;
 pub mod editor;
+pub mod ex_command;
 pub mod file_extension;
+pub mod file_format;
 pub mod highlighter;
 pub mod highlighter_rust;
 pub mod highlighter_toml;
+pub mod key_notation;
+pub mod marks;
+pub mod motion;
+pub mod normal_command;
+pub mod operator;
 pub mod piece;
 pub mod piece_table;
+pub mod piece_tree;
 pub mod position;
+pub mod regex;
+pub mod register;
+pub mod substitute;
+pub mod swap_file;
 pub mod terminal;
+#[cfg(test)]
+mod test_dir;
+pub mod text_object;
 pub mod tokenizer;
 pub mod tokenizer_rust;
 pub mod tokenizer_toml;
+pub mod undo_history;
+pub mod unicode;
+pub mod window;
+pub mod word;
@@ -1,25 +1,74 @@
 use nimbus_text_editor::{buffer::Buffer, editor::Editor};
-use std::{env, io::Error, process};
+use std::{
+    env,
+    io::{self, BufRead, Error, Write},
+};
 
-fn main() -> Result<(), Error> {
-    let args: Vec<String> = env::args().skip(1).collect();
-
-    let file_name = match args.len() {
-        0 => None,
-        1 => Some(args[0].as_str()),
-        2.. => {
-            eprintln!("Too many arguments!");
-            eprintln!("Usage: program [file_name]?");
-            process::exit(1);
+/// Ask what to do with a swap file left behind by an earlier session.
+/// Returns false if the user chose to quit.
+fn handle_swap_file(buffer: &mut Buffer) -> Result<bool, Error> {
+    let file_path = buffer.file_path().unwrap_or_default().to_string();
+    let matches = buffer.swap_file_matches();
+
+    println!("Found a swap file for {}.", file_path);
+    if matches {
+        println!("It contains unsaved changes of an earlier session, which did not exit cleanly.");
+    } else {
+        println!("The file was changed since the swap file was written, it can't be recovered.");
+    }
+
+    let stdin = io::stdin();
+    loop {
+        if matches {
+            print!("[r]ecover, [d]iff, discard [x], [q]uit: ");
+        } else {
+            print!("discard [x], [q]uit: ");
         }
-    };
+        io::stdout().flush()?;
 
-    let buffer = match file_name {
-        Some(path) => Buffer::from_file(path)?,
-        None => Buffer::default(),
-    };
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            return Ok(false);
+        }
+
+        match answer.trim() {
+            "r" if matches => {
+                buffer.recover_swap_file();
+                return Ok(true);
+            }
+            "d" if matches => {
+                for line in buffer.swap_file_diff() {
+                    println!("{}", line);
+                }
+            }
+            "x" => {
+                buffer.discard_swap_file()?;
+                return Ok(true);
+            }
+            "q" => return Ok(false),
+            _ => (),
+        }
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let paths: Vec<String> = env::args().skip(1).collect();
+
+    let mut buffers = vec![];
+    for path in paths.iter() {
+        let mut buffer = Buffer::from_file(path)?;
+        if buffer.has_swap_file() && !handle_swap_file(&mut buffer)? {
+            return Ok(());
+        }
+        buffers.push(buffer);
+    }
 
-    let mut editor = Editor::new(buffer)?;
+    let mut buffers = buffers.into_iter();
+    let mut editor = Editor::new(buffers.next().unwrap_or_default())?;
+    for buffer in buffers {
+        editor.add_buffer(buffer);
+    }
+    editor.load_file_marks();
     editor.run();
     Ok(())
 }
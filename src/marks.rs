@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::position::Position;
+use crate::undo_history;
+
+/// Number of positions kept in the jump list.
+const MAX_JUMPS: usize = 100;
+
+/// Mark `'`, the position before the latest jump.
+pub const LAST_JUMP: char = '\'';
+
+/// Whether `m{name}` can set the mark. `a` to `z` are marks of a buffer,
+/// `A` to `Z` are file marks, which are kept between sessions.
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphabetic() || name == LAST_JUMP
+}
+
+pub fn is_file_mark(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+/// File mark of a file, which is not open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMark {
+    pub path: String,
+    pub position: Position,
+}
+
+/// File where the file marks are stored: `$XDG_CACHE_HOME/nimbus/marks`.
+pub fn marks_path() -> Option<PathBuf> {
+    Some(undo_history::nimbus_dir()?.join("marks"))
+}
+
+/// Absolute path of a file, which is used to find file marks of the file.
+pub fn canonical_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Read the file marks. Every line holds the name, line, column and path of
+/// a mark, separated by spaces.
+pub fn read(path: &Path) -> io::Result<HashMap<char, FileMark>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut marks = HashMap::new();
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.splitn(4, ' ').collect();
+        let [name, y, x, path] = fields.as_slice() else {
+            continue;
+        };
+        let (Some(name), Ok(y), Ok(x)) = (name.chars().next(), y.parse(), x.parse()) else {
+            continue;
+        };
+        if is_file_mark(name) {
+            let position = Position::new(x, y);
+            let path = path.to_string();
+            marks.insert(name, FileMark { path, position });
+        }
+    }
+    Ok(marks)
+}
+
+pub fn write(path: &Path, marks: &HashMap<char, FileMark>) -> io::Result<()> {
+    let mut names: Vec<&char> = marks.keys().collect();
+    names.sort();
+    let contents: String = names
+        .into_iter()
+        .map(|name| {
+            let mark = &marks[name];
+            format!(
+                "{} {} {} {}\n",
+                name, mark.position.y, mark.position.x, mark.path
+            )
+        })
+        .collect();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)
+}
+
+/// Position in a buffer, from where a jump started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Jump {
+    pub buffer: usize,
+    pub position: Position,
+}
+
+/// Positions before large jumps (searching, `G`, `%`, marks, ...). `Ctrl-o`
+/// goes back through them and `Ctrl-i` forward again.
+#[derive(Debug, Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// Entry `Ctrl-o` and `Ctrl-i` moved to last. It is the length of the
+    /// list, if they were not used since the last jump.
+    index: usize,
+}
+
+impl JumpList {
+    /// Remember the position before a jump. An older entry for the same line
+    /// is dropped.
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps
+            .retain(|old| old.buffer != jump.buffer || old.position.y != jump.position.y);
+        self.jumps.push(jump);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+
+    /// Previous position (`Ctrl-o`). When starting to go back, the current
+    /// position is remembered, so `Ctrl-i` can return to it.
+    pub fn back(&mut self, current: Jump) -> Option<Jump> {
+        if self.jumps.is_empty() {
+            return None;
+        }
+        if self.index == self.jumps.len() {
+            self.push(current);
+            self.index = self.jumps.len() - 1;
+        }
+        self.index = self.index.checked_sub(1)?;
+        Some(self.jumps[self.index])
+    }
+
+    /// Next position (`Ctrl-i`), after going back.
+    pub fn forward(&mut self) -> Option<Jump> {
+        if self.index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.jumps[self.index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_marks_roundtrip() {
+        let path = std::env::temp_dir().join(format!("nimbus-marks-test-{}", std::process::id()));
+        let mut marks = HashMap::new();
+        marks.insert(
+            'A',
+            FileMark {
+                path: "/tmp/a file.rs".to_string(),
+                position: Position::new(4, 12),
+            },
+        );
+        marks.insert(
+            'B',
+            FileMark {
+                path: "/tmp/b.rs".to_string(),
+                position: Position::new(0, 0),
+            },
+        );
+
+        write(&path, &marks).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "A 12 4 /tmp/a file.rs\nB 0 0 /tmp/b.rs\n"
+        );
+        assert_eq!(read(&path).unwrap(), marks);
+        fs::remove_file(&path).unwrap();
+        assert!(read(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_jump_list() {
+        let jump = |y| Jump {
+            buffer: 0,
+            position: Position::new(0, y),
+        };
+        let mut jumps = JumpList::default();
+        assert_eq!(jumps.back(jump(1)), None);
+
+        jumps.push(jump(10));
+        jumps.push(jump(20));
+        jumps.push(jump(10));
+        assert_eq!(jumps.back(jump(30)), Some(jump(10)));
+        assert_eq!(jumps.back(jump(10)), Some(jump(20)));
+        assert_eq!(jumps.back(jump(20)), None);
+        assert_eq!(jumps.forward(), Some(jump(10)));
+        assert_eq!(jumps.forward(), Some(jump(30)));
+        assert_eq!(jumps.forward(), None);
+    }
+}
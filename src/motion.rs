@@ -0,0 +1,170 @@
+use std::ops::Range;
+
+/// Brackets `%` jumps between.
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Line `}` (`forward`) or `{` moves to from line `y`: the next or previous
+/// empty line after a non-empty line. None if there is no such line.
+pub fn paragraph(
+    y: usize,
+    total_lines: usize,
+    forward: bool,
+    is_empty: impl Fn(usize) -> bool,
+) -> Option<usize> {
+    let mut lines: Box<dyn Iterator<Item = usize>> = match forward {
+        true => Box::new(y..total_lines),
+        false => Box::new((0..=y).rev()),
+    };
+    lines.find(|y| !is_empty(*y))?;
+    lines.find(|y| is_empty(*y))
+}
+
+/// Byte offset `)` (`forward`) or `(` moves to from the byte offset of the
+/// text: the start of the next sentence, or of the current or previous one.
+/// None if there is no such sentence.
+pub fn sentence(text: &str, offset: usize, forward: bool) -> Option<usize> {
+    let starts = sentence_starts(text);
+    match forward {
+        true => starts.into_iter().find(|start| *start > offset),
+        false => starts.into_iter().rev().find(|start| *start < offset),
+    }
+}
+
+/// Byte offsets where sentences start. A sentence ends with `.`, `!` or `?`,
+/// optionally followed by closing brackets and quotes, and then by white
+/// space. Empty lines also start a sentence, as do the lines after them.
+fn sentence_starts(text: &str) -> Vec<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut starts = vec![];
+    let mut at_start = true;
+    // start of the line, while it has no other characters than line breaks
+    let mut line_start = Some(0);
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, ch) = chars[i];
+        i += 1;
+        if ch == '\r' && chars.get(i).is_some_and(|(_, next)| *next == '\n') {
+            continue;
+        }
+        if ch == '\n' {
+            if let Some(start) = line_start {
+                starts.push(start);
+                at_start = true;
+            }
+            line_start = Some(offset + 1);
+            continue;
+        }
+        line_start = None;
+        if ch.is_whitespace() {
+            continue;
+        }
+        if at_start {
+            starts.push(offset);
+            at_start = false;
+        }
+        if matches!(ch, '.' | '!' | '?') {
+            while chars
+                .get(i)
+                .is_some_and(|(_, ch)| matches!(ch, ')' | ']' | '"' | '\''))
+            {
+                i += 1;
+            }
+            at_start = chars.get(i).is_none_or(|(_, ch)| ch.is_whitespace());
+        }
+    }
+    starts
+}
+
+/// Byte offset of the bracket matching the first bracket at or after the
+/// byte offset, in the same line. Brackets in the ranges `skip` (strings and
+/// comments) are ignored, unless the first bracket is in the same range.
+pub fn matching_bracket(text: &str, offset: usize, skip: &[Range<usize>]) -> Option<usize> {
+    let range_at = |i: usize| skip.iter().find(|range| range.contains(&i));
+    let line_end = text[offset..]
+        .find('\n')
+        .map_or(text.len(), |end| offset + end);
+    let (start, bracket) = text[offset..line_end]
+        .char_indices()
+        .map(|(i, ch)| (offset + i, ch))
+        .find(|(i, ch)| {
+            BRACKETS
+                .iter()
+                .any(|(open, close)| ch == open || ch == close)
+                && range_at(*i) == range_at(offset)
+        })?;
+    let bracket_range = range_at(start);
+    let skipped = |i: usize| range_at(i) != bracket_range && range_at(i).is_some();
+
+    let (open, close) = *BRACKETS
+        .iter()
+        .find(|(open, close)| bracket == *open || bracket == *close)?;
+    let mut depth = 0;
+    let mut matches = |(i, ch): (usize, char)| {
+        if skipped(i) {
+            return false;
+        }
+        if ch == bracket {
+            depth += 1;
+        } else if ch == open || ch == close {
+            depth -= 1;
+        }
+        depth == 0
+    };
+    match bracket == open {
+        true => text[start..]
+            .char_indices()
+            .map(|(i, ch)| (start + i, ch))
+            .find(|found| matches(*found))
+            .map(|(i, _)| i),
+        false => text[..start + 1]
+            .char_indices()
+            .rev()
+            .find(|found| matches(*found))
+            .map(|(i, _)| i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paragraph() {
+        let lines = ["a", "b", "", "", "c", "", "d"];
+        let is_empty = |y: usize| lines[y].is_empty();
+        assert_eq!(paragraph(0, lines.len(), true, is_empty), Some(2));
+        assert_eq!(paragraph(2, lines.len(), true, is_empty), Some(5));
+        assert_eq!(paragraph(5, lines.len(), true, is_empty), None);
+        assert_eq!(paragraph(6, lines.len(), false, is_empty), Some(5));
+        assert_eq!(paragraph(4, lines.len(), false, is_empty), Some(3));
+        assert_eq!(paragraph(1, lines.len(), false, is_empty), None);
+    }
+
+    #[test]
+    fn test_sentence() {
+        let text = "One (two). Three?\" e.g.x\r\n\r\nFour";
+        assert_eq!(sentence_starts(text), vec![0, 11, 19, 26, 28]);
+        assert_eq!(sentence(text, 0, true), Some(11));
+        assert_eq!(sentence(text, 14, true), Some(19));
+        assert_eq!(sentence(text, 19, true), Some(26));
+        assert_eq!(sentence(text, 28, true), None);
+        assert_eq!(sentence(text, 14, false), Some(11));
+        assert_eq!(sentence(text, 11, false), Some(0));
+        assert_eq!(sentence(text, 0, false), None);
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let text = "if f(a[0], \")\") { g() } // }\r\n}";
+        assert_eq!(matching_bracket(text, 0, &[]), Some(12));
+        let skip = [11..14, 24..28];
+        assert_eq!(matching_bracket(text, 0, &skip), Some(14));
+        assert_eq!(matching_bracket(text, 14, &skip), Some(4));
+        assert_eq!(matching_bracket(text, 5, &skip), Some(8));
+        assert_eq!(matching_bracket(text, 15, &skip), Some(22));
+        assert_eq!(matching_bracket(text, 22, &skip), Some(16));
+        // inside of a comment, brackets of the comment are matched
+        assert_eq!(matching_bracket(text, 25, &skip), None);
+        assert_eq!(matching_bracket(text, 30, &skip), None);
+    }
+}
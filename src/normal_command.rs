@@ -0,0 +1,331 @@
+use std::{iter::Peekable, str::Chars};
+
+use crate::{operator::Operator, register::Registers};
+
+/// Largest count. A larger count is cut down to it, so a mistyped count, e.g.
+/// `999999999p`, does not keep the editor busy for hours.
+pub const MAX_COUNT: usize = 100_000;
+
+/// Command of Normal mode, parsed from the keys typed so far:
+/// `["x][count]{key}` or `["x][count]{operator}[count]{motion}`.
+#[derive(Debug, PartialEq)]
+pub enum NormalCommand {
+    /// The keys are the start of a command, more keys are needed.
+    Pending,
+    /// A motion or another command without an operator.
+    Key {
+        register: Option<char>,
+        count: Option<usize>,
+        key: char,
+        /// Key after a command which needs one, e.g. the `a` of `@a`.
+        argument: Option<char>,
+    },
+    /// Operator applied to the text the motion moves over. All counts are
+    /// multiplied. The operator key as motion (`dd`, `yy`) means whole lines.
+    Operator {
+        register: Option<char>,
+        operator: Operator,
+        count: Option<usize>,
+        motion: char,
+        /// Key after a motion which needs one, e.g. the `w` of `iw`.
+        argument: Option<char>,
+    },
+    /// The keys do not form a command.
+    Invalid,
+}
+
+/// Parse the keys typed in Normal mode.
+pub fn parse(keys: &str) -> NormalCommand {
+    let mut chars = keys.chars().peekable();
+    let (register, count) = match parse_prefix(&mut chars) {
+        Ok(prefix) => prefix,
+        Err(command) => return command,
+    };
+    let Some(key) = chars.next() else {
+        return NormalCommand::Pending;
+    };
+    let Some(operator) = Operator::from_key(key) else {
+        let argument = match key_needs_argument(key) {
+            true => match chars.next() {
+                Some(argument) => Some(argument),
+                None => return NormalCommand::Pending,
+            },
+            false => None,
+        };
+        return match chars.next() {
+            None => NormalCommand::Key {
+                register,
+                count,
+                key,
+                argument,
+            },
+            Some(_) => NormalCommand::Invalid,
+        };
+    };
+
+    let count = multiply(count, parse_count(&mut chars));
+    let Some(motion) = chars.next() else {
+        return NormalCommand::Pending;
+    };
+    if motion != key && Operator::from_key(motion).is_some() {
+        return NormalCommand::Invalid;
+    }
+    let argument = match motion_needs_argument(motion) {
+        true => match chars.next() {
+            Some(argument) => Some(argument),
+            None => return NormalCommand::Pending,
+        },
+        false => None,
+    };
+    match chars.next() {
+        None => NormalCommand::Operator {
+            register,
+            operator,
+            count,
+            motion,
+            argument,
+        },
+        Some(_) => NormalCommand::Invalid,
+    }
+}
+
+/// Whether the motion is followed by another key: `i` and `a` select a text
+/// object, e.g. `iw` or `a(`, `f`, `F`, `t` and `T` search a character,
+/// `g` starts a motion of two keys, e.g. `ge`, and `'` and `` ` `` take a mark.
+pub fn motion_needs_argument(motion: char) -> bool {
+    matches!(motion, 'i' | 'a' | 'f' | 'F' | 't' | 'T' | 'g' | '\'' | '`')
+}
+
+/// Whether the command is followed by another key: `q` and `@` take the
+/// register to record or play a macro, `f`, `F`, `t` and `T` a character,
+/// `g` the second key of a command, and `m`, `'` and `` ` `` a mark.
+pub fn key_needs_argument(key: char) -> bool {
+    matches!(
+        key,
+        'q' | '@' | 'f' | 'F' | 't' | 'T' | 'g' | 'm' | '\'' | '`'
+    )
+}
+
+/// Register selected in the keys typed so far, e.g. `"a` in Visual mode.
+pub fn register(keys: &str) -> Option<char> {
+    parse_prefix(&mut keys.chars().peekable()).ok()?.0
+}
+
+/// Parse `[count]["x][count]`.
+fn parse_prefix(
+    chars: &mut Peekable<Chars>,
+) -> Result<(Option<char>, Option<usize>), NormalCommand> {
+    let count = parse_count(chars);
+    if chars.peek() != Some(&'"') {
+        return Ok((None, count));
+    }
+    chars.next();
+    match chars.next() {
+        None => Err(NormalCommand::Pending),
+        Some(name) if Registers::is_valid(name) => {
+            Ok((Some(name), multiply(count, parse_count(chars))))
+        }
+        Some(_) => Err(NormalCommand::Invalid),
+    }
+}
+
+fn multiply(count: Option<usize>, other: Option<usize>) -> Option<usize> {
+    match (count, other) {
+        (Some(count), Some(other)) => Some(std::cmp::min(count.saturating_mul(other), MAX_COUNT)),
+        (count, other) => count.or(other),
+    }
+}
+
+/// Count in front of a key. A leading `0` is not a count, but a motion.
+fn parse_count(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut count: Option<usize> = None;
+    while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+        if count.is_none() && digit == 0 {
+            break;
+        }
+        let value = count
+            .unwrap_or(0)
+            .saturating_mul(10)
+            .saturating_add(digit as usize);
+        count = Some(std::cmp::min(value, MAX_COUNT));
+        chars.next();
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(""), NormalCommand::Pending);
+        assert_eq!(parse("1"), NormalCommand::Pending);
+        assert_eq!(
+            parse("12j"),
+            NormalCommand::Key {
+                register: None,
+                count: Some(12),
+                key: 'j',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("0"),
+            NormalCommand::Key {
+                register: None,
+                count: None,
+                key: '0',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("10"),
+            NormalCommand::Pending,
+            "0 after a digit belongs to the count"
+        );
+        assert_eq!(parse("2d"), NormalCommand::Pending);
+        assert_eq!(parse("2d3"), NormalCommand::Pending);
+        assert_eq!(
+            parse("2d3w"),
+            NormalCommand::Operator {
+                register: None,
+                operator: Operator::Delete,
+                count: Some(6),
+                motion: 'w',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("c$"),
+            NormalCommand::Operator {
+                register: None,
+                operator: Operator::Change,
+                count: None,
+                motion: '$',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("yy"),
+            NormalCommand::Operator {
+                register: None,
+                operator: Operator::Yank,
+                count: None,
+                motion: 'y',
+                argument: None
+            }
+        );
+        assert_eq!(parse("dy"), NormalCommand::Invalid);
+
+        assert_eq!(parse("\""), NormalCommand::Pending);
+        assert_eq!(parse("\"!"), NormalCommand::Invalid);
+        assert_eq!(
+            parse("2\"A3dd"),
+            NormalCommand::Operator {
+                register: Some('A'),
+                operator: Operator::Delete,
+                count: Some(6),
+                motion: 'd',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("\"_p"),
+            NormalCommand::Key {
+                register: Some('_'),
+                count: None,
+                key: 'p',
+                argument: None
+            }
+        );
+        assert_eq!(parse("3@"), NormalCommand::Pending);
+        assert_eq!(
+            parse("3@@"),
+            NormalCommand::Key {
+                register: None,
+                count: Some(3),
+                key: '@',
+                argument: Some('@')
+            }
+        );
+        assert_eq!(register("\"b"), Some('b'));
+
+        assert_eq!(parse("ci"), NormalCommand::Pending);
+        assert_eq!(
+            parse("ci\""),
+            NormalCommand::Operator {
+                register: None,
+                operator: Operator::Change,
+                count: None,
+                motion: 'i',
+                argument: Some('"')
+            }
+        );
+        assert_eq!(parse("dawx"), NormalCommand::Invalid);
+        assert_eq!(
+            parse("2dt)"),
+            NormalCommand::Operator {
+                register: None,
+                operator: Operator::Delete,
+                count: Some(2),
+                motion: 't',
+                argument: Some(')')
+            }
+        );
+        assert_eq!(
+            parse("Fa"),
+            NormalCommand::Key {
+                register: None,
+                count: None,
+                key: 'F',
+                argument: Some('a')
+            }
+        );
+        assert_eq!(register("2"), None);
+
+        assert_eq!(parse("m"), NormalCommand::Pending);
+        assert_eq!(
+            parse("d'a"),
+            NormalCommand::Operator {
+                register: None,
+                operator: Operator::Delete,
+                count: None,
+                motion: '\'',
+                argument: Some('a')
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_large_count() {
+        assert_eq!(
+            parse("99999999999999999999999j"),
+            NormalCommand::Key {
+                register: None,
+                count: Some(MAX_COUNT),
+                key: 'j',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("100001p"),
+            NormalCommand::Key {
+                register: None,
+                count: Some(MAX_COUNT),
+                key: 'p',
+                argument: None
+            }
+        );
+        assert_eq!(
+            parse("99999\"a99999d99999w"),
+            NormalCommand::Operator {
+                register: Some('a'),
+                operator: Operator::Delete,
+                count: Some(MAX_COUNT),
+                motion: 'w',
+                argument: None
+            }
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::ops::Range;
+
+use crate::register::RegisterKind;
+
+/// Number of spaces added or removed by the indent operators.
+pub const SHIFT_WIDTH: usize = 4;
+
+/// Operation on a range of text, selected in Visual mode or by a motion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Yank,
+    /// Delete and start Insert mode.
+    Change,
+    Indent,
+    Outdent,
+    ToggleCase,
+    Lowercase,
+    Uppercase,
+}
+
+impl Operator {
+    /// Operator for a key pressed in Normal mode, which is followed by a motion.
+    pub fn from_key(key: char) -> Option<Operator> {
+        match key {
+            'd' => Some(Operator::Delete),
+            'y' => Some(Operator::Yank),
+            'c' => Some(Operator::Change),
+            '>' => Some(Operator::Indent),
+            '<' => Some(Operator::Outdent),
+            _ => None,
+        }
+    }
+
+    /// Operator for a key pressed in Visual mode.
+    pub fn from_visual_key(key: char) -> Option<Operator> {
+        match key {
+            'd' | 'x' => Some(Operator::Delete),
+            'y' => Some(Operator::Yank),
+            'c' | 's' => Some(Operator::Change),
+            '>' => Some(Operator::Indent),
+            '<' => Some(Operator::Outdent),
+            '~' => Some(Operator::ToggleCase),
+            'u' => Some(Operator::Lowercase),
+            'U' => Some(Operator::Uppercase),
+            _ => None,
+        }
+    }
+
+    /// Changed text for the operators, which change the case of the text.
+    pub fn change_case(&self, text: &str) -> Option<String> {
+        match self {
+            Operator::ToggleCase => Some(
+                text.chars()
+                    .flat_map(|ch| -> Box<dyn Iterator<Item = char>> {
+                        if ch.is_uppercase() {
+                            Box::new(ch.to_lowercase())
+                        } else {
+                            Box::new(ch.to_uppercase())
+                        }
+                    })
+                    .collect(),
+            ),
+            Operator::Lowercase => Some(text.to_lowercase()),
+            Operator::Uppercase => Some(text.to_uppercase()),
+            _ => None,
+        }
+    }
+}
+
+/// Text an operator applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextRange {
+    /// Byte range of the buffer.
+    Chars(Range<usize>),
+    /// Lines `first..=last`.
+    Lines(usize, usize),
+    /// Character columns `columns` of the lines `first..=last`.
+    Block {
+        first: usize,
+        last: usize,
+        columns: Range<usize>,
+    },
+}
+
+impl TextRange {
+    pub fn kind(&self) -> RegisterKind {
+        match self {
+            TextRange::Chars(_) => RegisterKind::Chars,
+            TextRange::Lines(..) => RegisterKind::Lines,
+            TextRange::Block { .. } => RegisterKind::Block,
+        }
+    }
+}
+
+/// Line with `SHIFT_WIDTH` spaces added in front. Empty lines stay empty.
+pub fn indent(line: &str) -> String {
+    if line.is_empty() {
+        return String::new();
+    }
+    format!("{}{}", " ".repeat(SHIFT_WIDTH), line)
+}
+
+/// Line with up to `SHIFT_WIDTH` leading spaces, or one leading tab, removed.
+pub fn outdent(line: &str) -> &str {
+    if let Some(rest) = line.strip_prefix('\t') {
+        return rest;
+    }
+    let spaces = line
+        .chars()
+        .take(SHIFT_WIDTH)
+        .take_while(|ch| *ch == ' ')
+        .count();
+    &line[spaces..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_case() {
+        assert_eq!(
+            Operator::ToggleCase.change_case("File is Read ß"),
+            Some("fILE IS rEAD SS".to_string())
+        );
+        assert_eq!(
+            Operator::Lowercase.change_case("GRÜSSE"),
+            Some("grüsse".to_string())
+        );
+        assert_eq!(Operator::Delete.change_case("File"), None);
+    }
+
+    #[test]
+    fn test_indent() {
+        assert_eq!(indent("fn main() {"), "    fn main() {");
+        assert_eq!(indent(""), "");
+        assert_eq!(outdent("      let x = 1;"), "  let x = 1;");
+        assert_eq!(outdent("  }"), "}");
+        assert_eq!(outdent("\t\tx"), "\tx");
+    }
+}
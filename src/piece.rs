@@ -4,7 +4,7 @@ pub enum Source {
     Add,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Piece {
     pub source: Source,
     pub offset: usize,
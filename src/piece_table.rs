@@ -1,124 +1,201 @@
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
 use std::ops::Range;
 
 use crate::piece::{Piece, Source};
+use crate::piece_tree::PieceTree;
 use crate::position::Position;
+use crate::regex::{Haystack, Regex};
+
+/// State of the piece list before a change, together with the logical
+/// offset at which the change happened (used to restore the cursor), and the
+/// marks and `\n` line breaks as they were in that state.
+#[derive(Debug, Clone)]
+pub struct UndoStep {
+    pub pieces: PieceTree,
+    pub offset: usize,
+    pub marks: HashMap<char, usize>,
+    pub lf_line_breaks: BTreeSet<usize>,
+}
+
+/// Change applied to the piece table. All changes since loading are kept in
+/// `PieceTable::journal`, so they can be written to a swap file and replayed
+/// after a crash.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Insert { offset: usize, text: String },
+    Delete { offset: usize, count: usize },
+    Undo,
+    Redo,
+    BeginUndoGroup,
+    EndUndoGroup,
+}
 
-#[derive(Default)]
+/// Piece table over an original (`data`) and an append-only (`add`) buffer.
+///
+/// `line_starts_data` and `line_starts_add` hold the offsets of all `\n`
+/// characters in the respective buffer. Together with the line break counts
+/// cached in the `PieceTree`, they allow to find lines in O(log n).
+#[derive(Default, Clone)]
 pub struct PieceTable {
     pub data: String,
     pub add: String,
-    pub pieces: Vec<Piece>,
+    pub pieces: PieceTree,
     pub line_starts_data: Vec<usize>,
     pub line_starts_add: Vec<usize>,
+    pub undo_stack: Vec<UndoStep>,
+    pub redo_stack: Vec<UndoStep>,
+    pub journal: Vec<Edit>,
+    /// Marks (`m{a-z}`) as logical offsets. Changes move them along with the
+    /// text they point to.
+    pub marks: HashMap<char, usize>,
+    /// Offsets of the line breaks, which are a single `\n` in a file with
+    /// mixed line endings. Changes move them along with the text like marks,
+    /// a deleted line break is dropped.
+    pub lf_line_breaks: BTreeSet<usize>,
+    undo_group: bool,
+    undo_group_recorded: bool,
+}
+/// Text of a piece table, read from the pieces without joining them. The
+/// piece read last is kept, so reading the text in order looks up every
+/// piece only once.
+struct PieceText<'a> {
+    table: &'a PieceTable,
+    /// Logical offset and piece read last.
+    piece: Cell<Option<(usize, Piece)>>,
 }
-impl PieceTable {
-    pub fn from_string(mut data: String) -> PieceTable {
-        if !data.contains("\r\n") {
-            data = data.replace('\n', "\r\n");
-        }
 
-        let mut new_lines: Vec<usize> = vec![0];
-        for (idx, ch) in data.chars().enumerate() {
-            if ch == '\n' {
-                new_lines.push(idx);
-                continue;
+impl<'a> PieceText<'a> {
+    /// Logical offset and text of the piece containing the byte at `offset`.
+    fn piece_at(&self, offset: usize) -> Option<(usize, &'a str)> {
+        let (start, piece) = match self.piece.get() {
+            Some((start, piece)) if (start..start + piece.length).contains(&offset) => {
+                (start, piece)
             }
-        }
+            _ => {
+                // the piece ending at `offset + 1` contains the byte
+                let location = self.table.pieces.find_by_offset(offset + 1)?;
+                self.piece.set(Some((location.start, location.piece)));
+                (location.start, location.piece)
+            }
+        };
+        Some((start, self.table.piece_text(&piece)))
+    }
+}
+
+impl Haystack for PieceText<'_> {
+    fn len(&self) -> usize {
+        self.table.len()
+    }
 
+    fn char_at(&self, offset: usize) -> Option<char> {
+        let (start, text) = self.piece_at(offset)?;
+        text[offset - start..].chars().next()
+    }
+
+    fn char_before(&self, offset: usize) -> Option<char> {
+        // pieces are split at character boundaries
+        let (start, text) = self.piece_at(offset.checked_sub(1)?)?;
+        text[..offset - start].chars().next_back()
+    }
+}
+
+impl PieceTable {
+    pub fn from_string(data: String) -> PieceTable {
+        let new_lines: Vec<usize> = data.match_indices('\n').map(|(idx, _)| idx).collect();
         let length = data.len();
 
-        PieceTable {
+        let mut piece_table = PieceTable {
             data,
-            add: String::new(),
-            pieces: vec![Piece::new(Source::Data, 0, length)],
             line_starts_data: new_lines,
-            line_starts_add: vec![],
-        }
+            ..Default::default()
+        };
+        piece_table.pieces =
+            piece_table.tree_from_pieces(vec![Piece::new(Source::Data, 0, length)]);
+        piece_table
     }
 
-    /// Find the piece that contains the logical offset and return its index and
-    /// offset into that buffer (data or add).
-    /// Given the 0 index in a piece buffer, it will return previous piece index.
-    /// This is done, so that insertation is easier in the previous piece.
-    pub fn find_piece_from_offset(&self, offset: usize) -> Option<(usize, usize)> {
-        let mut remaining_offset = offset;
-        for (idx, piece) in self.pieces.iter().enumerate() {
-            if remaining_offset <= piece.length {
-                return Some((idx, piece.offset + remaining_offset));
-            }
-            remaining_offset -= piece.length;
+    /// Build a piece tree, counting the line breaks of every piece.
+    pub fn tree_from_pieces(&self, pieces: Vec<Piece>) -> PieceTree {
+        PieceTree::from_pieces(pieces.into_iter().map(|p| (p, self.line_breaks_in(&p))))
+    }
+
+    fn line_starts(&self, source: Source) -> &Vec<usize> {
+        match source {
+            Source::Data => &self.line_starts_data,
+            Source::Add => &self.line_starts_add,
         }
-        None
     }
 
-    /// Iterate all pieces and count new lines and their offsets, until the
-    /// position y matches the count.
-    /// Panic if position does not have an offset (out of bounds).
-    pub fn get_offset_from_position(&self, position: &Position) -> Option<usize> {
-        let mut offset = 0;
-        let mut y = position.y;
+    /// Number of line breaks in the piece.
+    fn line_breaks_in(&self, piece: &Piece) -> usize {
+        self.line_breaks_until(piece, piece.length)
+    }
 
-        if y == 0 {
-            return Some(position.x);
-        }
+    /// Number of line breaks in the first `length` bytes of the piece.
+    fn line_breaks_until(&self, piece: &Piece, length: usize) -> usize {
+        let line_starts = self.line_starts(piece.source);
+        let start = line_starts.partition_point(|x| *x < piece.offset);
+        let end = line_starts.partition_point(|x| *x < piece.offset + length);
+        end - start
+    }
 
-        for piece in self.pieces.iter() {
-            let line_starts: &Vec<usize> = match piece.source {
-                Source::Data => &self.line_starts_data,
-                Source::Add => &self.line_starts_add,
-            };
+    /// Replace pieces in `range` by `pieces`, dropping empty ones.
+    fn splice(&mut self, range: Range<usize>, pieces: Vec<Piece>) {
+        let pieces: Vec<(Piece, usize)> = pieces
+            .into_iter()
+            .map(|p| (p, self.line_breaks_in(&p)))
+            .collect();
+        self.pieces.splice(range, pieces);
+    }
 
-            let line_starts_len = line_starts
-                .iter()
-                .filter(|x| **x != 0 && (piece.offset..piece.offset + piece.length).contains(x))
-                .count();
+    /// Total length of the visible contents in bytes.
+    pub fn len(&self) -> usize {
+        self.pieces.total_length()
+    }
 
-            if line_starts_len == 0 {
-                offset += piece.length;
-                continue;
-            }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-            if y > line_starts_len {
-                offset += piece.length;
-                y -= line_starts_len;
-                continue;
-            }
+    /// Find the piece that contains the logical offset and return its index and
+    /// offset into that buffer (data or add).
+    /// Given the 0 index in a piece buffer, it will return previous piece index.
+    /// This is done, so that insertation is easier in the previous piece.
+    ///
+    /// Runtime: O(log n), where n = # of pieces
+    pub fn find_piece_from_offset(&self, offset: usize) -> Option<(usize, usize)> {
+        let location = self.pieces.find_by_offset(offset)?;
+        Some((
+            location.index,
+            location.piece.offset + offset - location.start,
+        ))
+    }
 
-            if let Some(line_start) = line_starts
-                .iter()
-                .filter(|x| **x != 0 && (piece.offset..piece.offset + piece.length).contains(x))
-                .nth(y - 1)
-            {
-                offset += line_start + 1 - piece.offset;
-                y = 0;
-                break;
-            }
+    /// Logical offset of the first character of line `y`.
+    fn line_start_offset(&self, y: usize) -> Option<usize> {
+        if y == 0 {
+            return Some(0);
         }
 
-        if y > 0 {
-            // panic!("Position does not have a valid offset into data.")
-            return None;
-        }
+        let location = self.pieces.find_by_line_break(y)?;
+        let line_starts = self.line_starts(location.piece.source);
+        let first = line_starts.partition_point(|x| *x < location.piece.offset);
+        let new_line = line_starts[first + y - location.line_breaks_before - 1];
+        Some(location.start + new_line - location.piece.offset + 1)
+    }
 
-        Some(offset + position.x)
+    /// Find the offset of the line start for position y and add position x.
+    /// Returns None if the line does not exist.
+    ///
+    /// Runtime: O(log n), where n = # of pieces
+    pub fn get_offset_from_position(&self, position: &Position) -> Option<usize> {
+        self.line_start_offset(position.y)
+            .map(|offset| offset + position.x)
     }
 
     pub fn get_total_lines(&self) -> usize {
-        let mut total = 0;
-        for piece in self.pieces.iter() {
-            let line_starts: &Vec<usize> = match piece.source {
-                Source::Data => &self.line_starts_data,
-                Source::Add => &self.line_starts_add,
-            };
-
-            let line_starts_len = line_starts
-                .iter()
-                .filter(|x| (piece.offset..piece.offset + piece.length).contains(x))
-                .count();
-
-            total += line_starts_len;
-        }
-        total
+        self.pieces.total_line_breaks() + 1
     }
 
     pub fn insert_new_line(&mut self, offset: usize) {
@@ -130,20 +207,30 @@ impl PieceTable {
             return;
         }
 
+        self.record_undo(offset);
+        self.journal.push(Edit::Insert {
+            offset,
+            text: text.to_string(),
+        });
+        self.shift_marks(offset, 0, text.len());
+        self.shift_line_breaks(offset, 0, text.len());
+
         let add_buffer_len = self.add.len();
         self.add.push_str(text);
-        if text == "\r\n" {
-            self.line_starts_add.push(add_buffer_len + 1);
-        }
+        self.line_starts_add.extend(
+            text.match_indices('\n')
+                .map(|(idx, _)| add_buffer_len + idx),
+        );
 
         if let Some((piece_idx, buffer_offset)) = self.find_piece_from_offset(offset) {
-            let piece = self.pieces.get_mut(piece_idx).unwrap();
+            let piece = self.pieces.get(piece_idx).unwrap();
 
             if piece.source == Source::Add
                 && buffer_offset == piece.offset + piece.length
                 && piece.offset + piece.length == add_buffer_len
             {
-                piece.length += text.len();
+                let extended = Piece::new(Source::Add, piece.offset, piece.length + text.len());
+                self.splice(piece_idx..piece_idx + 1, vec![extended]);
                 return;
             }
 
@@ -160,10 +247,12 @@ impl PieceTable {
             .filter(|x| x.length > 0)
             .collect();
 
-            self.pieces.splice(piece_idx..piece_idx + 1, new_pieces);
+            self.splice(piece_idx..piece_idx + 1, new_pieces);
         } else if self.pieces.is_empty() {
-            self.pieces
-                .push(Piece::new(Source::Add, add_buffer_len, text.len()));
+            self.splice(
+                0..0,
+                vec![Piece::new(Source::Add, add_buffer_len, text.len())],
+            );
         }
     }
 
@@ -175,37 +264,48 @@ impl PieceTable {
         let (initial_piece_idx, initial_buffer_offset);
         let (final_piece_idx, final_buffer_offset);
 
-        let mut res = self.find_piece_from_offset(offset);
-        if res.is_some() {
-            (initial_piece_idx, initial_buffer_offset) = res.unwrap();
+        if let Some(res) = self.find_piece_from_offset(offset) {
+            (initial_piece_idx, initial_buffer_offset) = res;
         } else {
             return;
         }
-        res = self.find_piece_from_offset(offset + count);
-        if res.is_some() {
-            (final_piece_idx, final_buffer_offset) = res.unwrap();
+        if let Some(res) = self.find_piece_from_offset(offset + count) {
+            (final_piece_idx, final_buffer_offset) = res;
         } else {
             return;
         }
 
+        self.record_undo(offset);
+        self.journal.push(Edit::Delete { offset, count });
+        self.shift_marks(offset, count, 0);
+        self.shift_line_breaks(offset, count, 0);
+
+        let initial_piece = self.pieces.get(initial_piece_idx).unwrap();
+        let final_piece = self.pieces.get(final_piece_idx).unwrap();
+
         if initial_buffer_offset == final_buffer_offset {
-            let initial_piece = self.pieces.get_mut(initial_piece_idx).unwrap();
             if initial_buffer_offset == initial_piece.offset {
                 // start of piece
-                initial_piece.offset += count;
-                initial_piece.length -= count;
+                let piece = Piece::new(
+                    initial_piece.source,
+                    initial_piece.offset + count,
+                    initial_piece.length - count,
+                );
+                self.splice(initial_piece_idx..initial_piece_idx + 1, vec![piece]);
                 return;
             }
             if final_buffer_offset == initial_piece.offset + initial_piece.length {
                 // end of piece
-                initial_piece.length -= count;
+                let piece = Piece::new(
+                    initial_piece.source,
+                    initial_piece.offset,
+                    initial_piece.length - count,
+                );
+                self.splice(initial_piece_idx..initial_piece_idx + 1, vec![piece]);
                 return;
             }
         }
 
-        let initial_piece = self.pieces.get(initial_piece_idx).unwrap();
-        let final_piece = self.pieces.get(final_piece_idx).unwrap();
-
         let new_pieces: Vec<Piece> = [
             Piece::new(
                 initial_piece.source,
@@ -222,108 +322,255 @@ impl PieceTable {
         .filter(|x| x.length > 0)
         .collect();
 
-        self.pieces
-            .splice(initial_piece_idx..final_piece_idx + 1, new_pieces);
+        self.splice(initial_piece_idx..final_piece_idx + 1, new_pieces);
     }
 
-    /// Get the visible contents of the buffer. Specify an starting offset
-    /// and optionally the length of the contents. If None is passed,
-    /// it obtains everything until the end.
-    ///
-    /// Runtime: O(n), where n = # of pieces
-    ///
-    pub fn get(&self, offset: usize, until_offset: Option<usize>) -> String {
-        let until = until_offset.unwrap_or(0);
-        let mut data = String::new();
-
-        let mut counted_start = 0;
-        let mut counted_len = 0;
+    /// Start grouping all following changes into a single undo step, until
+    /// `end_undo_group` is called. Does nothing if a group is started already,
+    /// e.g. when a change operator continues into an Insert mode session.
+    pub fn begin_undo_group(&mut self) {
+        if self.undo_group {
+            return;
+        }
+        self.undo_group = true;
+        self.undo_group_recorded = false;
+        self.journal.push(Edit::BeginUndoGroup);
+    }
 
-        for piece in self.pieces.iter() {
-            let mut range = piece.offset..piece.offset + piece.length;
+    pub fn end_undo_group(&mut self) {
+        self.undo_group = false;
+        self.undo_group_recorded = false;
+        self.journal.push(Edit::EndUndoGroup);
+    }
 
-            if counted_start + piece.length < offset {
-                counted_start += piece.length;
-                continue;
+    /// Replay a change from the journal.
+    pub fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { offset, text } => self.insert(text, *offset),
+            Edit::Delete { offset, count } => self.delete(*offset, *count),
+            Edit::Undo => {
+                self.undo();
             }
-            if counted_start < offset {
-                range.start = offset - counted_start;
-                counted_start += range.start;
+            Edit::Redo => {
+                self.redo();
             }
+            Edit::BeginUndoGroup => self.begin_undo_group(),
+            Edit::EndUndoGroup => self.end_undo_group(),
+        }
+    }
 
-            if until_offset.is_some() && counted_len + piece.length > until {
-                range.end = piece.offset + std::cmp::min(piece.length, until - counted_len);
+    /// Move the marks after `removed` bytes at `offset` were replaced by
+    /// `inserted` bytes. Marks in the removed text move to its start.
+    fn shift_marks(&mut self, offset: usize, removed: usize, inserted: usize) {
+        for mark in self.marks.values_mut() {
+            if *mark >= offset + removed {
+                *mark = *mark - removed + inserted;
+            } else if *mark > offset {
+                *mark = offset;
             }
+        }
+    }
 
-            if piece.source == Source::Data {
-                data.push_str(&self.data[range]);
-            } else {
-                data.push_str(&self.add[range]);
-            }
-            counted_len += piece.length;
+    /// Move the `\n` line breaks like `shift_marks`. A line break, which is
+    /// (partly) removed or split by the inserted text, is dropped.
+    fn shift_line_breaks(&mut self, offset: usize, removed: usize, inserted: usize) {
+        if self.lf_line_breaks.is_empty() {
+            return;
         }
-        data
+        self.lf_line_breaks = std::mem::take(&mut self.lf_line_breaks)
+            .into_iter()
+            .filter_map(|line_break| match line_break {
+                _ if line_break + 2 <= offset => Some(line_break),
+                _ if line_break >= offset + removed => Some(line_break - removed + inserted),
+                _ => None,
+            })
+            .collect();
     }
 
-    pub fn find(&self, text: &str, offset: usize, all: bool) -> Vec<Range<usize>> {
-        let mut found = vec![];
+    /// Restore the marks of an undo or redo step, after its piece list was
+    /// swapped in. Returns the marks before. Marks set after the step was
+    /// recorded stay where they are (within the text), removed marks stay
+    /// removed.
+    fn restore_marks(&mut self, marks: HashMap<char, usize>) -> HashMap<char, usize> {
+        let len = self.len();
+        let restored = self
+            .marks
+            .iter()
+            .map(|(name, offset)| match marks.get(name) {
+                Some(restored) => (*name, *restored),
+                None => (*name, std::cmp::min(*offset, len)),
+            })
+            .collect();
+        std::mem::replace(&mut self.marks, restored)
+    }
 
-        let mut counted_start = 0;
-        for piece in self.pieces.iter() {
-            let mut range = piece.offset..piece.offset + piece.length;
+    /// Save the current piece list before it gets modified. Inside of an
+    /// undo group only the first change is recorded.
+    fn record_undo(&mut self, offset: usize) {
+        if self.undo_group && self.undo_group_recorded {
+            return;
+        }
+        self.undo_group_recorded = self.undo_group;
+
+        self.undo_stack.push(UndoStep {
+            pieces: self.pieces.clone(),
+            offset,
+            marks: self.marks.clone(),
+            lf_line_breaks: self.lf_line_breaks.clone(),
+        });
+        self.redo_stack.clear();
+    }
 
-            if counted_start + piece.length < offset {
-                counted_start += piece.length;
-                continue;
-            }
-            if counted_start < offset {
-                range.start = offset - counted_start;
-                counted_start += range.start;
-            }
+    /// Restore the piece list from before the last change.
+    /// Returns the offset at which the undone change happened.
+    pub fn undo(&mut self) -> Option<usize> {
+        let step = self.undo_stack.pop()?;
+        let pieces = std::mem::replace(&mut self.pieces, step.pieces);
+        let marks = self.restore_marks(step.marks);
+        let lf_line_breaks = std::mem::replace(&mut self.lf_line_breaks, step.lf_line_breaks);
+        self.journal.push(Edit::Undo);
+        self.redo_stack.push(UndoStep {
+            pieces,
+            offset: step.offset,
+            marks,
+            lf_line_breaks,
+        });
+        self.undo_group_recorded = false;
+        Some(step.offset)
+    }
 
-            let data = match piece.source {
-                Source::Data => &self.data[range],
-                Source::Add => &self.add[range],
-            };
+    /// Re-apply the last undone change.
+    /// Returns the offset at which the redone change happened.
+    pub fn redo(&mut self) -> Option<usize> {
+        let step = self.redo_stack.pop()?;
+        let pieces = std::mem::replace(&mut self.pieces, step.pieces);
+        let marks = self.restore_marks(step.marks);
+        let lf_line_breaks = std::mem::replace(&mut self.lf_line_breaks, step.lf_line_breaks);
+        self.journal.push(Edit::Redo);
+        self.undo_stack.push(UndoStep {
+            pieces,
+            offset: step.offset,
+            marks,
+            lf_line_breaks,
+        });
+        self.undo_group_recorded = false;
+        Some(step.offset)
+    }
 
-            let mut data_slice = data;
-            let mut offset = 0;
-            while let Some(idx) = data_slice.find(text) {
-                found.push(offset+idx..offset+idx + text.len());
-                data_slice = &data_slice[idx+text.len()..];
-                offset += idx+text.len();
+    fn piece_text(&self, piece: &Piece) -> &str {
+        match piece.source {
+            Source::Data => &self.data[piece.offset..piece.offset + piece.length],
+            Source::Add => &self.add[piece.offset..piece.offset + piece.length],
+        }
+    }
 
-                if !all {
-                    return found;
-                }
+    /// Get the visible contents of the buffer. Specify an starting offset
+    /// and optionally the end offset of the contents. If None is passed,
+    /// it obtains everything until the end.
+    ///
+    /// Runtime: O(log n + k), where n = # of pieces, k = # of returned pieces
+    ///
+    pub fn get(&self, offset: usize, until_offset: Option<usize>) -> String {
+        let until = until_offset.unwrap_or(usize::MAX).min(self.len());
+        let mut data = String::new();
+        if offset >= until {
+            return data;
+        }
+
+        let Some(location) = self.pieces.find_by_offset(offset) else {
+            return data;
+        };
+
+        let mut piece_start = location.start;
+        for piece in self.pieces.iter_from(location.index) {
+            if piece_start >= until {
+                break;
             }
+
+            let text = self.piece_text(&piece);
+            let start = offset.saturating_sub(piece_start);
+            let end = std::cmp::min(piece.length, until - piece_start);
+            if start < end {
+                data.push_str(&text[start..end]);
+            }
+            piece_start += piece.length;
         }
+        data
+    }
+
+    /// Iterate the characters of the visible contents, starting at the offset,
+    /// without copying them into a string.
+    pub fn chars(&self, offset: usize) -> impl Iterator<Item = char> + '_ {
+        let location = self.pieces.find_by_offset(offset);
+        let (index, skip) =
+            location.map_or((self.pieces.len(), 0), |l| (l.index, offset - l.start));
 
-        found
+        self.pieces
+            .iter_from(index)
+            .enumerate()
+            .flat_map(move |(i, piece)| {
+                let text = self.piece_text(&piece);
+                if i == 0 {
+                    text[skip..].chars()
+                } else {
+                    text.chars()
+                }
+            })
     }
 
-    pub fn get_position_from_offset(&self, offset: usize) -> Position {
-        let mut position = Position::new(0, 0);
-
-        'outer: for piece in self.pieces.iter() {
-            let line_starts: &Vec<usize> = match piece.source {
-                Source::Data => &self.line_starts_data,
-                Source::Add => &self.line_starts_add,
-            };
-
-            let mut last_nl = -1isize;
-            for nl in line_starts.iter().skip(1) {
-                if *nl > offset {
-                    position.x = offset - (last_nl as usize + 1);
-                    break 'outer;
+    /// Iterate the characters of the visible contents before the offset,
+    /// backwards, without copying them into a string.
+    ///
+    /// Runtime: O(log n) per piece, where n = # of pieces
+    pub fn chars_rev(&self, offset: usize) -> impl Iterator<Item = char> + '_ {
+        let offset = std::cmp::min(offset, self.len());
+        let location = self.pieces.find_by_offset(offset);
+        let (count, end) = location.map_or((0, 0), |l| (l.index + 1, offset - l.start));
+
+        (0..count)
+            .rev()
+            .filter_map(|index| self.pieces.get(index))
+            .enumerate()
+            .flat_map(move |(i, piece)| {
+                let text = self.piece_text(&piece);
+                if i == 0 {
+                    text[..end].chars().rev()
+                } else {
+                    text.chars().rev()
                 }
+            })
+    }
 
-                last_nl = *nl as isize;
-                position.y += 1;
-            }
+    /// Matches of a regular expression starting at or after `offset`. The
+    /// pieces are searched as one text, so a match can span pieces. Only the
+    /// first match is returned, unless `all` is set.
+    pub fn find(&self, regex: &Regex, offset: usize, all: bool) -> Vec<Range<usize>> {
+        let text = PieceText {
+            table: self,
+            piece: Cell::new(None),
+        };
+        let offset = std::cmp::min(offset, self.len());
+        match all {
+            true => regex.find_all(&text, offset),
+            false => regex
+                .find_at(&text, offset)
+                .map(|captures| captures.range())
+                .into_iter()
+                .collect(),
         }
+    }
 
-        position
+    /// Runtime: O(log n), where n = # of pieces
+    pub fn get_position_from_offset(&self, offset: usize) -> Position {
+        let offset = std::cmp::min(offset, self.len());
+        let Some(location) = self.pieces.find_by_offset(offset) else {
+            return Position::new(0, 0);
+        };
+
+        let y = location.line_breaks_before
+            + self.line_breaks_until(&location.piece, offset - location.start);
+        let line_start = self.line_start_offset(y).unwrap_or(0);
+        Position::new(offset - line_start, y)
     }
 }
 
@@ -570,4 +817,233 @@ mod tests {
             "File is read.\r\nThe hero lied.\r\nThe end.\r\nBensu.\r\nHello.\r\n".to_string()
         );
     }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut buffer = PieceTable::from_string("File is read.".to_string());
+        buffer.insert("not ", 8);
+        buffer.delete(0, 5);
+        assert_eq!(buffer.get(0, None), "is not read.");
+
+        assert_eq!(buffer.undo(), Some(0));
+        assert_eq!(buffer.get(0, None), "File is not read.");
+        assert_eq!(buffer.undo(), Some(8));
+        assert_eq!(buffer.get(0, None), "File is read.");
+        assert_eq!(buffer.undo(), None);
+
+        assert_eq!(buffer.redo(), Some(8));
+        assert_eq!(buffer.get(0, None), "File is not read.");
+        assert_eq!(buffer.redo(), Some(0));
+        assert_eq!(buffer.get(0, None), "is not read.");
+        assert_eq!(buffer.redo(), None);
+    }
+
+    #[test]
+    fn test_undo_group() {
+        let mut buffer = PieceTable::from_string("File is read.".to_string());
+        buffer.begin_undo_group();
+        buffer.insert("n", 8);
+        buffer.insert("o", 9);
+        buffer.insert("t", 10);
+        buffer.insert(" ", 11);
+        buffer.delete(12, 1);
+        buffer.end_undo_group();
+        buffer.insert("!", 16);
+        assert_eq!(buffer.get(0, None), "File is not ead.!");
+
+        assert_eq!(buffer.undo(), Some(16));
+        assert_eq!(buffer.undo(), Some(8));
+        assert_eq!(buffer.get(0, None), "File is read.");
+    }
+
+    #[test]
+    fn test_new_change_clears_redo() {
+        let mut buffer = PieceTable::from_string("File is read.".to_string());
+        buffer.insert("not ", 8);
+        buffer.undo();
+        buffer.insert("!", 13);
+        assert_eq!(buffer.redo(), None);
+        assert_eq!(buffer.get(0, None), "File is read.!");
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let mut buffer = PieceTable::from_string("File is read.".to_string());
+        buffer.begin_undo_group();
+        buffer.insert("not ", 8);
+        buffer.delete(0, 5);
+        buffer.end_undo_group();
+        buffer.insert("!", 12);
+        buffer.undo();
+
+        let mut replayed = PieceTable::from_string("File is read.".to_string());
+        for edit in buffer.journal.iter() {
+            replayed.apply(edit);
+        }
+        assert_eq!(replayed.get(0, None), "is not read.");
+        assert_eq!(replayed.journal, buffer.journal);
+
+        replayed.undo();
+        assert_eq!(replayed.get(0, None), "File is read.");
+    }
+
+    #[test]
+    fn test_get_position_from_offset_modified_buffer() {
+        let mut buffer = PieceTable::from_string("File is read.\r\nThe hero lied.".to_string());
+        buffer.insert("has ", 24);
+        buffer.insert_new_line(5);
+
+        assert_eq!(buffer.get_position_from_offset(0), Position::new(0, 0));
+        assert_eq!(buffer.get_position_from_offset(7), Position::new(0, 1));
+        assert_eq!(buffer.get_position_from_offset(30), Position::new(13, 2));
+    }
+
+    #[test]
+    fn test_get_across_pieces() {
+        let mut buffer = PieceTable::from_string("File is read.\r\nThe hero lied.".to_string());
+        buffer.insert("has ", 24);
+        buffer.insert_new_line(5);
+
+        assert_eq!(buffer.get(7, Some(11)), "is r");
+        assert_eq!(buffer.get(20, Some(30)), " hero has ");
+        assert_eq!(buffer.get(26, None), "has lied.");
+        assert_eq!(buffer.get(30, Some(20)), "");
+    }
+
+    #[test]
+    fn test_marks() {
+        let mut buffer = PieceTable::from_string("File is read.\r\nThe end.".to_string());
+        buffer.marks.insert('a', 8);
+        buffer.marks.insert('b', 15);
+        buffer.marks.insert('c', 2);
+
+        buffer.insert("not ", 8);
+        assert_eq!(buffer.marks[&'a'], 12);
+        assert_eq!(buffer.marks[&'b'], 19);
+        buffer.delete(0, 5);
+        assert_eq!(buffer.marks[&'a'], 7);
+        assert_eq!(buffer.marks[&'c'], 0);
+
+        buffer.undo();
+        assert_eq!(buffer.marks[&'a'], 12);
+        buffer.undo();
+        assert_eq!(buffer.marks[&'a'], 8);
+        buffer.redo();
+        assert_eq!(buffer.marks[&'a'], 12);
+        assert_eq!(buffer.get(buffer.marks[&'b'], None), "The end.");
+    }
+
+    #[test]
+    fn test_marks_undo_group() {
+        let mut buffer = PieceTable::from_string("a x b x c".to_string());
+        buffer.marks.insert('b', 4);
+        buffer.marks.insert('c', 8);
+
+        // changes at two places, e.g. by `:s/x/yy/g`
+        buffer.begin_undo_group();
+        for offset in [2, 7] {
+            buffer.delete(offset, 1);
+            buffer.insert("yy", offset);
+        }
+        buffer.end_undo_group();
+        assert_eq!(buffer.get(0, None), "a yy b yy c");
+        assert_eq!((buffer.marks[&'b'], buffer.marks[&'c']), (5, 10));
+
+        // a mark set after the change stays, within the text
+        buffer.marks.insert('e', 10);
+        buffer.undo();
+        assert_eq!((buffer.marks[&'b'], buffer.marks[&'c']), (4, 8));
+        assert_eq!(buffer.marks[&'e'], 9);
+        buffer.redo();
+        assert_eq!((buffer.marks[&'b'], buffer.marks[&'c']), (5, 10));
+        assert_eq!(buffer.get(buffer.marks[&'c'], None), "c");
+    }
+
+    #[test]
+    fn test_chars() {
+        let mut buffer = PieceTable::from_string("Grüße.".to_string());
+        buffer.insert("🦀 ", 0);
+        buffer.insert("!", 13);
+
+        assert_eq!(buffer.chars(0).collect::<String>(), "🦀 Grüße.!");
+        assert_eq!(buffer.chars(7).collect::<String>(), "üße.!");
+        assert_eq!(buffer.chars(13).count(), 1);
+        assert_eq!(buffer.chars(14).count(), 0);
+
+        assert_eq!(buffer.chars_rev(14).collect::<String>(), "!.eßürG 🦀");
+        assert_eq!(buffer.chars_rev(9).collect::<String>(), "ürG 🦀");
+        assert_eq!(buffer.chars_rev(0).count(), 0);
+        assert_eq!(
+            PieceTable::from_string(String::new()).chars_rev(0).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_find_across_pieces() {
+        let mut buffer = PieceTable::from_string("The hro lied. The hero.".to_string());
+        buffer.insert("e", 5);
+        assert!(buffer.pieces.len() > 1);
+
+        let regex = Regex::new("hero", false).unwrap();
+        assert_eq!(buffer.find(&regex, 0, true), vec![4..8, 19..23]);
+        assert_eq!(buffer.find(&regex, 5, false), vec![19..23]);
+        let regex = Regex::new("^The \\w\\+", false).unwrap();
+        assert_eq!(buffer.find(&regex, 0, true), vec![0..8]);
+        assert_eq!(buffer.find(&regex, 100, true), vec![]);
+
+        // one piece per character, inserted from the end
+        let mut buffer = PieceTable::from_string(String::new());
+        for ch in "Grüße, the hero.\r\nThe end.".chars().rev() {
+            buffer.insert(&ch.to_string(), 0);
+        }
+        assert!(buffer.pieces.len() > 20);
+        let regex = Regex::new("ü\\w*\\|\\<the\\>\\|\\.\\n\\a", true).unwrap();
+        assert_eq!(buffer.find(&regex, 0, true), vec![2..7, 9..12, 17..21]);
+    }
+
+    #[test]
+    fn test_lines_inserted_text() {
+        let mut buffer = PieceTable::from_string("File is read.".to_string());
+        buffer.insert("\r\nThe hero lied.\r\nThe end.", 13);
+
+        assert_eq!(buffer.get_total_lines(), 3);
+        assert_eq!(
+            buffer.get_offset_from_position(&Position::new(4, 2)),
+            Some(35)
+        );
+        assert_eq!(buffer.get_position_from_offset(35), Position::new(4, 2));
+    }
+
+    #[test]
+    fn test_large_file() {
+        let lines = 50_000;
+        let file: String = (0..lines).map(|i| format!("Line {:05}.\r\n", i)).collect();
+        let mut buffer = PieceTable::from_string(file);
+        assert_eq!(buffer.get_total_lines(), lines + 1);
+
+        let start = std::time::Instant::now();
+        for i in 0..5_000 {
+            let y = (i * 7919) % lines;
+            let offset = buffer
+                .get_offset_from_position(&Position::new(5, y))
+                .unwrap();
+            buffer.insert("x", offset);
+            buffer.delete(offset, 1);
+            if i % 10 == 0 {
+                buffer.insert_new_line(offset);
+            }
+            assert_eq!(buffer.get_position_from_offset(offset).y, y);
+        }
+        // lookups must not degrade with the number of pieces
+        assert!(start.elapsed().as_secs() < 10);
+
+        assert_eq!(buffer.get_total_lines(), lines + 501);
+        assert!(buffer.pieces.len() > 5_000);
+        let y = buffer.get_total_lines() - 2;
+        let offset = buffer
+            .get_offset_from_position(&Position::new(0, y))
+            .unwrap();
+        assert_eq!(buffer.get(offset, None), "Line 49999.\r\n");
+    }
 }
@@ -0,0 +1,376 @@
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::piece::Piece;
+
+type Link = Option<Rc<Node>>;
+
+/// Node of the tree. Besides its own piece, every node caches the piece
+/// count, byte length and number of line breaks of its whole subtree.
+struct Node {
+    piece: Piece,
+    line_breaks: usize,
+    priority: u64,
+    left: Link,
+    right: Link,
+    size: usize,
+    total_length: usize,
+    total_line_breaks: usize,
+}
+
+impl Node {
+    fn new(piece: Piece, line_breaks: usize, priority: u64, left: Link, right: Link) -> Rc<Node> {
+        Rc::new(Node {
+            size: 1 + size(&left) + size(&right),
+            total_length: piece.length + length(&left) + length(&right),
+            total_line_breaks: line_breaks + line_breaks_of(&left) + line_breaks_of(&right),
+            piece,
+            line_breaks,
+            priority,
+            left,
+            right,
+        })
+    }
+
+    fn with_children(&self, left: Link, right: Link) -> Rc<Node> {
+        Node::new(self.piece, self.line_breaks, self.priority, left, right)
+    }
+}
+
+fn size(link: &Link) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+fn length(link: &Link) -> usize {
+    link.as_ref().map_or(0, |n| n.total_length)
+}
+
+fn line_breaks_of(link: &Link) -> usize {
+    link.as_ref().map_or(0, |n| n.total_line_breaks)
+}
+
+/// Pseudo random node priorities (splitmix64 over a global counter).
+fn next_priority() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut z = COUNTER
+        .fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed)
+        .wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn merge(a: Link, b: Link) -> Link {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(a), Some(b)) => {
+            if a.priority > b.priority {
+                let right = merge(a.right.clone(), Some(b));
+                Some(a.with_children(a.left.clone(), right))
+            } else {
+                let left = merge(Some(a), b.left.clone());
+                Some(b.with_children(left, b.right.clone()))
+            }
+        }
+    }
+}
+
+/// Split the tree into the first `count` pieces and the rest.
+fn split(link: Link, count: usize) -> (Link, Link) {
+    let Some(node) = link else {
+        return (None, None);
+    };
+
+    let left_size = size(&node.left);
+    if count <= left_size {
+        let (a, b) = split(node.left.clone(), count);
+        (a, Some(node.with_children(b, node.right.clone())))
+    } else {
+        let (a, b) = split(node.right.clone(), count - left_size - 1);
+        (Some(node.with_children(node.left.clone(), a)), b)
+    }
+}
+
+/// Location of a piece in the tree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PieceLocation {
+    pub index: usize,
+    pub piece: Piece,
+    /// Logical offset of the first byte of the piece.
+    pub start: usize,
+    /// Number of line breaks in all pieces before this one.
+    pub line_breaks_before: usize,
+}
+
+/// Ordered sequence of pieces stored in a persistent treap.
+///
+/// Lookups by offset or by line break and splicing are O(log n) in the
+/// number of pieces. Nodes are shared between clones, so cloning the tree
+/// (e.g. for the undo history) is O(1).
+#[derive(Clone, Default)]
+pub struct PieceTree {
+    root: Link,
+}
+
+impl PieceTree {
+    pub fn new() -> PieceTree {
+        PieceTree::default()
+    }
+
+    /// Build a tree from pieces paired with their number of line breaks.
+    pub fn from_pieces(pieces: impl IntoIterator<Item = (Piece, usize)>) -> PieceTree {
+        let mut tree = PieceTree::new();
+        for (piece, line_breaks) in pieces {
+            tree.push(piece, line_breaks);
+        }
+        tree
+    }
+
+    /// Number of pieces.
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Total length in bytes of all pieces.
+    pub fn total_length(&self) -> usize {
+        length(&self.root)
+    }
+
+    /// Total number of line breaks in all pieces.
+    pub fn total_line_breaks(&self) -> usize {
+        line_breaks_of(&self.root)
+    }
+
+    pub fn push(&mut self, piece: Piece, line_breaks: usize) {
+        let node = Node::new(piece, line_breaks, next_priority(), None, None);
+        self.root = merge(self.root.take(), Some(node));
+    }
+
+    pub fn get(&self, index: usize) -> Option<Piece> {
+        let mut link = &self.root;
+        let mut index = index;
+        while let Some(node) = link {
+            let left_size = size(&node.left);
+            if index < left_size {
+                link = &node.left;
+            } else if index == left_size {
+                return Some(node.piece);
+            } else {
+                index -= left_size + 1;
+                link = &node.right;
+            }
+        }
+        None
+    }
+
+    /// Find the first piece whose end is at or after the logical offset.
+    /// An offset on the border of two pieces belongs to the earlier one.
+    pub fn find_by_offset(&self, offset: usize) -> Option<PieceLocation> {
+        if offset > self.total_length() {
+            return None;
+        }
+
+        let mut link = &self.root;
+        let mut offset = offset;
+        let (mut index, mut start, mut line_breaks_before) = (0, 0, 0);
+
+        while let Some(node) = link {
+            let left_length = length(&node.left);
+            if node.left.is_some() && offset <= left_length {
+                link = &node.left;
+                continue;
+            }
+
+            index += size(&node.left);
+            start += left_length;
+            line_breaks_before += line_breaks_of(&node.left);
+            if offset <= left_length + node.piece.length {
+                return Some(PieceLocation {
+                    index,
+                    piece: node.piece,
+                    start,
+                    line_breaks_before,
+                });
+            }
+
+            offset -= left_length + node.piece.length;
+            index += 1;
+            start += node.piece.length;
+            line_breaks_before += node.line_breaks;
+            link = &node.right;
+        }
+        None
+    }
+
+    /// Find the piece containing the n-th (1-based) line break.
+    pub fn find_by_line_break(&self, n: usize) -> Option<PieceLocation> {
+        if n == 0 || n > self.total_line_breaks() {
+            return None;
+        }
+
+        let mut link = &self.root;
+        let mut n = n;
+        let (mut index, mut start, mut line_breaks_before) = (0, 0, 0);
+
+        while let Some(node) = link {
+            let left_line_breaks = line_breaks_of(&node.left);
+            if n <= left_line_breaks {
+                link = &node.left;
+                continue;
+            }
+
+            index += size(&node.left);
+            start += length(&node.left);
+            line_breaks_before += left_line_breaks;
+            if n <= left_line_breaks + node.line_breaks {
+                return Some(PieceLocation {
+                    index,
+                    piece: node.piece,
+                    start,
+                    line_breaks_before,
+                });
+            }
+
+            n -= left_line_breaks + node.line_breaks;
+            index += 1;
+            start += node.piece.length;
+            line_breaks_before += node.line_breaks;
+            link = &node.right;
+        }
+        None
+    }
+
+    /// Replace the pieces in `range` by `pieces` (paired with their number
+    /// of line breaks).
+    pub fn splice(
+        &mut self,
+        range: Range<usize>,
+        pieces: impl IntoIterator<Item = (Piece, usize)>,
+    ) {
+        let (left, rest) = split(self.root.take(), range.start);
+        let (_, right) = split(rest, range.end - range.start);
+        let middle = PieceTree::from_pieces(pieces);
+        self.root = merge(merge(left, middle.root), right);
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from(0)
+    }
+
+    /// Iterate the pieces, starting with the piece at `index`.
+    pub fn iter_from(&self, index: usize) -> Iter<'_> {
+        let mut stack = vec![];
+        let mut link = &self.root;
+        let mut index = index;
+        while let Some(node) = link {
+            let left_size = size(&node.left);
+            if index < left_size {
+                stack.push(node.as_ref());
+                link = &node.left;
+            } else if index == left_size {
+                stack.push(node.as_ref());
+                break;
+            } else {
+                index -= left_size + 1;
+                link = &node.right;
+            }
+        }
+        Iter { stack }
+    }
+}
+
+impl fmt::Debug for PieceTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl PartialEq for PieceTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+/// In-order iterator over the pieces of a `PieceTree`.
+pub struct Iter<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Piece;
+
+    fn next(&mut self) -> Option<Piece> {
+        let node = self.stack.pop()?;
+        let mut link = &node.right;
+        while let Some(child) = link {
+            self.stack.push(child.as_ref());
+            link = &child.left;
+        }
+        Some(node.piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Source;
+
+    fn tree(lengths: &[usize]) -> PieceTree {
+        let mut offset = 0;
+        PieceTree::from_pieces(lengths.iter().map(|len| {
+            let piece = Piece::new(Source::Data, offset, *len);
+            offset += len;
+            (piece, len / 2)
+        }))
+    }
+
+    #[test]
+    fn test_piece_tree_find_by_offset() {
+        let tree = tree(&[4, 6, 2, 8]);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.total_length(), 20);
+        assert_eq!(tree.total_line_breaks(), 10);
+
+        assert_eq!(tree.find_by_offset(0).unwrap().index, 0);
+        assert_eq!(tree.find_by_offset(4).unwrap().index, 0);
+        let location = tree.find_by_offset(5).unwrap();
+        assert_eq!((location.index, location.start), (1, 4));
+        assert_eq!(location.line_breaks_before, 2);
+        assert_eq!(tree.find_by_offset(20).unwrap().index, 3);
+        assert!(tree.find_by_offset(21).is_none());
+    }
+
+    #[test]
+    fn test_piece_tree_find_by_line_break() {
+        let tree = tree(&[4, 6, 2, 8]);
+        assert!(tree.find_by_line_break(0).is_none());
+        assert_eq!(tree.find_by_line_break(2).unwrap().index, 0);
+        assert_eq!(tree.find_by_line_break(3).unwrap().index, 1);
+        assert_eq!(tree.find_by_line_break(6).unwrap().index, 2);
+        let location = tree.find_by_line_break(10).unwrap();
+        assert_eq!((location.index, location.start), (3, 12));
+        assert!(tree.find_by_line_break(11).is_none());
+    }
+
+    #[test]
+    fn test_piece_tree_splice() {
+        let mut tree = tree(&[4, 6, 2, 8]);
+        let snapshot = tree.clone();
+
+        tree.splice(1..3, [(Piece::new(Source::Add, 0, 1), 0)]);
+        let lengths: Vec<usize> = tree.iter().map(|p| p.length).collect();
+        assert_eq!(lengths, vec![4, 1, 8]);
+        assert_eq!(tree.total_line_breaks(), 6);
+
+        let lengths: Vec<usize> = snapshot.iter_from(2).map(|p| p.length).collect();
+        assert_eq!(lengths, vec![2, 8]);
+        assert_eq!(snapshot.get(1).unwrap().length, 6);
+    }
+}
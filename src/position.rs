@@ -1,6 +1,10 @@
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
 pub struct Position {
+    /// Column as grapheme cluster index into the line. `Buffer` converts it
+    /// into byte offsets for the piece table and into terminal columns for
+    /// display.
     pub x: usize,
+    /// Line index.
     pub y: usize,
 }
 impl Position {
@@ -0,0 +1,933 @@
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::Chars;
+
+/// Pattern for searching and `:s`, with the syntax of vim's regular
+/// expressions ('magic'):
+/// - `.` any character, `[abc]`, `[^a-z]` character classes, `\s`, `\d`,
+///   `\w`, `\a`, `\l`, `\u` (and `\S`, `\D`... for the opposite) classes
+/// - `*`, `\+`, `\=` or `\?`, `\{n,m}` (`\{-n,m}` matches as few as possible)
+/// - `^`, `$` start and end of a line, `\<`, `\>` start and end of a word
+/// - `\(...\)` groups, `\%(...\)` groups which are not captured, `\1`...
+///   for the text of a group, `\|` alternatives
+/// - `\n` a line break, `\t` a tab
+///
+/// `.` and character classes do not match line breaks.
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    /// Number of captured groups.
+    groups: usize,
+    /// Number of positions saved while matching: the start and end of every
+    /// group (including group 0, the whole match), then one for every loop.
+    slots: usize,
+    ignore_case: bool,
+    /// Character every match starts with, to skip to it quickly.
+    first_char: Option<char>,
+}
+
+/// Most instructions a pattern is compiled to, e.g. for `\{n}` with a large
+/// count.
+const MAX_PROGRAM_LENGTH: usize = 100_000;
+
+/// Steps the matcher may take for every byte of the searched text (plus
+/// `MIN_STEPS`). A pattern, which backtracks more than that (e.g. `\(a*\)*b`
+/// on a long run of `a`), stops the search instead of hanging the editor.
+const STEPS_PER_BYTE: usize = 100;
+const MIN_STEPS: usize = 1_000_000;
+
+/// Text searched by a `Regex`. It does not need to be one string, e.g. the
+/// pieces of a piece table are read one after the other.
+pub trait Haystack {
+    /// Length in bytes.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Character starting at the byte offset.
+    fn char_at(&self, offset: usize) -> Option<char>;
+
+    /// Character ending at the byte offset.
+    fn char_before(&self, offset: usize) -> Option<char>;
+
+    /// Byte offset of the next `ch` at or after `offset`.
+    fn find_char(&self, offset: usize, ch: char) -> Option<usize> {
+        let mut offset = offset;
+        loop {
+            let found = self.char_at(offset)?;
+            if found == ch {
+                return Some(offset);
+            }
+            offset += found.len_utf8();
+        }
+    }
+}
+
+impl Haystack for str {
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    fn char_at(&self, offset: usize) -> Option<char> {
+        self.get(offset..)?.chars().next()
+    }
+
+    fn char_before(&self, offset: usize) -> Option<char> {
+        self.get(..offset)?.chars().next_back()
+    }
+
+    fn find_char(&self, offset: usize, ch: char) -> Option<usize> {
+        Some(offset + self.get(offset..)?.find(ch)?)
+    }
+}
+
+impl Haystack for String {
+    fn len(&self) -> usize {
+        self.as_str().len()
+    }
+
+    fn char_at(&self, offset: usize) -> Option<char> {
+        self.as_str().char_at(offset)
+    }
+
+    fn char_before(&self, offset: usize) -> Option<char> {
+        self.as_str().char_before(offset)
+    }
+
+    fn find_char(&self, offset: usize, ch: char) -> Option<usize> {
+        self.as_str().find_char(offset, ch)
+    }
+}
+
+/// Case sensitivity of searches (`:set ignorecase`, `:set smartcase`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CaseOptions {
+    pub ignore_case: bool,
+    /// With `ignore_case`: match the case, if the pattern contains an upper
+    /// case letter.
+    pub smart_case: bool,
+}
+
+/// Compile a typed search pattern. `\c` anywhere in the pattern ignores the
+/// case and `\C` matches it, regardless of the options.
+pub fn search_pattern(pattern: &str, options: CaseOptions) -> Result<Regex, String> {
+    let mut stripped = String::with_capacity(pattern.len());
+    let mut ignore_case = None;
+    let mut has_upper_case = false;
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            has_upper_case |= ch.is_uppercase();
+            stripped.push(ch);
+            continue;
+        }
+        // escaped letters are classes (`\S`, `\U`...), not text
+        match chars.next() {
+            Some('c') => ignore_case = Some(true),
+            Some('C') => ignore_case = Some(false),
+            Some(escaped) => {
+                stripped.push(ch);
+                stripped.push(escaped);
+            }
+            None => stripped.push(ch),
+        }
+    }
+    let ignore_case =
+        ignore_case.unwrap_or(options.ignore_case && !(options.smart_case && has_upper_case));
+    Regex::new(&stripped, ignore_case)
+}
+
+/// Byte ranges of a match (group 0) and of its captured groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures {
+    groups: Vec<Option<Range<usize>>>,
+}
+
+impl Captures {
+    /// Byte range of the whole match.
+    pub fn range(&self) -> Range<usize> {
+        self.groups[0].clone().unwrap_or_default()
+    }
+
+    /// Byte range of group `index`, or None if the group did not take part in
+    /// the match.
+    pub fn get(&self, index: usize) -> Option<Range<usize>> {
+        self.groups.get(index).cloned().flatten()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Char(char),
+    /// `.`
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    /// `\n`
+    LineBreak,
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+    /// Group with the index it is captured at.
+    Group(Box<Node>, Option<usize>),
+    /// `\1` to `\9`
+    Backreference(usize),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Space,
+    Digit,
+    Word,
+    Alpha,
+    Lower,
+    Upper,
+}
+
+impl ClassItem {
+    fn matches(&self, ch: char) -> bool {
+        match *self {
+            ClassItem::Char(item) => ch == item,
+            ClassItem::Range(from, to) => (from..=to).contains(&ch),
+            ClassItem::Space => ch == ' ' || ch == '\t',
+            ClassItem::Digit => ch.is_ascii_digit(),
+            ClassItem::Word => is_word(ch),
+            ClassItem::Alpha => ch.is_ascii_alphabetic(),
+            ClassItem::Lower => ch.is_ascii_lowercase(),
+            ClassItem::Upper => ch.is_ascii_uppercase(),
+        }
+    }
+}
+
+fn is_word(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn is_line_break(ch: char) -> bool {
+    ch == '\r' || ch == '\n'
+}
+
+impl Regex {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+            groups: 0,
+        };
+        let node = parser.parse_alternation()?;
+        if parser.chars.next().is_some() {
+            return Err("Unmatched \\)".to_string());
+        }
+        let first_char = match &node {
+            Node::Concat(nodes) => match nodes.first() {
+                Some(Node::Char(ch)) => Some(*ch),
+                _ => None,
+            },
+            Node::Char(ch) => Some(*ch),
+            _ => None,
+        };
+        let mut compiler = Compiler {
+            program: vec![],
+            slots: 2 * (parser.groups + 1),
+        };
+        compiler.compile(&node)?;
+        compiler.program.push(Inst::Match);
+        Ok(Regex {
+            program: compiler.program,
+            groups: parser.groups,
+            slots: compiler.slots,
+            ignore_case,
+            first_char: first_char.filter(|_| !ignore_case),
+        })
+    }
+
+    fn matcher<'t, H: Haystack + ?Sized>(&self, text: &'t H) -> Matcher<'_, 't, H> {
+        Matcher {
+            regex: self,
+            text,
+            steps: MIN_STEPS.saturating_add(text.len().saturating_mul(STEPS_PER_BYTE)),
+        }
+    }
+
+    /// First match starting at or after the byte offset `start`.
+    pub fn find_at<H: Haystack + ?Sized>(&self, text: &H, start: usize) -> Option<Captures> {
+        self.matcher(text).find_at(start)
+    }
+
+    /// Byte ranges of all matches, which do not overlap, starting at or
+    /// after the byte offset `start`.
+    pub fn find_all<H: Haystack + ?Sized>(&self, text: &H, start: usize) -> Vec<Range<usize>> {
+        let mut matcher = self.matcher(text);
+        let mut matches: Vec<Range<usize>> = vec![];
+        let mut position = start;
+        while let Some(captures) = matcher.find_at(position) {
+            let range = captures.range();
+            // an empty match directly after another match is skipped
+            let skipped =
+                range.is_empty() && matches.last().is_some_and(|last| last.end == range.start);
+            if !skipped {
+                matches.push(range.clone());
+            }
+            position = range.end;
+            // the search continues after an empty match
+            if range.is_empty() {
+                match text.char_at(position) {
+                    Some(ch) => position += ch.len_utf8(),
+                    None => break,
+                }
+            }
+        }
+        matches
+    }
+
+    /// Match starting exactly at the byte offset.
+    pub fn match_at<H: Haystack + ?Sized>(&self, text: &H, position: usize) -> Option<Captures> {
+        self.matcher(text).match_at(position)
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    groups: usize,
+}
+
+impl Parser<'_> {
+    /// Alternatives separated by `\|`, up to `\)` or the end of the pattern.
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.next_is_escaped('|') {
+            self.chars.next();
+            self.chars.next();
+            branches.push(self.parse_concat()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.pop().unwrap_or(Node::Concat(vec![])),
+            _ => Node::Alternation(branches),
+        })
+    }
+
+    fn next_is_escaped(&self, ch: char) -> bool {
+        let mut chars = self.chars.clone();
+        chars.next() == Some('\\') && chars.next() == Some(ch)
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![];
+        while let Some(&ch) = self.chars.peek() {
+            if self.next_is_escaped('|') || self.next_is_escaped(')') {
+                break;
+            }
+            self.chars.next();
+            let node = match ch {
+                '^' if nodes.is_empty() => Node::LineStart,
+                '$' if self.chars.peek().is_none()
+                    || self.next_is_escaped('|')
+                    || self.next_is_escaped(')') =>
+                {
+                    Node::LineEnd
+                }
+                '.' => Node::Any,
+                '[' => self.parse_class(),
+                '*' if nodes.is_empty() => Node::Char('*'),
+                '*' => {
+                    let node = Self::repeated(&mut nodes, "*")?;
+                    nodes.push(Node::Repeat {
+                        node,
+                        min: 0,
+                        max: None,
+                        greedy: true,
+                    });
+                    continue;
+                }
+                '\\' => match self.chars.next() {
+                    Some(quantifier @ ('+' | '=' | '?' | '{')) => {
+                        let node = Self::repeated(&mut nodes, &format!("\\{}", quantifier))?;
+                        let (min, max, greedy) = match quantifier {
+                            '+' => (1, None, true),
+                            '{' => self.parse_bounds()?,
+                            _ => (0, Some(1), true),
+                        };
+                        nodes.push(Node::Repeat {
+                            node,
+                            min,
+                            max,
+                            greedy,
+                        });
+                        continue;
+                    }
+                    Some(escaped) => self.parse_escaped(escaped)?,
+                    None => Node::Char('\\'),
+                },
+                _ => Node::Char(ch),
+            };
+            nodes.push(node);
+        }
+        Ok(match nodes.len() {
+            1 => nodes.pop().unwrap_or(Node::Concat(vec![])),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    /// Take the node a quantifier applies to.
+    fn repeated(nodes: &mut Vec<Node>, quantifier: &str) -> Result<Box<Node>, String> {
+        match nodes.pop() {
+            Some(node) if !matches!(node, Node::Repeat { .. }) => Ok(Box::new(node)),
+            _ => Err(format!("Nothing to repeat before {}", quantifier)),
+        }
+    }
+
+    /// Atom after a `\`.
+    fn parse_escaped(&mut self, ch: char) -> Result<Node, String> {
+        let class = |item, negated| Node::Class {
+            items: vec![item],
+            negated,
+        };
+        Ok(match ch {
+            '(' => {
+                self.groups += 1;
+                let index = self.groups;
+                Node::Group(Box::new(self.parse_group()?), Some(index))
+            }
+            '%' if self.chars.next_if_eq(&'(').is_some() => {
+                Node::Group(Box::new(self.parse_group()?), None)
+            }
+            ')' => return Err("Unmatched \\)".to_string()),
+            '<' => Node::WordStart,
+            '>' => Node::WordEnd,
+            'n' => Node::LineBreak,
+            't' => Node::Char('\t'),
+            'r' => Node::Char('\r'),
+            's' | 'S' => class(ClassItem::Space, ch == 'S'),
+            'd' | 'D' => class(ClassItem::Digit, ch == 'D'),
+            'w' | 'W' => class(ClassItem::Word, ch == 'W'),
+            'a' | 'A' => class(ClassItem::Alpha, ch == 'A'),
+            'l' | 'L' => class(ClassItem::Lower, ch == 'L'),
+            'u' | 'U' => class(ClassItem::Upper, ch == 'U'),
+            '1'..='9' => Node::Backreference(ch as usize - '0' as usize),
+            _ => Node::Char(ch),
+        })
+    }
+
+    /// Rest of a group after `\(`, up to and including `\)`.
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let node = self.parse_alternation()?;
+        if !self.next_is_escaped(')') {
+            return Err("Unmatched \\(".to_string());
+        }
+        self.chars.next();
+        self.chars.next();
+        Ok(node)
+    }
+
+    /// Bounds of `\{n,m}` after `\{`: minimum, maximum and whether as many as
+    /// possible are matched.
+    fn parse_bounds(&mut self) -> Result<(usize, Option<usize>, bool), String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some('\\') if self.chars.next_if_eq(&'}').is_some() => break,
+                Some(ch) => text.push(ch),
+                None => return Err("Missing } after \\{".to_string()),
+            }
+        }
+        let invalid = || format!("Invalid bounds: \\{{{}}}", text);
+        let (greedy, bounds) = match text.strip_prefix('-') {
+            Some(bounds) => (false, bounds),
+            None => (true, text.as_str()),
+        };
+        let number = |text: &str| match text {
+            "" => Ok(None),
+            _ => text.parse().map(Some).map_err(|_| invalid()),
+        };
+        let (min, max) = match bounds.split_once(',') {
+            Some((min, max)) => (number(min)?.unwrap_or(0), number(max)?),
+            None => match number(bounds)? {
+                Some(count) => (count, Some(count)),
+                None => (0, None),
+            },
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(invalid());
+        }
+        Ok((min, max, greedy))
+    }
+
+    /// Character class after `[`. Without a closing `]`, the `[` is taken
+    /// literally.
+    fn parse_class(&mut self) -> Node {
+        let saved = self.chars.clone();
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut items = vec![];
+        // a `]` at the start is part of the class
+        if let Some(bracket) = self.chars.next_if_eq(&']') {
+            items.push(ClassItem::Char(bracket));
+        }
+        loop {
+            let ch = match self.chars.next() {
+                Some(']') => break,
+                Some('\\') => match self.chars.next() {
+                    Some('t') => '\t',
+                    Some('d') => {
+                        items.push(ClassItem::Digit);
+                        continue;
+                    }
+                    Some('s') => {
+                        items.push(ClassItem::Space);
+                        continue;
+                    }
+                    Some(ch @ (']' | '\\' | '-' | '^')) => ch,
+                    Some(ch) => {
+                        items.push(ClassItem::Char('\\'));
+                        ch
+                    }
+                    None => '\\',
+                },
+                Some(ch) => ch,
+                None => {
+                    self.chars = saved;
+                    return Node::Char('[');
+                }
+            };
+            let mut lookahead = self.chars.clone();
+            match (lookahead.next(), lookahead.next()) {
+                (Some('-'), Some(to)) if to != ']' => {
+                    self.chars = lookahead;
+                    items.push(ClassItem::Range(ch, to));
+                }
+                _ => items.push(ClassItem::Char(ch)),
+            }
+        }
+        Node::Class { items, negated }
+    }
+}
+
+/// Instruction of a compiled pattern.
+#[derive(Debug)]
+enum Inst {
+    /// Node matching one character: `Char`, `Any` or `Class`.
+    Char(Node),
+    /// `\n`, which matches `\r\n` as well.
+    LineBreak,
+    /// Node matching no text: `LineStart`, `LineEnd`, `WordStart` or
+    /// `WordEnd`.
+    Assert(Node),
+    Backreference(usize),
+    /// Save the position in a slot. Backtracking restores the old value.
+    Save(usize),
+    /// Fail, if the position did not move since it was saved in the slot,
+    /// so a loop does not repeat an empty match forever.
+    Progress(usize),
+    /// Continue at the first instruction. If that fails, try the second.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+    slots: usize,
+}
+
+impl Compiler {
+    /// Add an instruction, returning its index.
+    fn push(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    /// Split to `next` and `end`, preferring `next` (more repetitions) if
+    /// greedy.
+    fn split(greedy: bool, next: usize, end: usize) -> Inst {
+        match greedy {
+            true => Inst::Split(next, end),
+            false => Inst::Split(end, next),
+        }
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        if self.program.len() > MAX_PROGRAM_LENGTH {
+            return Err("Pattern too long".to_string());
+        }
+        match node {
+            Node::Char(_) | Node::Any | Node::Class { .. } => {
+                self.push(Inst::Char(node.clone()));
+            }
+            Node::LineBreak => {
+                self.push(Inst::LineBreak);
+            }
+            Node::LineStart | Node::LineEnd | Node::WordStart | Node::WordEnd => {
+                self.push(Inst::Assert(node.clone()));
+            }
+            Node::Group(node, None) => self.compile(node)?,
+            Node::Group(node, Some(index)) => {
+                self.push(Inst::Save(2 * index));
+                self.compile(node)?;
+                self.push(Inst::Save(2 * index + 1));
+            }
+            Node::Backreference(index) => {
+                self.push(Inst::Backreference(*index));
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alternation(branches) => {
+                let mut jumps = vec![];
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 == branches.len() {
+                        self.compile(branch)?;
+                        break;
+                    }
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile(branch)?;
+                    jumps.push(self.push(Inst::Jump(0)));
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let slot = self.slots;
+                        self.slots += 1;
+                        let split = self.push(Inst::Split(0, 0));
+                        self.push(Inst::Save(slot));
+                        self.compile(node)?;
+                        self.push(Inst::Progress(slot));
+                        self.push(Inst::Jump(split));
+                        self.program[split] = Self::split(*greedy, split + 1, self.program.len());
+                    }
+                    Some(max) => {
+                        let mut splits = vec![];
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.compile(node)?;
+                            if self.program.len() > MAX_PROGRAM_LENGTH {
+                                return Err("Pattern too long".to_string());
+                            }
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.program[split] = Self::split(*greedy, split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Entry of the backtracking stack: another way to match, or a saved
+/// position to restore when going back past it.
+enum Backtrack {
+    Try { pc: usize, position: usize },
+    Restore { slot: usize, value: Option<usize> },
+}
+
+/// Backtracking matcher. Alternatives are kept on a stack instead of the
+/// call stack, so long texts cannot overflow it, and the number of steps is
+/// limited.
+struct Matcher<'r, 't, H: Haystack + ?Sized> {
+    regex: &'r Regex,
+    text: &'t H,
+    /// Steps left, before the search gives up.
+    steps: usize,
+}
+
+impl<H: Haystack + ?Sized> Matcher<'_, '_, H> {
+    fn find_at(&mut self, start: usize) -> Option<Captures> {
+        let mut position = start;
+        loop {
+            if let Some(first_char) = self.regex.first_char {
+                position = self.text.find_char(position, first_char)?;
+            }
+            if let Some(captures) = self.match_at(position) {
+                return Some(captures);
+            }
+            if self.steps == 0 {
+                return None;
+            }
+            position += self.text.char_at(position)?.len_utf8();
+        }
+    }
+
+    fn match_at(&mut self, start: usize) -> Option<Captures> {
+        let program = &self.regex.program;
+        let mut slots = vec![None; self.regex.slots];
+        let mut stack = vec![Backtrack::Try {
+            pc: 0,
+            position: start,
+        }];
+        while let Some(entry) = stack.pop() {
+            let (mut pc, mut position) = match entry {
+                Backtrack::Try { pc, position } => (pc, position),
+                Backtrack::Restore { slot, value } => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            loop {
+                if self.steps == 0 {
+                    return None;
+                }
+                self.steps -= 1;
+                match &program[pc] {
+                    Inst::Char(node) => match self.text.char_at(position) {
+                        Some(ch) if self.matches_char(node, ch) => position += ch.len_utf8(),
+                        _ => break,
+                    },
+                    Inst::LineBreak => {
+                        match (self.text.char_at(position), self.text.char_at(position + 1)) {
+                            (Some('\r'), Some('\n')) => position += 2,
+                            (Some('\n'), _) => position += 1,
+                            _ => break,
+                        }
+                    }
+                    Inst::Assert(node) => {
+                        if !self.assertion(node, position) {
+                            break;
+                        }
+                    }
+                    Inst::Backreference(index) => {
+                        match self.backreference(&slots, *index, position) {
+                            Some(end) => position = end,
+                            None => break,
+                        }
+                    }
+                    Inst::Save(slot) => {
+                        stack.push(Backtrack::Restore {
+                            slot: *slot,
+                            value: slots[*slot],
+                        });
+                        slots[*slot] = Some(position);
+                    }
+                    Inst::Progress(slot) => {
+                        if slots[*slot] == Some(position) {
+                            break;
+                        }
+                    }
+                    Inst::Split(first, second) => {
+                        stack.push(Backtrack::Try {
+                            pc: *second,
+                            position,
+                        });
+                        pc = *first;
+                        continue;
+                    }
+                    Inst::Jump(target) => {
+                        pc = *target;
+                        continue;
+                    }
+                    Inst::Match => {
+                        let mut groups: Vec<Option<Range<usize>>> = (0..=self.regex.groups)
+                            .map(|index| match (slots[2 * index], slots[2 * index + 1]) {
+                                (Some(start), Some(end)) if start <= end => Some(start..end),
+                                _ => None,
+                            })
+                            .collect();
+                        groups[0] = Some(start..position);
+                        return Some(Captures { groups });
+                    }
+                }
+                pc += 1;
+            }
+        }
+        None
+    }
+
+    fn chars_equal(&self, a: char, b: char) -> bool {
+        a == b || (self.regex.ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    /// Whether a node, which matches one character, matches `ch`.
+    fn matches_char(&self, node: &Node, ch: char) -> bool {
+        match node {
+            Node::Char(expected) => self.chars_equal(*expected, ch),
+            Node::Any => !is_line_break(ch),
+            Node::Class { items, negated } => {
+                let in_class = |ch: char| items.iter().any(|item| item.matches(ch));
+                let found = in_class(ch)
+                    || (self.regex.ignore_case
+                        && ch.to_lowercase().chain(ch.to_uppercase()).any(in_class));
+                !is_line_break(ch) && found != *negated
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a node, which matches no text, matches at the position.
+    fn assertion(&self, node: &Node, position: usize) -> bool {
+        let (before, at) = (self.text.char_before(position), self.text.char_at(position));
+        match node {
+            Node::LineStart => matches!(before, None | Some('\n')),
+            Node::LineEnd => matches!(at, None | Some('\r' | '\n')),
+            Node::WordStart => at.is_some_and(is_word) && !before.is_some_and(is_word),
+            Node::WordEnd => before.is_some_and(is_word) && !at.is_some_and(is_word),
+            _ => false,
+        }
+    }
+
+    /// End of the text of group `index` repeated at the position.
+    fn backreference(
+        &self,
+        slots: &[Option<usize>],
+        index: usize,
+        position: usize,
+    ) -> Option<usize> {
+        let (mut from, to) = match (slots.get(2 * index), slots.get(2 * index + 1)) {
+            (Some(Some(start)), Some(Some(end))) => (*start, *end),
+            _ => return Some(position),
+        };
+        let mut end = position;
+        while from < to {
+            let expected = self.text.char_at(from)?;
+            let ch = self.text.char_at(end)?;
+            if !self.chars_equal(expected, ch) {
+                return None;
+            }
+            from += expected.len_utf8();
+            end += ch.len_utf8();
+        }
+        Some(end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<Range<usize>> {
+        Some(
+            Regex::new(pattern, false)
+                .unwrap()
+                .find_at(text, 0)?
+                .range(),
+        )
+    }
+
+    #[test]
+    fn test_regex() {
+        assert_eq!(find("b.d", "abcd"), Some(1..4));
+        assert_eq!(find("x", "abcd"), None);
+        assert_eq!(find("a*b", "caaab"), Some(1..5));
+        assert_eq!(find("a\\+", "baa"), Some(1..3));
+        assert_eq!(find("ab\\=c", "ac"), Some(0..2));
+        assert_eq!(find("a\\{2,3}", "a aaaa"), Some(2..5));
+        assert_eq!(find("a\\{-1,}", "aaa"), Some(0..1));
+        assert_eq!(find("[0-9]\\+", "ab 123;"), Some(3..6));
+        assert_eq!(find("[^a-c ]", "ab cd"), Some(4..5));
+        assert_eq!(find("[]x]", "a]"), Some(1..2));
+        assert_eq!(find("[a", "b[a"), Some(1..3));
+        assert_eq!(find("\\d\\s\\w", "x 1 y"), Some(2..5));
+        assert_eq!(find("^b", "ab\r\nb"), Some(4..5));
+        assert_eq!(find("a$", "ab\r\na"), Some(4..5));
+        assert_eq!(find("b\\nc", "ab\r\nc"), Some(1..5));
+        assert_eq!(find("b.*", "abc\r\nd"), Some(1..3));
+        assert_eq!(find("\\<in\\>", "int in"), Some(4..6));
+        assert_eq!(find("cat\\|dog", "hotdog"), Some(3..6));
+        assert_eq!(find("\\(ab\\)\\+c", "xababc"), Some(1..6));
+        assert_eq!(find("\\(a\\|b\\)\\1", "abba"), Some(1..3));
+        assert_eq!(find("ü.", "Grüße"), Some(2..6));
+        assert_eq!(find("a\\.b", "axb a.b"), Some(4..7));
+        assert_eq!(find("*", "a*"), Some(1..2));
+
+        assert!(Regex::new("\\(a", false).is_err());
+        assert!(Regex::new("a\\)", false).is_err());
+        assert!(Regex::new("\\+", false).is_err());
+        assert!(Regex::new("a\\{3,1}", false).is_err());
+    }
+
+    #[test]
+    fn test_regex_captures() {
+        let regex = Regex::new("\\(\\w\\+\\)=\\%(\\(\\d\\)\\|x\\)", false).unwrap();
+        let captures = regex.find_at("let a=x, b=2", 0).unwrap();
+        assert_eq!(captures.range(), 4..7);
+        assert_eq!(captures.get(1), Some(4..5));
+        assert_eq!(captures.get(2), None);
+        let captures = regex.find_at("let a=x, b=2", 7).unwrap();
+        assert_eq!(captures.get(2), Some(11..12));
+
+        let regex = Regex::new("ab", true).unwrap();
+        assert_eq!(regex.find_all("AB xaB ab", 0), vec![0..2, 4..6, 7..9]);
+        let regex = Regex::new("[a-c]*", true).unwrap();
+        assert_eq!(regex.find_all("xCd", 0), vec![0..0, 1..2, 3..3]);
+    }
+
+    #[test]
+    fn test_long_text() {
+        let line = "ab".repeat(50_000);
+        let text = format!("{}c\r\n", line);
+        assert_eq!(find("\\(a\\|b\\)*c", &text), Some(0..100_001));
+        assert_eq!(find("\\%(ab\\)\\+$", &text), None);
+        assert_eq!(find("\\(a*\\)*c", "aaac"), Some(0..4));
+
+        // backtracking exponentially gives up instead of hanging
+        let text = "a".repeat(100_000);
+        let start = std::time::Instant::now();
+        assert_eq!(find("\\(a*\\)*b", &text), None);
+        assert_eq!(find("\\(a\\|aa\\)\\+$", &format!("{}b", text)), None);
+        assert!(start.elapsed().as_secs() < 30);
+
+        assert_eq!(
+            Regex::new("a\\{200000}", false).unwrap_err(),
+            "Pattern too long"
+        );
+    }
+
+    #[test]
+    fn test_search_pattern() {
+        let find = |pattern: &str, options: CaseOptions| {
+            search_pattern(pattern, options)
+                .unwrap()
+                .find_all("Ab ab AB", 0)
+        };
+        let ignore_case = CaseOptions {
+            ignore_case: true,
+            smart_case: false,
+        };
+        let smart_case = CaseOptions {
+            ignore_case: true,
+            smart_case: true,
+        };
+        assert_eq!(find("ab", CaseOptions::default()), vec![3..5]);
+        assert_eq!(find("ab", ignore_case), vec![0..2, 3..5, 6..8]);
+        assert_eq!(find("ab", smart_case), vec![0..2, 3..5, 6..8]);
+        assert_eq!(find("Ab", smart_case), vec![0..2]);
+        assert_eq!(
+            find("\\cAB", CaseOptions::default()),
+            vec![0..2, 3..5, 6..8]
+        );
+        assert_eq!(find("a\\Cb", ignore_case), vec![3..5]);
+        // an escaped class does not count as upper case
+        assert_eq!(find("\\Sb", smart_case), vec![0..2, 3..5, 6..8]);
+        assert_eq!(find("\\\\c", ignore_case), vec![]);
+    }
+}
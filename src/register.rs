@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+/// How the text of a register is put back into the buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterKind {
+    /// Inserted at the cursor.
+    Chars,
+    /// Whole lines, inserted above or below the cursor line.
+    Lines,
+    /// Rectangle of columns, one line of the text per buffer line.
+    Block,
+}
+
+/// Yanked or deleted text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    /// Text with `other` appended. Appending to or from lines gives lines.
+    fn append(&self, other: &Register) -> Register {
+        if self.kind == RegisterKind::Lines || other.kind == RegisterKind::Lines {
+            let mut text = self.text.clone();
+            if !text.is_empty() && !text.ends_with("\r\n") {
+                text.push_str("\r\n");
+            }
+            text.push_str(&other.text);
+            if !text.ends_with("\r\n") {
+                text.push_str("\r\n");
+            }
+            return Register {
+                text,
+                kind: RegisterKind::Lines,
+            };
+        }
+        Register {
+            text: self.text.clone() + &other.text,
+            kind: self.kind,
+        }
+    }
+}
+
+/// Unnamed register.
+pub const UNNAMED: char = '"';
+
+/// Register, which discards everything written to it.
+pub const BLACK_HOLE: char = '_';
+
+/// System clipboard. The editor copies text written to it to the clipboard;
+/// here it only updates the unnamed register.
+pub const CLIPBOARD: char = '+';
+
+/// All registers: the unnamed register `""`, the delete history `"1`-`"9`
+/// (`"1` is the latest) and the named registers `"a`-`"z`.
+#[derive(Default)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    deleted: Vec<Register>,
+    named: HashMap<char, Register>,
+}
+
+impl Registers {
+    /// Whether `name` can be given with `"{name}`. An uppercase letter
+    /// appends to the named register.
+    pub fn is_valid(name: char) -> bool {
+        matches!(name, UNNAMED | BLACK_HOLE | CLIPBOARD | '1'..='9' | 'a'..='z' | 'A'..='Z')
+    }
+
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            UNNAMED => self.unnamed.as_ref(),
+            '1'..='9' => self.deleted.get(name as usize - '1' as usize),
+            'a'..='z' | 'A'..='Z' => self.named.get(&name.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+
+    /// Store yanked text in register `name`, and in the unnamed register.
+    pub fn yank(&mut self, name: char, register: Register) {
+        self.write(name, register);
+    }
+
+    /// Store deleted text in register `name`. Without a name, the text is
+    /// also added to the delete history.
+    pub fn delete(&mut self, name: char, register: Register) {
+        if name == UNNAMED {
+            self.deleted.insert(0, register.clone());
+            self.deleted.truncate(9);
+        }
+        self.write(name, register);
+    }
+
+    fn write(&mut self, name: char, register: Register) {
+        match name {
+            BLACK_HOLE => (),
+            'a'..='z' | 'A'..='Z' => {
+                self.set_named(name, register);
+                self.unnamed = self.named.get(&name.to_ascii_lowercase()).cloned();
+            }
+            _ => self.unnamed = Some(register),
+        }
+    }
+
+    /// Store text in a named register, without changing the unnamed register
+    /// (e.g. a recorded macro). An uppercase name appends.
+    pub fn set_named(&mut self, name: char, register: Register) {
+        let name_lowercase = name.to_ascii_lowercase();
+        let register = match self.named.get(&name_lowercase) {
+            Some(existing) if name.is_ascii_uppercase() => existing.append(&register),
+            _ => register,
+        };
+        self.named.insert(name_lowercase, register);
+    }
+
+    /// All registers with content, in the order `:registers` shows them.
+    pub fn list(&self) -> Vec<(char, &Register)> {
+        let mut list: Vec<(char, &Register)> = vec![];
+        if let Some(register) = &self.unnamed {
+            list.push((UNNAMED, register));
+        }
+        for (i, register) in self.deleted.iter().enumerate() {
+            list.push((char::from(b'1' + i as u8), register));
+        }
+        let mut named: Vec<(char, &Register)> = self
+            .named
+            .iter()
+            .map(|(name, register)| (*name, register))
+            .collect();
+        named.sort_by_key(|(name, _)| *name);
+        list.extend(named);
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Register {
+        Register {
+            text: text.to_string(),
+            kind: RegisterKind::Chars,
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut registers = Registers::default();
+        registers.yank(UNNAMED, chars("yanked"));
+        for i in 0..10 {
+            registers.delete(UNNAMED, chars(&i.to_string()));
+        }
+        assert_eq!(registers.get(UNNAMED), Some(&chars("9")));
+        assert_eq!(registers.get('1'), Some(&chars("9")));
+        assert_eq!(registers.get('9'), Some(&chars("1")));
+
+        registers.yank('a', chars("one"));
+        registers.delete('A', chars(" two"));
+        assert_eq!(registers.get('a'), Some(&chars("one two")));
+        assert_eq!(registers.get(UNNAMED), Some(&chars("one two")));
+        assert_eq!(registers.get('1'), Some(&chars("9")));
+
+        registers.delete(BLACK_HOLE, chars("gone"));
+        assert_eq!(registers.get(UNNAMED), Some(&chars("one two")));
+
+        let line = Register {
+            text: "line\r\n".to_string(),
+            kind: RegisterKind::Lines,
+        };
+        registers.yank('A', line);
+        assert_eq!(
+            registers.get('a'),
+            Some(&Register {
+                text: "one two\r\nline\r\n".to_string(),
+                kind: RegisterKind::Lines
+            })
+        );
+        assert_eq!(registers.list().len(), 11);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{ex_command, regex::Captures};
+
+/// Arguments of `:s/pattern/replacement/[flags]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    /// `g`: replace all matches in a line, not only the first one.
+    pub global: bool,
+    /// `c`: ask before each replacement.
+    pub confirm: bool,
+    /// `i`: ignore case, `I`: match case. Without either, the search
+    /// options are used.
+    pub ignore_case: Option<bool>,
+}
+
+/// Parse the argument of `:s`. The first character is the delimiter, e.g.
+/// `/` or `#`.
+pub fn parse(argument: &str) -> Result<Substitute, String> {
+    let mut chars = argument.chars();
+    let delimiter = match chars.next() {
+        Some(ch) if !ch.is_alphanumeric() && !matches!(ch, '\\' | '"' | '|' | ' ') => ch,
+        _ => return Err("Expected s/pattern/replacement/".to_string()),
+    };
+    let (pattern, rest) = ex_command::split_pattern(chars.as_str(), delimiter);
+    let (replacement, flags) = ex_command::split_pattern(rest, delimiter);
+    let mut substitute = Substitute {
+        pattern,
+        replacement,
+        global: false,
+        confirm: false,
+        ignore_case: None,
+    };
+    for flag in flags.trim_end().chars() {
+        match flag {
+            'g' => substitute.global = true,
+            'c' => substitute.confirm = true,
+            'i' => substitute.ignore_case = Some(true),
+            'I' => substitute.ignore_case = Some(false),
+            _ => return Err(format!("Trailing characters: {}", flags)),
+        }
+    }
+    Ok(substitute)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+/// Case change of the replacement by `\u` and `\l` (`next`), or by `\U` and
+/// `\L` (`all`).
+#[derive(Default)]
+struct CaseChange {
+    next: Option<Case>,
+    all: Option<Case>,
+}
+
+impl CaseChange {
+    fn push(&mut self, expanded: &mut String, ch: char) {
+        match self.next.take().or(self.all) {
+            Some(Case::Upper) => expanded.extend(ch.to_uppercase()),
+            Some(Case::Lower) => expanded.extend(ch.to_lowercase()),
+            None => expanded.push(ch),
+        }
+    }
+}
+
+/// Text replacing a match of `:s`. In the replacement, `&` and `\0` stand
+/// for the whole match and `\1` to `\9` for its groups. `\r` and `\n` insert
+/// a line break, `\t` a tab. `\u` and `\l` change the case of the next
+/// character, `\U` and `\L` of the characters up to `\e` or `\E`.
+pub fn expand(replacement: &str, text: &str, captures: &Captures) -> String {
+    let mut expanded = String::new();
+    let mut case = CaseChange::default();
+    let group = |index: usize| captures.get(index).map_or("", |range| &text[range]);
+
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        let inserted = match ch {
+            '&' => group(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => group(digit as usize - '0' as usize),
+                Some('r' | 'n') => "\r\n",
+                Some('t') => "\t",
+                Some('u') => {
+                    case.next = Some(Case::Upper);
+                    continue;
+                }
+                Some('l') => {
+                    case.next = Some(Case::Lower);
+                    continue;
+                }
+                Some('U') => {
+                    case.all = Some(Case::Upper);
+                    continue;
+                }
+                Some('L') => {
+                    case.all = Some(Case::Lower);
+                    continue;
+                }
+                Some('e' | 'E') => {
+                    case.all = None;
+                    continue;
+                }
+                Some(escaped) => {
+                    case.push(&mut expanded, escaped);
+                    continue;
+                }
+                None => "\\",
+            },
+            _ => {
+                case.push(&mut expanded, ch);
+                continue;
+            }
+        };
+        for ch in inserted.chars() {
+            case.push(&mut expanded, ch);
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::Regex;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("#a/b#c\\#d#gc").unwrap(),
+            Substitute {
+                pattern: "a/b".to_string(),
+                replacement: "c#d".to_string(),
+                global: true,
+                confirm: true,
+                ignore_case: None,
+            }
+        );
+        let substitute = parse("/x").unwrap();
+        assert_eq!(substitute.pattern, "x");
+        assert_eq!(substitute.replacement, "");
+        assert_eq!(parse("/x/y/I").unwrap().ignore_case, Some(false));
+        assert!(parse("/a/b/x").is_err());
+        assert!(parse("abc").is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let text = "let some_name = 1;";
+        let regex = Regex::new("\\(\\w\\+\\) = \\(\\d\\)", false).unwrap();
+        let captures = regex.find_at(text, 0).unwrap();
+        assert_eq!(expand("\\2 = \\1", text, &captures), "1 = some_name");
+        assert_eq!(
+            expand("[&] \\& \\\\", text, &captures),
+            "[some_name = 1] & \\"
+        );
+        assert_eq!(expand("\\u\\1\\r", text, &captures), "Some_name\r\n");
+        assert_eq!(
+            expand("\\U\\1\\e_\\1", text, &captures),
+            "SOME_NAME_some_name"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::piece_table::Edit;
+
+const HEADER: &str = "nimbus-swap 1";
+
+/// Journal of the edits made to a file since it was loaded or saved.
+///
+/// The file starts with a header line containing the hash of the file
+/// contents the edits apply to, followed by one record per edit:
+/// `I <offset> <length>\n<text>\n`, `D <offset> <count>\n`, `U\n`, `R\n`,
+/// `B\n` or `E\n` (begin / end of an undo group). Records are only ever
+/// appended, so an incomplete record at the end (e.g. after a crash while
+/// writing) is ignored when reading.
+#[derive(Debug, PartialEq)]
+pub struct SwapFile {
+    pub content_hash: u64,
+    pub edits: Vec<Edit>,
+}
+
+/// Path of the swap file for `file_path`: `.<name>.nimbus-swp` in the same
+/// directory. The name differs from vim's `.<name>.swp`, so a vim session
+/// editing the same file keeps its swap file.
+pub fn swap_path(file_path: &str) -> PathBuf {
+    let path = Path::new(file_path);
+    let name = path
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().to_string());
+    path.with_file_name(format!(".{}.nimbus-swp", name))
+}
+
+/// Append edits to the swap file, creating it if it does not exist.
+pub fn append(path: &Path, content_hash: u64, edits: &[Edit]) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+
+    let mut out = String::new();
+    if file.metadata()?.len() == 0 {
+        out.push_str(&format!("{} {:016x}\n", HEADER, content_hash));
+    }
+    for edit in edits {
+        match edit {
+            Edit::Insert { offset, text } => {
+                out.push_str(&format!("I {} {}\n{}\n", offset, text.len(), text))
+            }
+            Edit::Delete { offset, count } => out.push_str(&format!("D {} {}\n", offset, count)),
+            Edit::Undo => out.push_str("U\n"),
+            Edit::Redo => out.push_str("R\n"),
+            Edit::BeginUndoGroup => out.push_str("B\n"),
+            Edit::EndUndoGroup => out.push_str("E\n"),
+        }
+    }
+
+    file.write_all(out.as_bytes())?;
+    file.sync_data()
+}
+
+/// Read the swap file, if one exists.
+pub fn read(path: &Path) -> io::Result<Option<SwapFile>> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut rest = contents.as_slice();
+    let header = next_line(&mut rest).unwrap_or_default();
+    let content_hash = header
+        .strip_prefix(HEADER)
+        .and_then(|hash| u64::from_str_radix(hash.trim(), 16).ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not a swap file"))?;
+
+    let mut edits = vec![];
+    while let Some(line) = next_line(&mut rest) {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let numbers: Vec<usize> = fields
+            .iter()
+            .skip(1)
+            .filter_map(|f| f.parse().ok())
+            .collect();
+
+        let edit = match (fields[0], numbers.as_slice()) {
+            ("I", [offset, length]) => {
+                let Some(text) = rest
+                    .get(..*length)
+                    .and_then(|t| String::from_utf8(t.to_vec()).ok())
+                else {
+                    break;
+                };
+                if rest.get(*length) != Some(&b'\n') {
+                    break;
+                }
+                rest = &rest[length + 1..];
+                Edit::Insert {
+                    offset: *offset,
+                    text,
+                }
+            }
+            ("D", [offset, count]) => Edit::Delete {
+                offset: *offset,
+                count: *count,
+            },
+            ("U", []) => Edit::Undo,
+            ("R", []) => Edit::Redo,
+            ("B", []) => Edit::BeginUndoGroup,
+            ("E", []) => Edit::EndUndoGroup,
+            _ => break,
+        };
+        edits.push(edit);
+    }
+
+    Ok(Some(SwapFile {
+        content_hash,
+        edits,
+    }))
+}
+
+/// Take the next complete line, without the `\n`.
+fn next_line(rest: &mut &[u8]) -> Option<String> {
+    let end = rest.iter().position(|b| *b == b'\n')?;
+    let line = String::from_utf8_lossy(&rest[..end]).to_string();
+    *rest = &rest[end + 1..];
+    Some(line)
+}
+
+/// Remove the swap file. A missing file is not an error.
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_path() {
+        assert_eq!(
+            swap_path("src/main.rs"),
+            PathBuf::from("src/.main.rs.nimbus-swp")
+        );
+        assert_eq!(
+            swap_path("Cargo.toml"),
+            PathBuf::from(".Cargo.toml.nimbus-swp")
+        );
+    }
+
+    #[test]
+    fn test_swap_file_roundtrip() {
+        let path = std::env::temp_dir().join(format!("nimbus-swap-test-{}", std::process::id()));
+        remove(&path).unwrap();
+
+        let edits = [
+            Edit::BeginUndoGroup,
+            Edit::Insert {
+                offset: 3,
+                text: "has\r\nGrüße".to_string(),
+            },
+            Edit::EndUndoGroup,
+        ];
+        append(&path, 42, &edits[..2]).unwrap();
+        append(&path, 42, &edits[2..]).unwrap();
+        append(
+            &path,
+            42,
+            &[
+                Edit::Delete {
+                    offset: 1,
+                    count: 2,
+                },
+                Edit::Undo,
+            ],
+        )
+        .unwrap();
+
+        let swap = read(&path).unwrap().unwrap();
+        assert_eq!(swap.content_hash, 42);
+        assert_eq!(swap.edits.len(), 5);
+        assert_eq!(swap.edits[..3], edits[..]);
+        assert_eq!(swap.edits[4], Edit::Undo);
+
+        // an incomplete record at the end is ignored
+        fs::write(
+            &path,
+            [fs::read(&path).unwrap(), b"I 0 10\nabc".to_vec()].concat(),
+        )
+        .unwrap();
+        assert_eq!(read(&path).unwrap().unwrap().edits.len(), 5);
+
+        remove(&path).unwrap();
+        assert_eq!(read(&path).unwrap(), None);
+    }
+}
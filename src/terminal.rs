@@ -1,31 +1,95 @@
-use std::io::{stdout, Error, Stdin, Stdout, Write};
+use std::cell::Cell;
+use std::io::{stdin, stdout, Error, ErrorKind, Stdout, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 use termion::{
     color,
-    event::Key,
+    event::{Event, Key},
     input::TermRead,
     raw::{IntoRawMode, RawTerminal},
 };
 
-use crate::position::Position;
+use crate::{clipboard, position::Position};
+
+/// Escape sequences to enable/disable focus reporting. With it enabled, the
+/// terminal sends `FOCUS_GAINED` when its window is focused again.
+const ENABLE_FOCUS_REPORTING: &str = "\x1b[?1004h";
+const DISABLE_FOCUS_REPORTING: &str = "\x1b[?1004l";
+const FOCUS_GAINED: &[u8] = b"\x1b[I";
+
+/// Input read from the terminal.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Key(Key),
+    FocusGained,
+}
 
 pub struct Terminal {
     size: (u16, u16),
-    stdout: RawTerminal<Stdout>,
+    /// Stdout in raw mode. None for a terminal of a fixed size, which does
+    /// not need a TTY (e.g. in tests).
+    stdout: Option<RawTerminal<Stdout>>,
+    /// Column where lines written after a line break start.
+    line_start: Cell<usize>,
+    /// Row of the cursor, as of the last `goto` and the line breaks since.
+    row: Cell<usize>,
+    /// Input read by a thread, so waiting for it can time out. The thread is
+    /// started by the first `read_input`.
+    input: Option<Receiver<Result<Input, Error>>>,
+}
+
+/// Read the input from stdin in a new thread.
+fn spawn_input_reader() -> Receiver<Result<Input, Error>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for event in stdin().lock().events() {
+            let input = match event {
+                Ok(Event::Key(key)) => Ok(Input::Key(key)),
+                Ok(Event::Unsupported(bytes)) if bytes == FOCUS_GAINED => Ok(Input::FocusGained),
+                Ok(_) => continue,
+                Err(e) => Err(e),
+            };
+            // the editor quit
+            if sender.send(input).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
 }
 
 impl Terminal {
     pub fn new() -> Result<Terminal, Error> {
         let size = termion::terminal_size()?;
-        Ok(Terminal {
+        let mut stdout = stdout().into_raw_mode()?;
+        write!(stdout, "{}", ENABLE_FOCUS_REPORTING)?;
+        let mut terminal = Terminal::with_size(size);
+        terminal.stdout = Some(stdout);
+        Ok(terminal)
+    }
+
+    /// Terminal of the given size (columns, rows), without switching stdout
+    /// into raw mode.
+    pub fn with_size(size: (u16, u16)) -> Terminal {
+        Terminal {
             size,
-            stdout: stdout().into_raw_mode()?,
-        })
+            stdout: None,
+            line_start: Cell::new(0),
+            row: Cell::new(0),
+            input: None,
+        }
     }
 
-    pub fn read_key(&self, stdin: &Stdin) -> Result<Key, Error> {
-        loop {
-            if let Some(key) = stdin.lock().keys().next() {
-                return key;
+    /// Wait up to `timeout` for a key or another input. Returns None, if
+    /// there was none.
+    pub fn read_input(&mut self, timeout: Duration) -> Result<Option<Input>, Error> {
+        let input = self.input.get_or_insert_with(spawn_input_reader);
+        match input.recv_timeout(timeout) {
+            Ok(input) => input.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Error::new(ErrorKind::UnexpectedEof, "Input closed"))
             }
         }
     }
@@ -34,17 +98,42 @@ impl Terminal {
         print!("{}", termion::clear::All);
     }
 
+    pub fn clear_line(&self) {
+        print!("{}", termion::clear::CurrentLine);
+    }
+
     pub fn goto(&self, position: &Position) {
         let (x, y) = position.get_terminal();
+        self.row.set(position.y);
         print!("{}", termion::cursor::Goto(x, y));
     }
 
+    /// Set the column where written lines start after a line break, to draw
+    /// text into a window which does not start at the left edge.
+    pub fn set_line_start(&self, x: usize) {
+        self.line_start.set(x);
+    }
+
+    /// Print the text with the given style. Line breaks move the cursor to
+    /// the line start in the next row.
+    fn print_lines(&self, text: &str, style: &str, reset: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.goto(&Position::new(self.line_start.get(), self.row.get() + 1));
+            }
+            print!("{}{}{}", style, line.trim_end_matches('\r'), reset);
+        }
+    }
+
     pub fn write(&self, buffer: &str) {
-        print!("{}", buffer);
+        self.print_lines(buffer, "", "");
     }
 
     pub fn flush(&mut self) {
-        self.stdout.flush().unwrap();
+        match &mut self.stdout {
+            Some(stdout) => stdout.flush().unwrap(),
+            None => stdout().flush().unwrap(),
+        }
     }
 
     pub fn size(&self) -> (u16, u16) {
@@ -52,7 +141,11 @@ impl Terminal {
     }
 
     pub fn write_with_color(&self, buffer: &str, color: &dyn color::Color) {
-        print!("{}{}{}", color::Fg(color), buffer, color::Fg(color::Reset));
+        self.print_lines(
+            buffer,
+            &color::Fg(color).to_string(),
+            &color::Fg(color::Reset).to_string(),
+        );
     }
 
     pub fn write_with_color_bg(
@@ -61,16 +154,18 @@ impl Terminal {
         color: &dyn color::Color,
         bg_color: &dyn color::Color,
     ) {
-        print!(
-            "{}{}{}{}{}",
-            color::Bg(bg_color),
-            color::Fg(color),
+        self.print_lines(
             buffer,
-            color::Fg(color::Reset),
-            color::Bg(color::Reset)
+            &format!("{}{}", color::Bg(bg_color), color::Fg(color)),
+            &format!("{}{}", color::Fg(color::Reset), color::Bg(color::Reset)),
         );
     }
 
+    /// Copy the text into the clipboard of the terminal (OSC 52).
+    pub fn copy_to_clipboard(&self, text: &str) {
+        print!("{}", clipboard::osc52(text));
+    }
+
     pub fn show_cursor(&self) {
         print!("{}", termion::cursor::Show);
     }
@@ -79,3 +174,12 @@ impl Terminal {
         print!("{}", termion::cursor::Hide);
     }
 }
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if let Some(stdout) = &mut self.stdout {
+            write!(stdout, "{}", DISABLE_FOCUS_REPORTING).ok();
+            stdout.flush().ok();
+        }
+    }
+}
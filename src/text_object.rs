@@ -0,0 +1,427 @@
+use std::ops::Range;
+
+use crate::{
+    file_extension::FileExtension,
+    operator::TextRange,
+    tokenizer::{TokenType, Tokenizer},
+    tokenizer_rust::TokenizerRust,
+    tokenizer_toml::TokenizerToml,
+    unicode,
+};
+
+/// Text selected by the text object `i{object}` or `a{object}` (with
+/// `around`) at a byte offset of the text. The `a` variants include the
+/// surrounding white space, quotes, brackets or tags.
+///
+/// `strings` are the byte ranges of the string constants in the text (see
+/// `string_ranges`). A quoted object inside of one selects the whole string,
+/// and brackets inside of strings are not matched, unless the cursor is in
+/// the same string.
+pub fn find(
+    text: &str,
+    offset: usize,
+    object: char,
+    around: bool,
+    strings: &[Range<usize>],
+) -> Option<TextRange> {
+    match object {
+        'w' => word(text, offset, around, false),
+        'W' => word(text, offset, around, true),
+        '"' | '\'' | '`' => quoted(text, offset, object, around, strings),
+        '(' | ')' | 'b' => brackets(text, offset, ('(', ')'), around, strings),
+        '[' | ']' => brackets(text, offset, ('[', ']'), around, strings),
+        '{' | '}' | 'B' => brackets(text, offset, ('{', '}'), around, strings),
+        '<' | '>' => brackets(text, offset, ('<', '>'), around, strings),
+        't' => tag(text, offset, around),
+        'p' => paragraph(text, offset, around),
+        _ => None,
+    }
+}
+
+/// Byte ranges of the string constants in Rust or TOML code, including the
+/// quotes, as found by the tokenizers.
+pub fn string_ranges(text: &str, file_extension: &FileExtension) -> Vec<Range<usize>> {
+    let range_of = |token: &str| {
+        let start = token.as_ptr() as usize - text.as_ptr() as usize;
+        start..start + token.len()
+    };
+
+    let mut ranges = vec![];
+    match file_extension {
+        FileExtension::Rust => {
+            let mut tokenizer = TokenizerRust::new(text);
+            while let Some(token_type) = tokenizer.next() {
+                let token = tokenizer.token().trim_start();
+                if token_type == TokenType::Constant && token.starts_with('"') {
+                    ranges.push(range_of(token));
+                }
+            }
+        }
+        FileExtension::Toml => {
+            let mut tokenizer = TokenizerToml::new(text);
+            while let Some(token_type) = tokenizer.next() {
+                let token = tokenizer.token();
+                if let (TokenType::StringConstant, Some(quote)) = (token_type, token.find('"')) {
+                    ranges.push(range_of(&token[quote..]));
+                }
+            }
+        }
+        _ => (),
+    }
+    ranges
+}
+
+/// Byte ranges of the comments in Rust or TOML code, as found by the
+/// tokenizers.
+pub fn comment_ranges(text: &str, file_extension: &FileExtension) -> Vec<Range<usize>> {
+    let range_of = |token: &str| {
+        let token = token.trim();
+        let start = token.as_ptr() as usize - text.as_ptr() as usize;
+        start..start + token.len()
+    };
+
+    let mut ranges = vec![];
+    match file_extension {
+        FileExtension::Rust => {
+            let mut tokenizer = TokenizerRust::new(text);
+            while let Some(token_type) = tokenizer.next() {
+                if token_type == TokenType::Comment {
+                    ranges.push(range_of(tokenizer.token()));
+                }
+            }
+        }
+        FileExtension::Toml => {
+            let mut tokenizer = TokenizerToml::new(text);
+            while let Some(token_type) = tokenizer.next() {
+                if token_type == TokenType::Comment {
+                    ranges.push(range_of(tokenizer.token()));
+                }
+            }
+        }
+        _ => (),
+    }
+    ranges
+}
+
+/// Number of bytes of the white space (without line breaks) at the start of
+/// the text, or at its end with `reverse`.
+fn blank_len(text: &str, reverse: bool) -> usize {
+    let is_blank = |ch: &char| *ch == ' ' || *ch == '\t';
+    match reverse {
+        true => text
+            .chars()
+            .rev()
+            .take_while(is_blank)
+            .map(char::len_utf8)
+            .sum(),
+        false => text.chars().take_while(is_blank).map(char::len_utf8).sum(),
+    }
+}
+
+/// Range with the white space after it added, or before it if there is none.
+fn with_blanks(text: &str, range: Range<usize>) -> Range<usize> {
+    match blank_len(&text[range.end..], false) {
+        0 => range.start - blank_len(&text[..range.start], true)..range.end,
+        trailing => range.start..range.end + trailing,
+    }
+}
+
+fn word(text: &str, offset: usize, around: bool, big: bool) -> Option<TextRange> {
+    // 0: white space, 1: word characters, 2: punctuation
+    let class = |ch: char| -> Option<u8> {
+        match ch {
+            '\r' | '\n' => None,
+            ' ' | '\t' => Some(0),
+            _ if big || ch.is_alphanumeric() || ch == '_' => Some(1),
+            _ => Some(2),
+        }
+    };
+    let run_len = |chars: &mut dyn Iterator<Item = char>, class_of_run: u8| -> usize {
+        chars
+            .take_while(|ch| unicode::is_combining(*ch) || class(*ch) == Some(class_of_run))
+            .map(char::len_utf8)
+            .sum()
+    };
+
+    let current = class(text[offset..].chars().next()?)?;
+    let start = offset - run_len(&mut text[..offset].chars().rev(), current);
+    let end = offset + run_len(&mut text[offset..].chars(), current);
+    if !around {
+        return Some(TextRange::Chars(start..end));
+    }
+
+    if current == 0 {
+        // the white space and the word after it
+        let next = text[end..].chars().next().and_then(class);
+        let word = next.map_or(0, |next| run_len(&mut text[end..].chars(), next));
+        return Some(TextRange::Chars(start..end + word));
+    }
+    Some(TextRange::Chars(with_blanks(text, start..end)))
+}
+
+fn quoted(
+    text: &str,
+    offset: usize,
+    quote: char,
+    around: bool,
+    strings: &[Range<usize>],
+) -> Option<TextRange> {
+    let string = strings
+        .iter()
+        .find(|range| range.contains(&offset) && text[(*range).clone()].starts_with(quote));
+    let (open, close) = match string {
+        Some(range) => (range.start, range.end - quote.len_utf8()),
+        None => {
+            // quotes in the line are paired from the start of the line
+            let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+            let mut quotes = vec![];
+            let mut escaped = false;
+            for (i, ch) in text[line_start..line_end].char_indices() {
+                if ch == quote && !escaped {
+                    quotes.push(line_start + i);
+                }
+                escaped = ch == '\\' && !escaped;
+            }
+            quotes
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|(_, close)| offset <= *close)?
+        }
+    };
+
+    let range = match around {
+        true => with_blanks(text, open..close + quote.len_utf8()),
+        false => open + quote.len_utf8()..close,
+    };
+    Some(TextRange::Chars(range))
+}
+
+fn brackets(
+    text: &str,
+    offset: usize,
+    (open_ch, close_ch): (char, char),
+    around: bool,
+    strings: &[Range<usize>],
+) -> Option<TextRange> {
+    let string_at = |i: usize| strings.iter().find(|range| range.contains(&i));
+    let cursor_string = string_at(offset);
+    let skip = |i: usize| string_at(i).is_some_and(|string| Some(string) != cursor_string);
+
+    // the bracket at the cursor belongs to the pair
+    let cursor_end = offset + text[offset..].chars().next().map_or(0, char::len_utf8);
+    let mut depth = 0;
+    let mut open = None;
+    for (i, ch) in text[..cursor_end].char_indices().rev() {
+        if skip(i) {
+            continue;
+        }
+        if ch == close_ch && i != offset {
+            depth += 1;
+        } else if ch == open_ch {
+            if depth == 0 {
+                open = Some(i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let open = open?;
+
+    let mut depth = 0;
+    let mut close = None;
+    for (i, ch) in text[open + 1..].char_indices() {
+        let i = open + 1 + i;
+        if skip(i) {
+            continue;
+        }
+        if ch == open_ch {
+            depth += 1;
+        } else if ch == close_ch {
+            if depth == 0 {
+                close = Some(i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let close = close?;
+
+    if around {
+        return Some(TextRange::Chars(open..close + 1));
+    }
+    // brackets on lines of their own stay on their lines
+    let mut start = open + 1;
+    let mut end = close;
+    if let Some(line_break) = text[start..end].find('\n') {
+        if text[start..start + line_break].trim().is_empty() {
+            start += line_break + 1;
+        }
+    }
+    if let Some(line_break) = text[..end].rfind('\n') {
+        if line_break >= start && text[line_break..end].trim().is_empty() {
+            end = text[..line_break]
+                .strip_suffix('\r')
+                .map_or(line_break, str::len);
+        }
+    }
+    Some(TextRange::Chars(start..std::cmp::max(start, end)))
+}
+
+fn tag(text: &str, offset: usize, around: bool) -> Option<TextRange> {
+    // open tags without a close tag yet: name, start and end
+    let mut open_tags: Vec<(&str, usize, usize)> = vec![];
+    let mut i = 0;
+    while let Some(found) = text[i..].find('<') {
+        let start = i + found;
+        let Some(length) = text[start..].find('>') else {
+            break;
+        };
+        let end = start + length + 1;
+        let inside = &text[start + 1..end - 1];
+        i = end;
+
+        if let Some(name) = inside.strip_prefix('/') {
+            let Some(index) = open_tags.iter().rposition(|tag| tag.0 == name.trim()) else {
+                continue;
+            };
+            let (_, open_start, open_end) = open_tags[index];
+            open_tags.truncate(index);
+            // inner tags are closed first
+            if open_start <= offset && offset < end {
+                return Some(TextRange::Chars(match around {
+                    true => open_start..end,
+                    false => open_end..start,
+                }));
+            }
+        } else if !inside.ends_with('/') {
+            let name = inside.split_whitespace().next().unwrap_or("");
+            if name
+                .chars()
+                .all(|ch| ch.is_alphanumeric() || ch == '-' || ch == ':')
+            {
+                open_tags.push((name, start, end));
+            }
+        }
+    }
+    None
+}
+
+fn paragraph(text: &str, offset: usize, around: bool) -> Option<TextRange> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let is_blank = |y: usize| lines[y].trim().is_empty();
+    let y = text[..offset].matches('\n').count();
+    let blank = is_blank(y);
+
+    let mut first = y;
+    while first > 0 && is_blank(first - 1) == blank {
+        first -= 1;
+    }
+    let mut last = y;
+    while last + 1 < lines.len() && is_blank(last + 1) == blank {
+        last += 1;
+    }
+
+    if around {
+        // the blank lines after a paragraph, or before it if there are none
+        let end = last;
+        while last + 1 < lines.len() && is_blank(last + 1) != blank {
+            last += 1;
+        }
+        if last == end && !blank {
+            while first > 0 && is_blank(first - 1) {
+                first -= 1;
+            }
+        }
+    }
+    Some(TextRange::Lines(first, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select(text: &str, cursor: &str, object: char, around: bool) -> Option<String> {
+        let offset = text.find(cursor).unwrap();
+        let strings = string_ranges(text, &FileExtension::Rust);
+        match find(text, offset, object, around, &strings)? {
+            TextRange::Chars(range) => Some(text[range].to_string()),
+            range => Some(format!("{:?}", range)),
+        }
+    }
+
+    #[test]
+    fn test_words() {
+        let text = "let value = compute(a, b);";
+        assert_eq!(select(text, "lue", 'w', false).unwrap(), "value");
+        assert_eq!(select(text, "lue", 'w', true).unwrap(), "value ");
+        assert_eq!(select(text, "(a", 'w', false).unwrap(), "(");
+        assert_eq!(select(text, "(a", 'W', false).unwrap(), "compute(a,");
+        assert_eq!(select(text, "b);", 'w', true).unwrap(), " b");
+        assert_eq!(select(text, " = ", 'w', true).unwrap(), " =");
+    }
+
+    #[test]
+    fn test_quotes() {
+        let text = "print(\"a (b\", 'c', \"d\\\"e\")";
+        assert_eq!(select(text, "a (", '"', false).unwrap(), "a (b");
+        assert_eq!(select(text, "a (", '"', true).unwrap(), "\"a (b\"");
+        assert_eq!(select(text, "c'", '\'', true).unwrap(), " 'c'");
+        assert_eq!(select(text, "print", '\'', false).unwrap(), "c");
+        assert_eq!(select(text, "e\"", '"', false).unwrap(), "d\\\"e");
+    }
+
+    #[test]
+    fn test_brackets() {
+        let text = "f(\"(\", g(x), [1]) + 2";
+        assert_eq!(select(text, "x", '(', false).unwrap(), "x");
+        assert_eq!(
+            select(text, ", g", ')', true).unwrap(),
+            "(\"(\", g(x), [1])"
+        );
+        assert_eq!(select(text, "(x", 'b', false).unwrap(), "x");
+        assert_eq!(select(text, "1", '[', true).unwrap(), "[1]");
+        assert_eq!(select(text, "+", '(', false), None);
+
+        let text = "fn main() {\r\n    let x = 1;\r\n}";
+        assert_eq!(select(text, "let", '{', false).unwrap(), "    let x = 1;");
+        assert_eq!(select(text, "x =", 'B', true).unwrap(), &text[10..]);
+    }
+
+    #[test]
+    fn test_tags() {
+        let text = "<div class=\"a\"><b>bold</b> text<br/></div>";
+        assert_eq!(select(text, "old", 't', false).unwrap(), "bold");
+        assert_eq!(select(text, "old", 't', true).unwrap(), "<b>bold</b>");
+        assert_eq!(
+            select(text, "text", 't', false).unwrap(),
+            "<b>bold</b> text<br/>"
+        );
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let text = "a\r\nb\r\n\r\n\r\nc\r\n";
+        assert_eq!(select(text, "b", 'p', false).unwrap(), "Lines(0, 1)");
+        assert_eq!(select(text, "b", 'p', true).unwrap(), "Lines(0, 3)");
+        assert_eq!(select(text, "\r\n\r\nc", 'p', true).unwrap(), "Lines(2, 4)");
+        assert_eq!(select(text, "c", 'p', true).unwrap(), "Lines(4, 5)");
+    }
+
+    #[test]
+    fn test_string_ranges() {
+        let text = "let a = \"x)\"; // \"comment\"\r\nlet b = 1;";
+        assert_eq!(string_ranges(text, &FileExtension::Rust), vec![8..12]);
+        let text = "name = \"nimbus\"\r\nversion = \"0.1\"\r\n";
+        assert_eq!(
+            string_ranges(text, &FileExtension::Toml),
+            vec![7..15, 27..32]
+        );
+    }
+
+    #[test]
+    fn test_comment_ranges() {
+        let text = "let a = \"//\"; // (x\r\nlet b = 1;\r\n";
+        assert_eq!(comment_ranges(text, &FileExtension::Rust), vec![14..19]);
+    }
+}
@@ -37,49 +37,50 @@ impl<'a> Tokenizer<'a> for TokenizerRust<'a> {
         let mut string_constant = false;
         let mut comment = false;
 
-        for (i, ch) in self.text.chars().skip(self.counter).enumerate() {
-            let comp = &self.text[self.counter..self.counter + i + 1];
+        // `counter` and `i` are byte offsets, `end` is the end of the current char
+        for (i, ch) in self.text[self.counter..].char_indices() {
+            let end = i + ch.len_utf8();
+            let comp = &self.text[self.counter..self.counter + end];
+            let next_ch = self.text[self.counter + end..].chars().next();
 
             if comment {
                 if ch == '\n' {
                     token_type = Some(TokenType::Comment);
                     self.token = comp;
-                    self.counter += i + 1;
+                    self.counter += end;
                     break;
                 }
                 continue;
             }
 
-            let next_ch = self.text.chars().nth(self.counter + i + 1);
-            if ch == '/' && next_ch.is_some() && next_ch.unwrap() == '/' {
-                comment = true;
-                continue;
-            }
-
             if string_constant {
                 if ch == '"' {
                     token_type = Some(TokenType::Constant);
                     self.token = comp;
-                    self.counter += i + 1;
+                    self.counter += end;
                     break;
                 }
                 continue;
             }
 
+            if ch == '/' && next_ch == Some('/') {
+                comment = true;
+                continue;
+            }
+
             if comp.trim().is_empty() {
                 continue;
             }
 
             let mut keyword_end = false;
-            let next_ch = self.text.chars().nth(self.counter + i + 1);
-            if next_ch.is_some() {
-                keyword_end = !next_ch.unwrap().is_alphanumeric() && next_ch.unwrap() != '_';
+            if let Some(next_ch) = next_ch {
+                keyword_end = !next_ch.is_alphanumeric() && next_ch != '_';
             }
 
             if keyword_end && self.keywords.contains(&comp.trim().to_string()) {
                 token_type = Some(TokenType::Keyword);
                 self.token = comp;
-                self.counter += i + 1;
+                self.counter += end;
                 break;
             }
 
@@ -91,21 +92,21 @@ impl<'a> Tokenizer<'a> for TokenizerRust<'a> {
             if self.symbols.contains(&ch) {
                 token_type = Some(TokenType::Symbol);
                 self.token = comp;
-                self.counter += i + 1;
+                self.counter += end;
                 break;
             }
 
             if comp.trim().parse::<usize>().is_ok() {
                 token_type = Some(TokenType::Constant);
                 self.token = comp;
-                self.counter += i + 1;
+                self.counter += end;
                 break;
             }
 
             if let Some(next_ch) = next_ch {
                 if next_ch == ' ' || self.symbols.contains(&next_ch) {
                     self.token = comp;
-                    self.counter += i + 1;
+                    self.counter += end;
                     token_type = Some(TokenType::Identifier);
                     break;
                 }
@@ -260,4 +261,23 @@ mod tests {
         assert_eq!(tokenizer.next(), None);
         assert_eq!(tokenizer.token(), "");
     }
+
+    #[test]
+    fn test_tokenizer_rust_multi_byte() {
+        let code = "// Grüße 🦀\nlet s = \"äöü\";";
+
+        let mut tokenizer = TokenizerRust::new(code);
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Comment);
+        assert_eq!(tokenizer.token(), "// Grüße 🦀\n");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Keyword);
+        assert_eq!(tokenizer.token(), "let");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Identifier);
+        assert_eq!(tokenizer.token().trim(), "s");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Symbol);
+        assert_eq!(tokenizer.token().trim(), "=");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Constant);
+        assert_eq!(tokenizer.token().trim(), "\"äöü\"");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Symbol);
+        assert_eq!(tokenizer.token(), ";");
+    }
 }
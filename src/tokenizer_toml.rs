@@ -67,17 +67,18 @@ impl<'a> Tokenizer<'a> for TokenizerToml<'a> {
                     let mut i = 0;
                     let mut it = token.chars().peekable();
 
+                    // `start` and `i` are byte offsets into the token
                     while let Some(ch) = it.next() {
                         let next_ch = *it.peek().unwrap_or(&' ');
                         let comp = match next_ch {
                             '$' => {
                                 i += 2;
-                                &token[start..i + 1]
+                                &token[start..i + ch.len_utf8()]
                             }
-                            _ => &token[start..i + 1],
+                            _ => &token[start..i + ch.len_utf8()],
                         };
 
-                        i += 1;
+                        i += ch.len_utf8();
 
                         if ch == '\"' {
                             if !is_string {
@@ -146,5 +147,36 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_tokenizer_toml() {}
+    fn test_tokenizer_toml() {
+        let code = "[package]\r\nname = \"nimbus\"\r\n";
+
+        let mut tokenizer = TokenizerToml::new(code);
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Table);
+        assert_eq!(tokenizer.token().trim(), "[package]");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Key);
+        assert_eq!(tokenizer.token().trim(), "name");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Symbol);
+        assert_eq!(tokenizer.token(), "=");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::StringConstant);
+        assert_eq!(tokenizer.token().trim(), "\"nimbus\"");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Value);
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_toml_multi_byte() {
+        let code = "# Grüße\r\nauthors = [\"Jürgen 🦀\"]\r\n";
+
+        let mut tokenizer = TokenizerToml::new(code);
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Comment);
+        assert_eq!(tokenizer.token(), "# Grüße\r\n");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Key);
+        assert_eq!(tokenizer.token().trim(), "authors");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Symbol);
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Value);
+        assert_eq!(tokenizer.next().unwrap(), TokenType::Symbol);
+        assert_eq!(tokenizer.token(), "[");
+        assert_eq!(tokenizer.next().unwrap(), TokenType::StringConstant);
+        assert_eq!(tokenizer.token(), "\"Jürgen 🦀\"");
+    }
 }
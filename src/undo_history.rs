@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::file_format;
+use crate::piece::{Piece, Source};
+use crate::piece_table::{PieceTable, UndoStep};
+use crate::piece_tree::PieceTree;
+
+const MAGIC: &[u8] = b"NIMBUSUNDO2\n";
+
+/// Result of looking up the stored undo history of a file.
+pub enum History {
+    /// History matches the file contents and was restored.
+    Restored(Box<PieceTable>),
+    /// History exists, but was written for different file contents.
+    Stale,
+    /// No history stored for the file.
+    Missing,
+}
+
+/// FNV-1a hash. Used instead of `DefaultHasher`, because the value is written
+/// to disk and must stay the same between builds.
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Directory where Nimbus keeps data between sessions:
+/// `$XDG_CACHE_HOME/nimbus`, falling back to `$HOME/.cache/nimbus`.
+pub fn nimbus_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("nimbus"))
+}
+
+/// Directory where undo files are stored: `$XDG_CACHE_HOME/nimbus/undo`,
+/// falling back to `$HOME/.cache/nimbus/undo`.
+pub fn cache_dir() -> Option<PathBuf> {
+    Some(nimbus_dir()?.join("undo"))
+}
+
+/// Path of the undo file for `file_path`. The name is derived from the
+/// absolute path, so files with the same name in different directories do
+/// not share history.
+pub fn history_path(dir: &Path, file_path: &str) -> PathBuf {
+    let absolute = fs::canonicalize(file_path).unwrap_or_else(|_| PathBuf::from(file_path));
+    let key = hash(absolute.to_string_lossy().as_bytes());
+    dir.join(format!("{:016x}.undo", key))
+}
+
+/// Write the piece table, including its undo and redo stacks, for a file
+/// whose contents on disk hash to `content_hash`.
+pub fn write(
+    dir: &Path,
+    file_path: &str,
+    piece_table: &PieceTable,
+    content_hash: u64,
+) -> io::Result<()> {
+    let mut out = Vec::from(MAGIC);
+    write_u64(&mut out, content_hash);
+    write_str(&mut out, &piece_table.data);
+    write_str(&mut out, &piece_table.add);
+    write_usizes(&mut out, &piece_table.line_starts_data);
+    write_usizes(&mut out, &piece_table.line_starts_add);
+    write_pieces(&mut out, &piece_table.pieces);
+    write_steps(&mut out, &piece_table.undo_stack);
+    write_steps(&mut out, &piece_table.redo_stack);
+
+    fs::create_dir_all(dir)?;
+    fs::write(history_path(dir, file_path), out)
+}
+
+/// Read the stored history of a file. `contents` is the text as it was read
+/// from disk; the history is only restored if it was written for the same
+/// contents.
+pub fn read(dir: &Path, file_path: &str, contents: &str) -> io::Result<History> {
+    let bytes = match fs::read(history_path(dir, file_path)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(History::Missing),
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = Reader { bytes: &bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("not an undo file"));
+    }
+    if reader.u64()? != hash(contents.as_bytes()) {
+        return Ok(History::Stale);
+    }
+
+    let mut piece_table = PieceTable::default();
+    piece_table.data = reader.string()?;
+    piece_table.add = reader.string()?;
+    piece_table.line_starts_data = reader.usizes()?;
+    piece_table.line_starts_add = reader.usizes()?;
+    let pieces = reader.pieces()?;
+    let undo_stack = reader.steps()?;
+    let redo_stack = reader.steps()?;
+
+    let all_pieces = pieces.iter().chain(
+        undo_stack
+            .iter()
+            .chain(redo_stack.iter())
+            .flat_map(|(_, pieces, _)| pieces.iter()),
+    );
+    for piece in all_pieces {
+        let source = match piece.source {
+            Source::Data => &piece_table.data,
+            Source::Add => &piece_table.add,
+        };
+        let end = piece.offset.checked_add(piece.length);
+        if end.and_then(|end| source.get(piece.offset..end)).is_none() {
+            return Err(invalid("piece out of bounds"));
+        }
+    }
+
+    let into_steps = |piece_table: &PieceTable, steps: Vec<Step>| {
+        steps
+            .into_iter()
+            .map(|(offset, pieces, lf_line_breaks)| UndoStep {
+                pieces: piece_table.tree_from_pieces(pieces),
+                offset,
+                // the undo file has no marks, undoing leaves them where they are
+                marks: HashMap::new(),
+                lf_line_breaks: lf_line_breaks.into_iter().collect(),
+            })
+            .collect()
+    };
+    piece_table.pieces = piece_table.tree_from_pieces(pieces);
+    piece_table.undo_stack = into_steps(&piece_table, undo_stack);
+    piece_table.redo_stack = into_steps(&piece_table, redo_stack);
+
+    if piece_table.get(0, None) != file_format::normalize(contents) {
+        return Ok(History::Stale);
+    }
+
+    Ok(History::Restored(Box::new(piece_table)))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u64(out, value.len() as u64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_usizes(out: &mut Vec<u8>, values: &[usize]) {
+    write_u64(out, values.len() as u64);
+    for value in values {
+        write_u64(out, *value as u64);
+    }
+}
+
+fn write_pieces(out: &mut Vec<u8>, pieces: &PieceTree) {
+    write_u64(out, pieces.len() as u64);
+    for piece in pieces.iter() {
+        out.push(match piece.source {
+            Source::Data => 0,
+            Source::Add => 1,
+        });
+        write_u64(out, piece.offset as u64);
+        write_u64(out, piece.length as u64);
+    }
+}
+
+fn write_steps(out: &mut Vec<u8>, steps: &[UndoStep]) {
+    write_u64(out, steps.len() as u64);
+    for step in steps {
+        write_u64(out, step.offset as u64);
+        write_pieces(out, &step.pieces);
+        let lf_line_breaks: Vec<usize> = step.lf_line_breaks.iter().copied().collect();
+        write_usizes(out, &lf_line_breaks);
+    }
+}
+
+/// Undo step as stored: offset, pieces and `\n` line breaks.
+type Step = (usize, Vec<Piece>, Vec<usize>);
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(invalid("unexpected end of undo file"));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    fn usize(&mut self) -> io::Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("value too large"))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("invalid utf-8"))
+    }
+
+    fn usizes(&mut self) -> io::Result<Vec<usize>> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.usize()).collect()
+    }
+
+    fn pieces(&mut self) -> io::Result<Vec<Piece>> {
+        let len = self.usize()?;
+        (0..len)
+            .map(|_| {
+                let source = match self.take(1)?[0] {
+                    0 => Source::Data,
+                    1 => Source::Add,
+                    _ => return Err(invalid("invalid piece source")),
+                };
+                Ok(Piece::new(source, self.usize()?, self.usize()?))
+            })
+            .collect()
+    }
+
+    fn steps(&mut self) -> io::Result<Vec<Step>> {
+        let len = self.usize()?;
+        (0..len)
+            .map(|_| Ok((self.usize()?, self.pieces()?, self.usizes()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("nimbus-undo-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_history_roundtrip() {
+        let dir = test_dir("roundtrip");
+        let mut piece_table = PieceTable::from_string("File is read.\r\n".to_string());
+        piece_table.lf_line_breaks.insert(13);
+        piece_table.insert("not ", 8);
+        piece_table.delete(0, 5);
+        piece_table.undo();
+        let contents = piece_table.get(0, None);
+
+        write(&dir, "test.txt", &piece_table, hash(contents.as_bytes())).unwrap();
+
+        let History::Restored(mut restored) = read(&dir, "test.txt", &contents).unwrap() else {
+            panic!("history not restored");
+        };
+        assert_eq!(restored.get(0, None), "File is not read.\r\n");
+        assert_eq!(restored.redo(), Some(0));
+        assert_eq!(restored.get(0, None), "is not read.\r\n");
+        assert_eq!(restored.lf_line_breaks, [12].into());
+        restored.undo();
+        restored.undo();
+        assert_eq!(restored.get(0, None), "File is read.\r\n");
+        assert_eq!(restored.lf_line_breaks, [13].into());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_history_stale() {
+        let dir = test_dir("stale");
+        let piece_table = PieceTable::from_string("File is read.\r\n".to_string());
+        write(&dir, "test.txt", &piece_table, hash(b"File is read.\r\n")).unwrap();
+
+        assert!(matches!(
+            read(&dir, "test.txt", "File was changed.\r\n").unwrap(),
+            History::Stale
+        ));
+        assert!(matches!(
+            read(&dir, "other.txt", "File is read.\r\n").unwrap(),
+            History::Missing
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Number of terminal columns a character occupies: 0 for combining marks
+/// and joiners, 2 for east asian wide characters and emoji, 1 otherwise.
+///
+/// This is an approximation of `wcwidth`, covering the common ranges.
+pub fn char_width(ch: char) -> usize {
+    match ch as u32 {
+        0x0300..=0x036F
+        | 0x1AB0..=0x1AFF
+        | 0x1DC0..=0x1DFF
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F680..=0x1F6FF
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Whether the character combines with the character before it, e.g. an
+/// accent, a variation selector or a zero width joiner.
+pub fn is_combining(ch: char) -> bool {
+    matches!(
+        ch as u32,
+        0x0300..=0x036F
+            | 0x1AB0..=0x1AFF
+            | 0x1DC0..=0x1DFF
+            | 0x200D
+            | 0x20D0..=0x20FF
+            | 0xFE00..=0xFE0F
+            | 0xFE20..=0xFE2F
+    )
+}
+
+/// Number of terminal columns a grapheme cluster occupies: the width of its
+/// first character, e.g. an emoji with a skin tone is as wide as the emoji.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.chars().next().map_or(0, char_width)
+}
+
+/// Number of terminal columns the text occupies.
+pub fn str_width(text: &str) -> usize {
+    graphemes(text).map(grapheme_width).sum()
+}
+
+/// Longest prefix of the text, which fits into `width` terminal columns.
+/// A grapheme cluster is never split.
+pub fn truncate(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// Grapheme clusters of the text: what a user sees as one character, e.g. a
+/// letter together with its combining accents.
+pub fn graphemes(text: &str) -> impl Iterator<Item = &str> {
+    text.graphemes(true)
+}
+
+/// Byte offset of grapheme cluster `index`, or the length of the text, if it
+/// has fewer clusters.
+pub fn grapheme_offset(text: &str, index: usize) -> usize {
+    text.grapheme_indices(true)
+        .nth(index)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// Number of grapheme clusters, which end at or before the byte offset.
+pub fn grapheme_index(text: &str, offset: usize) -> usize {
+    text.grapheme_indices(true)
+        .take_while(|(start, grapheme)| start + grapheme.len() <= offset)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_str_width() {
+        assert_eq!(str_width("File is read."), 13);
+        assert_eq!(str_width("Grüße"), 5);
+        assert_eq!(str_width("e\u{301}"), 1);
+        assert_eq!(str_width("日本"), 4);
+        assert_eq!(str_width("🦀!"), 3);
+        assert_eq!(str_width("\u{1F44D}\u{1F3FD}"), 2);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("File is read.", 4), "File");
+        assert_eq!(truncate("File", 10), "File");
+        assert_eq!(truncate("日本語", 5), "日本");
+        assert_eq!(truncate("e\u{301}x", 1), "e\u{301}");
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let text = "e\u{301}t\u{e9}\u{1F44D}\u{1F3FD}!";
+        assert_eq!(graphemes(text).count(), 5);
+        assert_eq!(grapheme_offset(text, 1), 3);
+        assert_eq!(grapheme_offset(text, 3), 6);
+        assert_eq!(grapheme_offset(text, 9), text.len());
+        assert_eq!(grapheme_index(text, 3), 1);
+        assert_eq!(grapheme_index(text, 1), 0);
+        assert_eq!(grapheme_index(text, text.len()), 5);
+        assert_eq!(graphemes("a\r\nb").count(), 3);
+    }
+}
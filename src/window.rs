@@ -0,0 +1,202 @@
+use crate::position::Position;
+
+/// Area of the terminal, in cells.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// View of a buffer. Every window has its own scroll offset and cursor, so
+/// several windows can show different parts of the same buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Window {
+    /// Index into the buffer list.
+    pub buffer: usize,
+    pub offset_y: usize,
+    pub cursor_position: Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitDirection {
+    /// Windows above each other (`:split`).
+    Horizontal,
+    /// Windows next to each other (`:vsplit`).
+    Vertical,
+}
+
+/// Arrangement of the windows on the screen. Windows are referenced by their
+/// index into the window list. The children of a split share its area equally.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Window(usize),
+    Split(SplitDirection, Vec<Layout>),
+}
+
+impl Layout {
+    /// Split `window`, placing `new_window` after it (below or right).
+    pub fn split(&mut self, window: usize, new_window: usize, direction: SplitDirection) {
+        match self {
+            Layout::Window(index) if *index == window => {
+                *self = Layout::Split(
+                    direction,
+                    vec![Layout::Window(window), Layout::Window(new_window)],
+                );
+            }
+            Layout::Window(_) => (),
+            Layout::Split(split_direction, children) => {
+                let position = children
+                    .iter()
+                    .position(|child| *child == Layout::Window(window));
+                match position {
+                    // add to this split instead of nesting another one
+                    Some(i) if *split_direction == direction => {
+                        children.insert(i + 1, Layout::Window(new_window))
+                    }
+                    _ => children
+                        .iter_mut()
+                        .for_each(|child| child.split(window, new_window, direction)),
+                }
+            }
+        }
+    }
+
+    /// Remove `window` and shift the indices of all windows after it, to
+    /// match the window list with the window removed.
+    pub fn remove(&mut self, window: usize) {
+        match self {
+            Layout::Window(index) if *index > window => *index -= 1,
+            Layout::Window(_) => (),
+            Layout::Split(_, children) => {
+                children.retain(|child| *child != Layout::Window(window));
+                for child in children.iter_mut() {
+                    child.remove(window);
+                }
+                if children.len() == 1 {
+                    let child = children.pop().unwrap();
+                    *self = child;
+                }
+            }
+        }
+    }
+
+    /// Area of every window inside of `area`. Each area includes the status
+    /// bar in its last row. Windows next to each other are separated by a
+    /// column, which is returned in `separators`.
+    pub fn rects(&self, area: Rect, rects: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        match self {
+            Layout::Window(index) => rects.push((*index, area)),
+            Layout::Split(direction, children) => {
+                let count = children.len();
+                let (total, gap) = match direction {
+                    SplitDirection::Horizontal => (area.height, 0),
+                    SplitDirection::Vertical => (area.width.saturating_sub(count - 1), 1),
+                };
+
+                let mut start = 0;
+                for (i, child) in children.iter().enumerate() {
+                    // the last child gets the remainder
+                    let size = match i + 1 == count {
+                        true => total - (total / count) * (count - 1),
+                        false => total / count,
+                    };
+                    let child_area = match direction {
+                        SplitDirection::Horizontal => {
+                            Rect::new(area.x, area.y + start, area.width, size)
+                        }
+                        SplitDirection::Vertical => {
+                            Rect::new(area.x + start, area.y, size, area.height)
+                        }
+                    };
+                    child.rects(child_area, rects, separators);
+
+                    start += size;
+                    if gap > 0 && i + 1 < count {
+                        separators.push(Rect::new(area.x + start, area.y, gap, area.height));
+                        start += gap;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rects(layout: &Layout, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let (mut rects, mut separators) = (vec![], vec![]);
+        layout.rects(area, &mut rects, &mut separators);
+        (rects, separators)
+    }
+
+    #[test]
+    fn test_layout_split() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, SplitDirection::Horizontal);
+        layout.split(0, 2, SplitDirection::Horizontal);
+        layout.split(1, 3, SplitDirection::Vertical);
+        assert_eq!(
+            layout,
+            Layout::Split(
+                SplitDirection::Horizontal,
+                vec![
+                    Layout::Window(0),
+                    Layout::Window(2),
+                    Layout::Split(
+                        SplitDirection::Vertical,
+                        vec![Layout::Window(1), Layout::Window(3)]
+                    ),
+                ]
+            )
+        );
+
+        let (rects, separators) = rects(&layout, Rect::new(0, 0, 81, 31));
+        assert_eq!(
+            rects,
+            vec![
+                (0, Rect::new(0, 0, 81, 10)),
+                (2, Rect::new(0, 10, 81, 10)),
+                (1, Rect::new(0, 20, 40, 11)),
+                (3, Rect::new(41, 20, 40, 11)),
+            ]
+        );
+        assert_eq!(separators, vec![Rect::new(40, 20, 1, 11)]);
+    }
+
+    #[test]
+    fn test_layout_remove() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, SplitDirection::Vertical);
+        layout.split(1, 2, SplitDirection::Horizontal);
+
+        layout.remove(1);
+        assert_eq!(
+            layout,
+            Layout::Split(
+                SplitDirection::Vertical,
+                vec![Layout::Window(0), Layout::Window(1)]
+            )
+        );
+        layout.remove(0);
+        assert_eq!(layout, Layout::Window(0));
+    }
+}
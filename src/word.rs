@@ -0,0 +1,267 @@
+use std::iter::Peekable;
+
+use crate::buffer::Buffer;
+use crate::unicode;
+
+/// Motion over words, as in vim. A word is a run of letters, digits and
+/// underscores, or a run of other non-blank characters. A WORD (`big`) is a
+/// run of non-blank characters. Empty lines count as words, except for `e`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordMotion {
+    /// `w`: start of the next word.
+    Forward,
+    /// `e`: end of the word, or of the next word.
+    ForwardEnd,
+    /// `b`: start of the word, or of the previous word.
+    Backward,
+    /// `ge`: end of the previous word.
+    BackwardEnd,
+}
+
+impl WordMotion {
+    /// Motion for a key (`w`, `e`, `b`, or `e` after `g`), and whether it
+    /// moves over WORDs (the keys in upper case).
+    pub fn from_key(key: char, after_g: bool) -> Option<(WordMotion, bool)> {
+        let motion = match (key.to_ascii_lowercase(), after_g) {
+            ('w', false) => WordMotion::Forward,
+            ('e', false) => WordMotion::ForwardEnd,
+            ('b', false) => WordMotion::Backward,
+            ('e', true) => WordMotion::BackwardEnd,
+            _ => return None,
+        };
+        Some((motion, key.is_ascii_uppercase()))
+    }
+
+    /// Whether an operator includes the character the motion moves onto.
+    pub fn is_inclusive(&self) -> bool {
+        matches!(self, WordMotion::ForwardEnd | WordMotion::BackwardEnd)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+    Blank,
+    LineBreak,
+    Punctuation,
+    Word,
+}
+
+fn class(ch: char, big: bool) -> Class {
+    match ch {
+        '\r' | '\n' => Class::LineBreak,
+        _ if ch.is_whitespace() => Class::Blank,
+        _ if big || ch.is_alphanumeric() || ch == '_' => Class::Word,
+        _ => Class::Punctuation,
+    }
+}
+
+/// Byte offset the motion moves to from the byte offset of the buffer, or
+/// None if there is no word in that direction.
+pub fn find(buffer: &Buffer, offset: usize, motion: WordMotion, big: bool) -> Option<usize> {
+    match motion {
+        WordMotion::Forward => next_start(classes(buffer, offset, true, big)),
+        WordMotion::ForwardEnd => next_end(classes(buffer, offset, true, big)),
+        WordMotion::Backward => previous_start(classes(buffer, offset, false, big)),
+        WordMotion::BackwardEnd => {
+            let current = classes(buffer, offset, true, big).next().map(|(_, c)| c);
+            previous_end(classes(buffer, offset, false, big), current)
+        }
+    }
+}
+
+/// Classes of the characters after (`forward`) or before the byte offset,
+/// with their offsets. `\r\n` is one line break, and a character together
+/// with its combining marks is one character.
+fn classes(
+    buffer: &Buffer,
+    offset: usize,
+    forward: bool,
+    big: bool,
+) -> Peekable<impl Iterator<Item = (usize, Class)> + '_> {
+    let chars: Box<dyn Iterator<Item = char>> = match forward {
+        true => Box::new(buffer.chars(offset)),
+        false => Box::new(buffer.chars_rev(offset)),
+    };
+    let mut chars = chars.peekable();
+    let pair = if forward { ('\r', '\n') } else { ('\n', '\r') };
+    let mut offset = offset;
+    std::iter::from_fn(move || {
+        let mut ch = chars.next()?;
+        let mut len = ch.len_utf8();
+        if ch == pair.0 && chars.next_if_eq(&pair.1).is_some() {
+            len += 1;
+        }
+        if forward {
+            while let Some(mark) = chars.next_if(|next| unicode::is_combining(*next)) {
+                len += mark.len_utf8();
+            }
+        } else {
+            while unicode::is_combining(ch) {
+                match chars.next_if(|next| !matches!(next, '\r' | '\n')) {
+                    Some(base) => {
+                        len += base.len_utf8();
+                        ch = base;
+                    }
+                    None => break,
+                }
+            }
+        }
+        let start = match forward {
+            true => offset,
+            false => offset - len,
+        };
+        offset = match forward {
+            true => offset + len,
+            false => offset - len,
+        };
+        Some((start, class(ch, big)))
+    })
+    .peekable()
+}
+
+/// Start of the next word or empty line, after the character at the start
+/// of `chars`.
+fn next_start(mut chars: impl Iterator<Item = (usize, Class)>) -> Option<usize> {
+    let (_, mut previous) = chars.next()?;
+    for (offset, class) in chars {
+        match class {
+            Class::Word | Class::Punctuation if class != previous => return Some(offset),
+            Class::LineBreak if previous == Class::LineBreak => return Some(offset),
+            _ => (),
+        }
+        previous = class;
+    }
+    None
+}
+
+/// Last character of the word after the character at the start of `chars`.
+fn next_end(mut chars: Peekable<impl Iterator<Item = (usize, Class)>>) -> Option<usize> {
+    chars.next()?;
+    while let Some((offset, class)) = chars.next() {
+        if matches!(class, Class::Blank | Class::LineBreak) {
+            continue;
+        }
+        let mut end = offset;
+        while let Some((offset, _)) = chars.next_if(|(_, next)| *next == class) {
+            end = offset;
+        }
+        return Some(end);
+    }
+    None
+}
+
+/// Start of the word or empty line before the characters `chars` (which go
+/// backwards).
+fn previous_start(mut chars: Peekable<impl Iterator<Item = (usize, Class)>>) -> Option<usize> {
+    while let Some((offset, class)) = chars.next() {
+        match class {
+            Class::Blank => (),
+            Class::LineBreak => {
+                if matches!(chars.peek(), None | Some((_, Class::LineBreak))) {
+                    return Some(offset);
+                }
+            }
+            _ => {
+                let mut start = offset;
+                while let Some((offset, _)) = chars.next_if(|(_, next)| *next == class) {
+                    start = offset;
+                }
+                return Some(start);
+            }
+        }
+    }
+    None
+}
+
+/// Last character of the word or empty line before the word of the
+/// character of class `current`, which is followed by `chars` (backwards).
+fn previous_end(
+    mut chars: Peekable<impl Iterator<Item = (usize, Class)>>,
+    current: Option<Class>,
+) -> Option<usize> {
+    if let Some(current @ (Class::Word | Class::Punctuation)) = current {
+        while chars.next_if(|(_, class)| *class == current).is_some() {}
+    }
+    while let Some((offset, class)) = chars.next() {
+        match class {
+            Class::Blank => (),
+            Class::LineBreak => {
+                if matches!(chars.peek(), None | Some((_, Class::LineBreak))) {
+                    return Some(offset);
+                }
+            }
+            _ => return Some(offset),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offsets visited by repeating the motion from the offset.
+    fn walk(text: &str, offset: usize, motion: WordMotion, big: bool) -> Vec<usize> {
+        let buffer = Buffer::from_string(text.to_string());
+        std::iter::successors(Some(offset), |offset| find(&buffer, *offset, motion, big))
+            .skip(1)
+            .collect()
+    }
+
+    #[test]
+    fn test_words() {
+        let text = "fn main() {\r\n    let x_1 = a.b;\r\n\r\n}";
+        assert_eq!(
+            walk(text, 0, WordMotion::Forward, false),
+            vec![3, 7, 10, 17, 21, 25, 27, 28, 29, 30, 33, 35]
+        );
+        assert_eq!(
+            walk(text, 0, WordMotion::Forward, true),
+            vec![3, 10, 17, 21, 25, 27, 33, 35]
+        );
+        assert_eq!(
+            walk(text, 0, WordMotion::ForwardEnd, false),
+            vec![1, 6, 8, 10, 19, 23, 25, 27, 28, 29, 30, 35]
+        );
+        assert_eq!(
+            walk(text, 35, WordMotion::Backward, false),
+            vec![33, 30, 29, 28, 27, 25, 21, 17, 10, 7, 3, 0]
+        );
+        assert_eq!(
+            walk(text, 35, WordMotion::Backward, true),
+            vec![33, 27, 25, 21, 17, 10, 3, 0]
+        );
+        assert_eq!(
+            walk(text, 35, WordMotion::BackwardEnd, false),
+            vec![33, 30, 29, 28, 27, 25, 23, 19, 10, 8, 6, 1]
+        );
+    }
+
+    #[test]
+    fn test_words_multi_byte() {
+        let text = "Grüße 🦀 lied.\r\nB";
+        assert_eq!(
+            walk(text, 0, WordMotion::Forward, false),
+            vec![8, 13, 17, 20]
+        );
+        assert_eq!(
+            walk(text, 0, WordMotion::ForwardEnd, false),
+            vec![6, 8, 16, 17, 20]
+        );
+        assert_eq!(
+            walk(text, 20, WordMotion::BackwardEnd, false),
+            vec![17, 16, 8, 6]
+        );
+        // combining marks belong to the character before them
+        let text = "cafe\u{301}. x";
+        assert_eq!(walk(text, 0, WordMotion::Forward, false), vec![6, 8]);
+        assert_eq!(walk(text, 0, WordMotion::ForwardEnd, false), vec![3, 6, 8]);
+        assert_eq!(walk(text, 8, WordMotion::Backward, false), vec![6, 0]);
+        assert_eq!(walk(text, 8, WordMotion::BackwardEnd, false), vec![6, 3]);
+        assert_eq!(
+            WordMotion::from_key('E', true),
+            Some((WordMotion::BackwardEnd, true))
+        );
+        assert_eq!(WordMotion::from_key('b', true), None);
+    }
+}